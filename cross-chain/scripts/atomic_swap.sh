@@ -36,6 +36,7 @@ Options:
   --source PROFILE   Signing profile (default: $SOURCE_KEY)
   --network NET      Network (default: $NETWORK)
   --escrow-duration  Hours until escrow cancellation (default: 24)
+  --max-taking AMT   Revert the fill if it would cost more than AMT of the taker asset (default: no cap)
   --dry-run          Show what would be done without executing
   --help|-h          Show this help
 
@@ -54,6 +55,9 @@ Note: Only the escrow is deployed fresh each time. The LOP contract is pre-deplo
 EOF
 }
 
+# fill_order's max_taking_amount that disables the slippage check
+I128_MAX="170141183460469231731687303715884105727"
+
 is_contract_id() { [[ "${1:-}" =~ ^C[0-9A-Z]{55,60}$ ]]; }
 is_gaddr() { [[ "${1:-}" =~ ^G[0-9A-Z]{55}$ ]]; }
 
@@ -116,6 +120,12 @@ deploy_escrow() {
   
   echo "🏗️  Deploying escrow contract..." >&2
   
+  # Withdrawals close at the cancellation time; anyone can push or cancel an hour either side
+  local deployed_at="$(date +%s)"
+  local cancellation_offset=$((cancellation_time - deployed_at))
+  local public_withdrawal_offset=$((cancellation_offset > 3600 ? cancellation_offset - 3600 : 0))
+  local public_cancellation_offset=$((cancellation_offset + 3600))
+  
   # Create immutables file
   local imm_file="$(mktemp -t immutables.XXXXXX.json)"
   cat > "$imm_file" <<EOF
@@ -125,7 +135,17 @@ deploy_escrow() {
   "taker": "$taker", 
   "token": "$token",
   "amount": "$amount",
-  "cancellation_timestamp": $cancellation_time
+  "timelocks": {
+    "deployed_at": $deployed_at,
+    "withdrawal": 0,
+    "public_withdrawal": $public_withdrawal_offset,
+    "cancellation": $cancellation_offset,
+    "public_cancellation": $public_cancellation_offset
+  },
+  "safety_deposit": "0",
+  "deposit_token": "$token",
+  "hash_algorithm": 0,
+  "arbiter": null
 }
 EOF
   
@@ -188,7 +208,9 @@ fill_lop_order() {
     --network "$NETWORK" \
     -- fill_order \
     --order "$(cat "$order_file")" \
-    --taker "$taker"
+    --taker "$taker" \
+    --requested_making_amount "$(jq -r '.making_amount' "$order_file")" \
+    --max_taking_amount "$MAX_TAKING"
     
   echo "✅ LOP order filled successfully"
 }
@@ -338,6 +360,7 @@ LOP_CONTRACT=""
 ORDER_FILE=""
 ESCROW_DURATION=24
 DRY_RUN=false
+MAX_TAKING="$I128_MAX"
 
 while [[ $# -gt 0 ]]; do
   case "$1" in
@@ -353,6 +376,10 @@ while [[ $# -gt 0 ]]; do
       ESCROW_DURATION="$2"
       shift 2
       ;;
+    --max-taking)
+      MAX_TAKING="$2"
+      shift 2
+      ;;
     --dry-run)
       DRY_RUN=true
       shift
@@ -403,5 +430,10 @@ if ! [[ "$ESCROW_DURATION" =~ ^[0-9]+$ ]] || (( ESCROW_DURATION < 1 )); then
   exit 1
 fi
 
+if ! [[ "$MAX_TAKING" =~ ^[0-9]+$ ]]; then
+  echo "Error: Max taking amount must be a non-negative integer"
+  exit 1
+fi
+
 # Execute atomic swap
 atomic_swap "$LOP_CONTRACT" "$ORDER_FILE" "$ESCROW_DURATION" "$DRY_RUN"
\ No newline at end of file
@@ -7,12 +7,15 @@ NETWORK="${NETWORK:-testnet}"
 
 # --- helpers ---
 need() { command -v "$1" >/dev/null 2>&1 || { echo "Missing dependency: $1"; exit 1; }; }
-need stellar; need grep; need awk; need sed
+need stellar; need grep; need awk; need sed; need jq
 
 is_contract_id() { [[ "${1:-}" =~ ^C[0-9A-Z]{55,60}$ ]]; }
 is_gaddr()       { [[ "${1:-}" =~ ^G[0-9A-Z]{55}$ ]]; }
 is_hex64()       { [[ "${1:-}" =~ ^[0-9a-fA-F]{64}$ ]]; }
 
+# fill_order's max_taking_amount that disables the slippage check
+I128_MAX="170141183460469231731687303715884105727"
+
 resolve_native_token() {
   stellar contract id asset --network "$NETWORK" --asset native 2>/dev/null \
     | grep -oE 'C[0-9A-Z]{55,60}' | head -n1
@@ -29,7 +32,7 @@ Flags (optional):
 
 Commands:
   info <lop_contract>
-  fill-order <lop_contract> <order_json_file> <taker_address>
+  fill-order <lop_contract> <order_json_file> <taker_address> [max_taking_amount]
   cancel-order <lop_contract> <order_json_file>
   get-order-state <lop_contract> <order_json_file>
   get-current-price <lop_contract> <order_json_file>
@@ -133,9 +136,10 @@ case "$COMMAND" in
 
   fill-order)
     if [[ $# -lt 3 ]]; then echo "fill-order requires <lop_contract> <order_json_file> <taker_address>"; exit 1; fi
-    LOP_ADDRESS="$1"; ORDER_FILE="$2"; TAKER_ADDRESS="$3"
+    LOP_ADDRESS="$1"; ORDER_FILE="$2"; TAKER_ADDRESS="$3"; MAX_TAKING="${4:-$I128_MAX}"
     is_contract_id "$LOP_ADDRESS" || { echo "Invalid LOP contract address: $LOP_ADDRESS"; exit 1; }
     is_gaddr "$TAKER_ADDRESS" || { echo "Invalid taker address: $TAKER_ADDRESS"; exit 1; }
+    [[ "$MAX_TAKING" =~ ^[0-9]+$ ]] || { echo "Invalid max taking amount: $MAX_TAKING"; exit 1; }
     [[ -f "$ORDER_FILE" ]] || { echo "Order file not found: $ORDER_FILE"; exit 1; }
     
     echo "Filling order from $ORDER_FILE with taker $TAKER_ADDRESS..."
@@ -149,7 +153,9 @@ case "$COMMAND" in
       --network "$NETWORK" \
       -- fill_order \
       --order "$(cat "$ORDER_FILE")" \
-      --taker "$TAKER_ADDRESS"
+      --taker "$TAKER_ADDRESS" \
+      --requested_making_amount "$(jq -r '.making_amount' "$ORDER_FILE")" \
+      --max_taking_amount "$MAX_TAKING"
     ;;
 
   cancel-order)
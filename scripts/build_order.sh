@@ -18,18 +18,42 @@ Usage:
     --maker-traits <u64> \
     --auction-start <u64> \
     --auction-end <u64> \
+    [--auction-duration <u64>] \
     --taking-start "<i128 string>" \
     --taking-end "<i128 string>" \
+    [--allowed-taker G... ...] \
+    [--post-fill-hook C... --post-fill-fn <symbol>] \
+    [--settle-token C... --peg-rate "<i128 string>"] \
+    [--expiration <u64>] \
+    [--pre-interaction C...] [--post-interaction C...] \
+    [--min-fill "<i128 string>"] \
     [--out /path/order.json]
 
 Notes:
   - For a regular (non-dutch) order, set maker-traits=0 and auction fields can be 0.
   - For dutch, set maker-traits=1 (IS_DUTCH_AUCTION) and fill auction fields.
-  - i128 values (making/taking/taking-start/taking-end) must be quoted strings.
+  - For an auction timed from its first fill, also set RELATIVE_AUCTION (16384) and --auction-duration.
+  - --allowed-taker may be repeated; set HAS_ALLOWED_TAKERS (8) in maker-traits to enforce the list.
+  - The post-fill hook is only called when both --post-fill-hook and --post-fill-fn are given.
+  - --peg-rate is settle-token units per taker-asset unit, scaled by 1e7 (defaults to 0).
+  - --expiration is the timestamp fills are rejected from; 0 (the default) never expires.
+  - Interaction contracts are only called with HAS_PRE_INTERACTION (32) / HAS_POST_INTERACTION (64) set in maker-traits.
+  - --min-fill is the smallest partial fill in maker-asset units; 0 (the default) sets no minimum.
+  - i128 values (making/taking/taking-start/taking-end/peg-rate/min-fill) must be quoted strings.
 EOF
 }
 
 OUT=""
+AU_DURATION="0"
+ALLOWED_TAKERS=""
+POST_FILL_HOOK=""
+POST_FILL_FN=""
+SETTLE_TOKEN=""
+PEG_RATE="0"
+EXPIRATION="0"
+PRE_INTERACTION=""
+POST_INTERACTION=""
+MIN_FILL="0"
 # Parse args
 while [[ $# -gt 0 ]]; do
   case "$1" in
@@ -43,8 +67,18 @@ while [[ $# -gt 0 ]]; do
     --maker-traits) MAKER_TRAITS="$2"; shift 2;;
     --auction-start) AU_START="$2"; shift 2;;
     --auction-end) AU_END="$2"; shift 2;;
+    --auction-duration) AU_DURATION="$2"; shift 2;;
     --taking-start) TK_START="$2"; shift 2;;
     --taking-end) TK_END="$2"; shift 2;;
+    --allowed-taker) ALLOWED_TAKERS="${ALLOWED_TAKERS:+$ALLOWED_TAKERS, }\"$2\""; shift 2;;
+    --post-fill-hook) POST_FILL_HOOK="$2"; shift 2;;
+    --post-fill-fn) POST_FILL_FN="$2"; shift 2;;
+    --settle-token) SETTLE_TOKEN="$2"; shift 2;;
+    --peg-rate) PEG_RATE="$2"; shift 2;;
+    --expiration) EXPIRATION="$2"; shift 2;;
+    --pre-interaction) PRE_INTERACTION="$2"; shift 2;;
+    --post-interaction) POST_INTERACTION="$2"; shift 2;;
+    --min-fill) MIN_FILL="$2"; shift 2;;
     --out) OUT="$2"; shift 2;;
     -h|--help) usage; exit 0;;
     *) echo "Unknown arg: $1"; usage; exit 1;;
@@ -57,6 +91,9 @@ for k in SALT MAKER RECEIVER MAKER_ASSET TAKER_ASSET MAKING TAKING MAKER_TRAITS
   req "$k" "$(echo "$k" | tr 'A-Z_' 'a-z-')"
 done
 
+# Optional fields are null when unset
+opt() { if [[ -n "$1" ]]; then printf '"%s"' "$1"; else printf 'null'; fi; }
+
 JSON=$(cat <<EOF
 {
   "salt": $SALT,
@@ -69,8 +106,18 @@ JSON=$(cat <<EOF
   "maker_traits": $MAKER_TRAITS,
   "auction_start_time": $AU_START,
   "auction_end_time": $AU_END,
+  "auction_duration": $AU_DURATION,
   "taking_amount_start": "$TK_START",
-  "taking_amount_end": "$TK_END"
+  "taking_amount_end": "$TK_END",
+  "allowed_takers": [$ALLOWED_TAKERS],
+  "post_fill_hook": $(opt "$POST_FILL_HOOK"),
+  "post_fill_fn": $(opt "$POST_FILL_FN"),
+  "settle_token": $(opt "$SETTLE_TOKEN"),
+  "peg_rate": "$PEG_RATE",
+  "expiration": $EXPIRATION,
+  "pre_interaction": $(opt "$PRE_INTERACTION"),
+  "post_interaction": $(opt "$POST_INTERACTION"),
+  "min_fill_making_amount": "$MIN_FILL"
 }
 EOF
 )
@@ -13,7 +13,15 @@ TAKER_ADDRESS="GD4XRIZEWF7AI5XT5VOQFCGIZO3IYYJL6AORSUECCXSZTE3OBVWW74LA"
 
 # XLM has 7 decimals: 10 XLM -> 100000000
 AMOUNT="9000000"            # i128 as STRING
-CANCELLATION_TIMESTAMP=1754184950
+SAFETY_DEPOSIT="0"          # i128 as STRING, paid in the escrow token
+
+# Timelock stages, in seconds after DEPLOYED_AT
+DEPLOYED_AT="$(date +%s)"
+WITHDRAWAL_OFFSET=0               # Finality lock ends; the taker can withdraw
+PUBLIC_WITHDRAWAL_OFFSET=1800     # Anyone with the secret can push funds to the taker
+CANCELLATION_OFFSET=3600          # Withdrawals close; the maker can cancel
+PUBLIC_CANCELLATION_OFFSET=7200   # Anyone can cancel, refunding the maker
+CANCELLATION_TIMESTAMP=$((DEPLOYED_AT + CANCELLATION_OFFSET))
 
 #############################################
 # Deps
@@ -63,7 +71,17 @@ cat >"$IMM_FILE" <<EOF
   "taker": "$TAKER_ADDRESS",
   "token": "$TOKEN_ADDRESS",
   "amount": "$AMOUNT",
-  "cancellation_timestamp": $CANCELLATION_TIMESTAMP
+  "timelocks": {
+    "deployed_at": $DEPLOYED_AT,
+    "withdrawal": $WITHDRAWAL_OFFSET,
+    "public_withdrawal": $PUBLIC_WITHDRAWAL_OFFSET,
+    "cancellation": $CANCELLATION_OFFSET,
+    "public_cancellation": $PUBLIC_CANCELLATION_OFFSET
+  },
+  "safety_deposit": "$SAFETY_DEPOSIT",
+  "deposit_token": "$TOKEN_ADDRESS",
+  "hash_algorithm": 0,
+  "arbiter": null
 }
 EOF
 
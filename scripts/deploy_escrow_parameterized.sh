@@ -7,6 +7,8 @@ set -euo pipefail
 usage() {
     echo "Usage: $0 <hashlock> <maker> <taker> <token> <amount> <cancellation_timestamp> [salt]"
     echo "Example: $0 abc123... GD4X... GD4X... CDLZ... 9000000 1754184950 [optional_salt]"
+    echo "Timelock stages relative to now can be tuned with WITHDRAWAL_OFFSET, PUBLIC_WITHDRAWAL_OFFSET"
+    echo "and PUBLIC_CANCELLATION_DELAY (seconds after the cancellation timestamp)"
     exit 1
 }
 
@@ -28,6 +30,18 @@ SALT="${7:-$(openssl rand -hex 32)}"  # Use provided salt or generate one
 FACTORY_ID="${FACTORY_ID:-CACURHRDVGNUCFSMDWTIF6TABC76LNTAQYSIVDJK34P43OYOGPSGYDSO}"
 SOURCE_KEY="${SOURCE_KEY:-lion}"
 NETWORK="${NETWORK:-testnet}"
+SAFETY_DEPOSIT="${SAFETY_DEPOSIT:-0}"  # i128 as STRING, paid in the escrow token
+
+# Timelock stages, in seconds after DEPLOYED_AT; withdrawals close at the cancellation timestamp
+DEPLOYED_AT="$(date +%s)"
+if [ "$CANCELLATION_TIMESTAMP" -le "$DEPLOYED_AT" ]; then
+    echo "Cancellation timestamp must be in the future" >&2
+    exit 1
+fi
+CANCELLATION_OFFSET=$((CANCELLATION_TIMESTAMP - DEPLOYED_AT))
+WITHDRAWAL_OFFSET="${WITHDRAWAL_OFFSET:-0}"
+PUBLIC_WITHDRAWAL_OFFSET="${PUBLIC_WITHDRAWAL_OFFSET:-$((CANCELLATION_OFFSET / 2))}"
+PUBLIC_CANCELLATION_OFFSET=$((CANCELLATION_OFFSET + ${PUBLIC_CANCELLATION_DELAY:-3600}))
 
 #############################################
 # Deps
@@ -42,6 +56,7 @@ echo "  Taker: $TAKER_ADDRESS"
 echo "  Token: $TOKEN_ADDRESS"
 echo "  Amount: $AMOUNT"
 echo "  Cancellation timestamp: $CANCELLATION_TIMESTAMP"
+echo "  Timelocks: deployed_at=$DEPLOYED_AT withdrawal=+$WITHDRAWAL_OFFSET public_withdrawal=+$PUBLIC_WITHDRAWAL_OFFSET cancellation=+$CANCELLATION_OFFSET public_cancellation=+$PUBLIC_CANCELLATION_OFFSET"
 echo "  Salt: $SALT"
 
 #############################################
@@ -57,7 +72,17 @@ cat >"$IMM_FILE" <<EOF
   "taker": "$TAKER_ADDRESS",
   "token": "$TOKEN_ADDRESS",
   "amount": "$AMOUNT",
-  "cancellation_timestamp": $CANCELLATION_TIMESTAMP
+  "timelocks": {
+    "deployed_at": $DEPLOYED_AT,
+    "withdrawal": $WITHDRAWAL_OFFSET,
+    "public_withdrawal": $PUBLIC_WITHDRAWAL_OFFSET,
+    "cancellation": $CANCELLATION_OFFSET,
+    "public_cancellation": $PUBLIC_CANCELLATION_OFFSET
+  },
+  "safety_deposit": "$SAFETY_DEPOSIT",
+  "deposit_token": "$TOKEN_ADDRESS",
+  "hash_algorithm": 0,
+  "arbiter": null
 }
 EOF
 
@@ -10,6 +10,7 @@ FACTORY_ID="CDLWW5OUQZWE76WLYGKN6TCOG2UKOZ4HTOU5UN6RMNTXOCMDVWHE5UO4"
 # Defaults
 SOURCE_KEY="${SOURCE_KEY:-lion}"
 NETWORK="${NETWORK:-testnet}"
+CALLER="${CALLER:-}"
 
 # --- helpers ---
 need() { command -v "$1" >/dev/null 2>&1 || { echo "Missing dependency: $1"; exit 1; }; }
@@ -27,20 +28,28 @@ Usage:
 Flags (optional):
   --source   Signing profile/key (default: lion)
   --network  Network to use (default: testnet)
+  --caller   Address acting for role-gated commands (default: the --source key's address)
 
 Commands:
   info                              Get factory information
   get-lop-wasm-hash                Get current LOP WASM hash
   get-dutch-auction-wasm-hash      Get current Dutch auction WASM hash
   get-admin                        Get factory admin address
-  update-lop-wasm <new_hash>       Update LOP WASM hash (admin only)
-  update-dutch-wasm <new_hash>     Update Dutch auction WASM hash (admin only)
+  get-role <address>               Get the role of an address (SuperAdmin, Operator or none)
+  is-paused                        Check whether deployments are paused
+  update-lop-wasm <new_hash>       Update LOP WASM hash (operator or super admin)
+  update-dutch-wasm <new_hash>     Update Dutch auction WASM hash (operator or super admin)
+  set-paused <true|false>          Pause or unpause deployments (operator or super admin)
+  add-operator <address>           Grant the operator role (super admin only)
+  remove-operator <address>        Revoke the operator role (super admin only)
+  transfer-super-admin <address>   Hand the super admin role to a new address (super admin only)
   deploy-standalone-dutch <salt>   Deploy standalone Dutch auction contract
 
 Examples:
   $0 info
   $0 get-lop-wasm-hash
   $0 update-lop-wasm b5f8b3315108593e18dbcd4a3fc36c40d4b4ba5b335ccc23de9d7ce1ce47ff02
+  $0 --source ops add-operator GCOPERATORADDRESSXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
   $0 deploy-standalone-dutch a1b2c3d4e5f6789012345678901234567890abcdef1234567890abcdef123456
 EOF
 }
@@ -50,6 +59,7 @@ while [[ $# -gt 0 ]]; do
   case "$1" in
     --source)  SOURCE_KEY="$2"; shift 2 ;;
     --network) NETWORK="$2"; shift 2 ;;
+    --caller)  CALLER="$2"; shift 2 ;;
     --help|-h) show_usage; exit 0 ;;
     --) shift; break ;;
     -* ) echo "Unknown flag: $1"; show_usage; exit 1 ;;
@@ -61,6 +71,18 @@ done
 if [[ $# -lt 1 ]]; then show_usage; exit 1; fi
 COMMAND="$1"; shift
 
+# Role-gated methods take the acting address explicitly; default to the signer's
+resolve_caller() {
+  if [[ -z "$CALLER" ]]; then
+    CALLER=$(stellar keys address "$SOURCE_KEY")
+  fi
+  is_gaddr "$CALLER" || is_contract_id "$CALLER" || { echo "Invalid caller address: $CALLER"; exit 1; }
+}
+
+require_address() {
+  is_gaddr "$1" || is_contract_id "$1" || { echo "Invalid address: $1"; exit 1; }
+}
+
 case "$COMMAND" in
   info)
     echo "🏭 LOP Factory Information"
@@ -124,13 +146,16 @@ case "$COMMAND" in
     NEW_HASH="$1"
     is_hex64 "$NEW_HASH" || { echo "WASM hash must be 64 hex characters"; exit 1; }
     
+    resolve_caller
+    
     echo "Updating LOP WASM hash to: $NEW_HASH"
-    echo "⚠️  This requires admin authorization!"
+    echo "⚠️  This requires operator or super admin authorization!"
     stellar contract invoke \
       --id "$FACTORY_ID" \
       --source "$SOURCE_KEY" \
       --network "$NETWORK" \
       -- update_lop_wasm_hash \
+      --caller "$CALLER" \
       --new_wasm_hash "$NEW_HASH"
     ;;
 
@@ -139,16 +164,93 @@ case "$COMMAND" in
     NEW_HASH="$1"
     is_hex64 "$NEW_HASH" || { echo "WASM hash must be 64 hex characters"; exit 1; }
     
+    resolve_caller
+    
     echo "Updating Dutch auction WASM hash to: $NEW_HASH"
-    echo "⚠️  This requires admin authorization!"
+    echo "⚠️  This requires operator or super admin authorization!"
     stellar contract invoke \
       --id "$FACTORY_ID" \
       --source "$SOURCE_KEY" \
       --network "$NETWORK" \
       -- update_dutch_auction_wasm_hash \
+      --caller "$CALLER" \
       --new_wasm_hash "$NEW_HASH"
     ;;
 
+  get-role)
+    if [[ $# -lt 1 ]]; then echo "get-role requires <address>"; exit 1; fi
+    require_address "$1"
+    
+    echo "Getting role of: $1"
+    stellar contract invoke \
+      --id "$FACTORY_ID" \
+      --source "$SOURCE_KEY" \
+      --network "$NETWORK" \
+      -- get_role \
+      --account "$1"
+    ;;
+
+  is-paused)
+    echo "Checking whether deployments are paused..."
+    stellar contract invoke \
+      --id "$FACTORY_ID" \
+      --source "$SOURCE_KEY" \
+      --network "$NETWORK" \
+      -- is_paused
+    ;;
+
+  set-paused)
+    if [[ $# -lt 1 ]]; then echo "set-paused requires <true|false>"; exit 1; fi
+    PAUSED="$1"
+    [[ "$PAUSED" == "true" || "$PAUSED" == "false" ]] || { echo "set-paused takes true or false"; exit 1; }
+    resolve_caller
+    
+    echo "Setting deployments paused: $PAUSED"
+    echo "⚠️  This requires operator or super admin authorization!"
+    stellar contract invoke \
+      --id "$FACTORY_ID" \
+      --source "$SOURCE_KEY" \
+      --network "$NETWORK" \
+      -- set_paused \
+      --caller "$CALLER" \
+      --paused "$PAUSED"
+    ;;
+
+  add-operator|remove-operator)
+    if [[ $# -lt 1 ]]; then echo "$COMMAND requires <address>"; exit 1; fi
+    OPERATOR="$1"
+    require_address "$OPERATOR"
+    resolve_caller
+    METHOD="${COMMAND//-/_}"
+    
+    echo "Calling $METHOD for: $OPERATOR"
+    echo "⚠️  This requires super admin authorization!"
+    stellar contract invoke \
+      --id "$FACTORY_ID" \
+      --source "$SOURCE_KEY" \
+      --network "$NETWORK" \
+      -- "$METHOD" \
+      --caller "$CALLER" \
+      --operator "$OPERATOR"
+    ;;
+
+  transfer-super-admin)
+    if [[ $# -lt 1 ]]; then echo "transfer-super-admin requires <address>"; exit 1; fi
+    NEW_ADMIN="$1"
+    require_address "$NEW_ADMIN"
+    resolve_caller
+    
+    echo "Transferring super admin from $CALLER to: $NEW_ADMIN"
+    echo "⚠️  This requires super admin authorization and can't be undone by the old admin!"
+    stellar contract invoke \
+      --id "$FACTORY_ID" \
+      --source "$SOURCE_KEY" \
+      --network "$NETWORK" \
+      -- transfer_super_admin \
+      --caller "$CALLER" \
+      --new_admin "$NEW_ADMIN"
+    ;;
+
   deploy-standalone-dutch)
     if [[ $# -lt 1 ]]; then echo "deploy-standalone-dutch requires <salt>"; exit 1; fi
     SALT="$1"
@@ -1,124 +1,383 @@
-#![no_std]
-use soroban_sdk::{
-    contract, contracterror, contractimpl, Env,
-};
-
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-#[repr(u32)]
-pub enum Error {
-    InvalidTimeRange = 1,
-    AuctionNotStarted = 2,
-    InvalidAmountRange = 3,
-    ArithmeticOverflow = 4,
-}
-
-#[contract]
-pub struct SorobanDutchAuction;
-
-#[contractimpl]
-impl SorobanDutchAuction {
-    /// Calculate the current taking amount for a Dutch auction
-    /// Linear interpolation between start and end amounts based on time
-    pub fn calculate_taking_amount(
-        env: Env,
-        making_amount: i128,
-        taking_amount_start: i128,
-        taking_amount_end: i128,
-        auction_start_time: u64,
-        auction_end_time: u64,
-    ) -> Result<i128, Error> {
-        // Validate time range
-        if auction_end_time <= auction_start_time {
-            return Err(Error::InvalidTimeRange);
-        }
-
-        // Validate amount range (start should be higher than end for Dutch auction)
-        if taking_amount_start <= taking_amount_end {
-            return Err(Error::InvalidAmountRange);
-        }
-
-        let current_time = env.ledger().timestamp();
-
-        // If auction hasn't started, use start price
-        if current_time < auction_start_time {
-            return Ok(taking_amount_start);
-        }
-
-        // If auction has ended, use end price
-        if current_time >= auction_end_time {
-            return Ok(taking_amount_end);
-        }
-
-        // Calculate current price using linear interpolation
-        let time_elapsed = current_time - auction_start_time;
-        let total_duration = auction_end_time - auction_start_time;
-        let price_difference = taking_amount_start - taking_amount_end;
-
-        // Calculate: taking_amount_start - (price_difference * time_elapsed / total_duration)
-        let price_reduction = price_difference
-            .checked_mul(time_elapsed as i128)
-            .ok_or(Error::ArithmeticOverflow)?
-            .checked_div(total_duration as i128)
-            .ok_or(Error::ArithmeticOverflow)?;
-
-        let current_taking_amount = taking_amount_start
-            .checked_sub(price_reduction)
-            .ok_or(Error::ArithmeticOverflow)?;
-
-        Ok(current_taking_amount)
-    }
-
-    /// Calculate the current making amount for a Dutch auction
-    /// This is typically used when the taker specifies how much they want to pay
-    pub fn calculate_making_amount(
-        env: Env,
-        taking_amount: i128,
-        making_amount_start: i128,
-        making_amount_end: i128,
-        auction_start_time: u64,
-        auction_end_time: u64,
-    ) -> Result<i128, Error> {
-        // Validate time range
-        if auction_end_time <= auction_start_time {
-            return Err(Error::InvalidTimeRange);
-        }
-
-        // Validate amount range (start should be lower than end for making amount in Dutch auction)
-        if making_amount_start >= making_amount_end {
-            return Err(Error::InvalidAmountRange);
-        }
-
-        let current_time = env.ledger().timestamp();
-
-        // If auction hasn't started, use start amount
-        if current_time < auction_start_time {
-            return Ok(making_amount_start);
-        }
-
-        // If auction has ended, use end amount
-        if current_time >= auction_end_time {
-            return Ok(making_amount_end);
-        }
-
-        // Calculate current making amount using linear interpolation
-        let time_elapsed = current_time - auction_start_time;
-        let total_duration = auction_end_time - auction_start_time;
-        let amount_difference = making_amount_end - making_amount_start;
-
-        // Calculate: making_amount_start + (amount_difference * time_elapsed / total_duration)
-        let amount_increase = amount_difference
-            .checked_mul(time_elapsed as i128)
-            .ok_or(Error::ArithmeticOverflow)?
-            .checked_div(total_duration as i128)
-            .ok_or(Error::ArithmeticOverflow)?;
-
-        let current_making_amount = making_amount_start
-            .checked_add(amount_increase)
-            .ok_or(Error::ArithmeticOverflow)?;
-
-        Ok(current_making_amount)
-    }
-}
-
+#![no_std]
+use soroban_sdk::{
+    contract, contracterror, contractimpl, Env, Symbol,
+};
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    InvalidTimeRange = 1,
+    AuctionNotStarted = 2,
+    InvalidAmountRange = 3,
+    ArithmeticOverflow = 4,
+    InvalidBlackout = 5,
+    PriceOutOfRange = 6,
+}
+
+// Fixed-point scale of exchange rates (taking per making, 7 decimals like Stellar amounts)
+const RATE_SCALE: i128 = 10_000_000;
+
+#[contract]
+pub struct SorobanDutchAuction;
+
+#[contractimpl]
+impl SorobanDutchAuction {
+    /// Calculate the current taking amount for a Dutch auction
+    /// Linear interpolation between start and end amounts based on time
+    #[allow(unused_variables)]
+    pub fn calculate_taking_amount(
+        env: Env,
+        making_amount: i128,
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+    ) -> Result<i128, Error> {
+        Self::taking_amount_at(
+            taking_amount_start,
+            taking_amount_end,
+            auction_start_time,
+            auction_end_time,
+            env.ledger().timestamp(),
+        )
+    }
+
+    /// Calculate the current taking amount for an auction whose price is frozen during a blackout
+    /// During `[blackout_start, blackout_end)` the price holds at its value at `blackout_start`;
+    /// afterwards decay resumes, so the schedule shifts later by the blackout length.
+    /// Equal blackout times mean no blackout
+    #[allow(unused_variables, clippy::too_many_arguments)]
+    pub fn calculate_taking_amount_blackout(
+        env: Env,
+        making_amount: i128,
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+        blackout_start: u64,
+        blackout_end: u64,
+    ) -> Result<i128, Error> {
+        // Validate the blackout lies within the auction window
+        if blackout_end < blackout_start
+            || (blackout_end > blackout_start
+                && (blackout_start < auction_start_time || blackout_end > auction_end_time))
+        {
+            return Err(Error::InvalidBlackout);
+        }
+
+        // Time spent in the blackout doesn't count towards the decay
+        let current_time = env.ledger().timestamp();
+        let effective_time = if current_time < blackout_start {
+            current_time
+        } else if current_time < blackout_end {
+            blackout_start
+        } else {
+            current_time - (blackout_end - blackout_start)
+        };
+
+        Self::taking_amount_at(
+            taking_amount_start,
+            taking_amount_end,
+            auction_start_time,
+            auction_end_time,
+            effective_time,
+        )
+    }
+
+    /// Calculate the current taking amount, rounding the price reduction to nearest
+    /// `calculate_taking_amount` truncates the reduction, which can overstate the price by almost a
+    /// full unit on long auctions with a small price range; this halves the worst-case error
+    #[allow(unused_variables)]
+    pub fn calculate_taking_amount_rounded(
+        env: Env,
+        making_amount: i128,
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+    ) -> Result<i128, Error> {
+        Self::interpolate_taking_amount(
+            taking_amount_start,
+            taking_amount_end,
+            auction_start_time,
+            auction_end_time,
+            env.ledger().timestamp(),
+            true,
+        )
+    }
+
+    /// Calculate the current taking amount from exchange rates instead of absolute amounts
+    /// Rates are taking per making scaled by 1e7; `taking_amount = making_amount * rate / 1e7`
+    pub fn calculate_taking_amount_by_rate(
+        env: Env,
+        making_amount: i128,
+        rate_start: i128,
+        rate_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+    ) -> Result<i128, Error> {
+        // Interpolate the rate itself (validates time and rate ranges)
+        let current_rate = Self::calculate_taking_amount(
+            env,
+            making_amount,
+            rate_start,
+            rate_end,
+            auction_start_time,
+            auction_end_time,
+        )?;
+
+        let taking_amount = making_amount
+            .checked_mul(current_rate)
+            .ok_or(Error::ArithmeticOverflow)?
+            / RATE_SCALE;
+
+        Ok(taking_amount)
+    }
+
+    /// Calculate the current discount from the start price in basis points
+    /// `(taking_amount_start - current) * 10_000 / taking_amount_start`
+    pub fn current_discount_bps(
+        env: Env,
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+    ) -> Result<u32, Error> {
+        // A discount is only meaningful against a positive start price
+        if taking_amount_start <= 0 {
+            return Err(Error::InvalidAmountRange);
+        }
+
+        let current_taking_amount = Self::calculate_taking_amount(
+            env,
+            0,
+            taking_amount_start,
+            taking_amount_end,
+            auction_start_time,
+            auction_end_time,
+        )?;
+
+        let discount_bps = (taking_amount_start - current_taking_amount)
+            .checked_mul(10_000)
+            .ok_or(Error::ArithmeticOverflow)?
+            / taking_amount_start;
+
+        Ok(discount_bps as u32)
+    }
+
+    /// Get how far through the auction the current time is, in basis points
+    /// `(current - start) * 10_000 / (end - start)`, clamped to [0, 10000] outside the window
+    pub fn auction_progress_bps(
+        env: Env,
+        auction_start_time: u64,
+        auction_end_time: u64,
+    ) -> Result<u32, Error> {
+        // Validate time range
+        if auction_end_time <= auction_start_time {
+            return Err(Error::InvalidTimeRange);
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        if current_time <= auction_start_time {
+            return Ok(0);
+        }
+
+        if current_time >= auction_end_time {
+            return Ok(10_000);
+        }
+
+        let time_elapsed = (current_time - auction_start_time) as u128;
+        let total_duration = (auction_end_time - auction_start_time) as u128;
+
+        Ok((time_elapsed * 10_000 / total_duration) as u32)
+    }
+
+    /// Get the first timestamp at which the taking amount has decayed to `target_price`
+    /// Solves the linear schedule for time, rounding up so the price at the returned time is at
+    /// most `target_price`; errors if the target is outside `[taking_amount_end, taking_amount_start]`
+    pub fn time_to_price(
+        _env: Env,
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+        target_price: i128,
+    ) -> Result<u64, Error> {
+        // Validate time range
+        if auction_end_time < auction_start_time {
+            return Err(Error::InvalidTimeRange);
+        }
+
+        // Validate amount range (start should be higher than end for Dutch auction)
+        if taking_amount_start <= taking_amount_end {
+            return Err(Error::InvalidAmountRange);
+        }
+
+        if target_price < taking_amount_end || target_price > taking_amount_start {
+            return Err(Error::PriceOutOfRange);
+        }
+
+        // Smallest elapsed time with price_difference * elapsed / total_duration >= start - target
+        let total_duration = (auction_end_time - auction_start_time) as i128;
+        let price_difference = taking_amount_start - taking_amount_end;
+        let time_elapsed = (taking_amount_start - target_price)
+            .checked_mul(total_duration)
+            .ok_or(Error::ArithmeticOverflow)?
+            .checked_add(price_difference - 1)
+            .ok_or(Error::ArithmeticOverflow)?
+            / price_difference;
+
+        Ok(auction_start_time + time_elapsed as u64)
+    }
+
+    /// Calculate the current making amount for a Dutch auction
+    /// This is typically used when the taker specifies how much they want to pay
+    #[allow(unused_variables)]
+    pub fn calculate_making_amount(
+        env: Env,
+        taking_amount: i128,
+        making_amount_start: i128,
+        making_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+    ) -> Result<i128, Error> {
+        // Validate time range
+        if auction_end_time < auction_start_time {
+            return Err(Error::InvalidTimeRange);
+        }
+
+        // Validate amount range (start should be lower than end for making amount in Dutch auction)
+        if making_amount_start >= making_amount_end {
+            return Err(Error::InvalidAmountRange);
+        }
+
+        // A zero-duration auction is a fixed-price order at the end amount
+        if auction_end_time == auction_start_time {
+            return Ok(making_amount_end);
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        // If auction hasn't started, use start amount
+        if current_time < auction_start_time {
+            return Ok(making_amount_start);
+        }
+
+        // If auction has ended, use end amount
+        if current_time >= auction_end_time {
+            return Ok(making_amount_end);
+        }
+
+        // Calculate current making amount using linear interpolation
+        let time_elapsed = current_time - auction_start_time;
+        let total_duration = auction_end_time - auction_start_time;
+        let amount_difference = making_amount_end - making_amount_start;
+
+        // Calculate: making_amount_start + (amount_difference * time_elapsed / total_duration)
+        let amount_increase = amount_difference
+            .checked_mul(time_elapsed as i128)
+            .ok_or(Error::ArithmeticOverflow)?
+            .checked_div(total_duration as i128)
+            .ok_or(Error::ArithmeticOverflow)?;
+
+        let current_making_amount = making_amount_start
+            .checked_add(amount_increase)
+            .ok_or(Error::ArithmeticOverflow)?;
+
+        Ok(current_making_amount)
+    }
+
+    /// Helper function to interpolate the taking amount at `current_time`
+    fn taking_amount_at(
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+        current_time: u64,
+    ) -> Result<i128, Error> {
+        Self::interpolate_taking_amount(
+            taking_amount_start,
+            taking_amount_end,
+            auction_start_time,
+            auction_end_time,
+            current_time,
+            false,
+        )
+    }
+
+    /// Helper function to interpolate the taking amount at `current_time`
+    /// The price reduction is truncated, or rounded to nearest (halves up) with `round_to_nearest`
+    fn interpolate_taking_amount(
+        taking_amount_start: i128,
+        taking_amount_end: i128,
+        auction_start_time: u64,
+        auction_end_time: u64,
+        current_time: u64,
+        round_to_nearest: bool,
+    ) -> Result<i128, Error> {
+        // Validate time range
+        if auction_end_time < auction_start_time {
+            return Err(Error::InvalidTimeRange);
+        }
+
+        // Validate amount range (start should be higher than end for Dutch auction)
+        if taking_amount_start <= taking_amount_end {
+            return Err(Error::InvalidAmountRange);
+        }
+
+        // A zero-duration auction is a fixed-price order at the end amount
+        if auction_end_time == auction_start_time {
+            return Ok(taking_amount_end);
+        }
+
+        // If auction hasn't started, use start price
+        if current_time < auction_start_time {
+            return Ok(taking_amount_start);
+        }
+
+        // If auction has ended, use end price
+        if current_time >= auction_end_time {
+            return Ok(taking_amount_end);
+        }
+
+        // Calculate current price using linear interpolation
+        let time_elapsed = current_time - auction_start_time;
+        let total_duration = auction_end_time - auction_start_time;
+        let price_difference = taking_amount_start - taking_amount_end;
+
+        // Calculate: taking_amount_start - (price_difference * time_elapsed / total_duration)
+        let rounding = if round_to_nearest { total_duration as i128 / 2 } else { 0 };
+        let price_reduction = price_difference
+            .checked_mul(time_elapsed as i128)
+            .ok_or(Error::ArithmeticOverflow)?
+            .checked_add(rounding)
+            .ok_or(Error::ArithmeticOverflow)?
+            .checked_div(total_duration as i128)
+            .ok_or(Error::ArithmeticOverflow)?;
+
+        let current_taking_amount = taking_amount_start
+            .checked_sub(price_reduction)
+            .ok_or(Error::ArithmeticOverflow)?;
+
+        Ok(current_taking_amount)
+    }
+
+    /// Get the symbolic name of an error code (e.g. 6 -> "PriceOutOfRange")
+    /// Returns "Unknown" for codes not defined by this contract
+    pub fn error_name(env: Env, code: u32) -> Symbol {
+        let name = match code {
+            1 => "InvalidTimeRange",
+            2 => "AuctionNotStarted",
+            3 => "InvalidAmountRange",
+            4 => "ArithmeticOverflow",
+            5 => "InvalidBlackout",
+            6 => "PriceOutOfRange",
+            _ => "Unknown",
+        };
+        Symbol::new(&env, name)
+    }
+}
+
 mod test;
\ No newline at end of file
@@ -1,149 +1,401 @@
-#![cfg(test)]
-extern crate std;
-
-use super::*;
-use soroban_sdk::{
-    testutils::Ledger,
-    Env,
-};
-
-fn create_dutch_auction_contract(e: &Env) -> SorobanDutchAuctionClient {
-    SorobanDutchAuctionClient::new(e, &e.register(SorobanDutchAuction, ()))
-}
-
-#[test]
-fn test_calculate_taking_amount_at_start() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-    
-    // Set time to auction start
-    env.ledger().with_mut(|li| {
-        li.timestamp = 1000;
-    });
-
-    let result = contract.calculate_taking_amount(
-        &100,      // making_amount
-        &1000,     // taking_amount_start (higher price)
-        &500,      // taking_amount_end (lower price)
-        &1000,     // auction_start_time
-        &2000,     // auction_end_time
-    );
-
-    assert_eq!(result, 1000); // Should return start price
-}
-
-#[test]
-fn test_calculate_taking_amount_at_end() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-    
-    // Set time to after auction end
-    env.ledger().with_mut(|li| {
-        li.timestamp = 2500;
-    });
-
-    let result = contract.calculate_taking_amount(
-        &100,      // making_amount
-        &1000,     // taking_amount_start
-        &500,      // taking_amount_end
-        &1000,     // auction_start_time
-        &2000,     // auction_end_time
-    );
-
-    assert_eq!(result, 500); // Should return end price
-}
-
-#[test]
-fn test_calculate_taking_amount_midway() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-    
-    // Set time to middle of auction (50% through)
-    env.ledger().with_mut(|li| {
-        li.timestamp = 1500;
-    });
-
-    let result = contract.calculate_taking_amount(
-        &100,      // making_amount
-        &1000,     // taking_amount_start
-        &500,      // taking_amount_end
-        &1000,     // auction_start_time
-        &2000,     // auction_end_time
-    );
-
-    assert_eq!(result, 750); // Should be halfway: 1000 - (500 * 0.5) = 750
-}
-
-#[test]
-fn test_calculate_taking_amount_before_start() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-    
-    // Set time before auction start
-    env.ledger().with_mut(|li| {
-        li.timestamp = 500;
-    });
-
-    let result = contract.calculate_taking_amount(
-        &100,      // making_amount
-        &1000,     // taking_amount_start
-        &500,      // taking_amount_end
-        &1000,     // auction_start_time
-        &2000,     // auction_end_time
-    );
-
-    assert_eq!(result, 1000); // Should return start price
-}
-
-#[test]
-fn test_invalid_time_range() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-
-    let result = contract.try_calculate_taking_amount(
-        &100,      // making_amount
-        &1000,     // taking_amount_start
-        &500,      // taking_amount_end
-        &2000,     // auction_start_time (after end time)
-        &1000,     // auction_end_time
-    );
-
-    assert_eq!(result, Err(Ok(Error::InvalidTimeRange)));
-}
-
-#[test]
-fn test_invalid_amount_range() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-
-    let result = contract.try_calculate_taking_amount(
-        &100,      // making_amount
-        &500,      // taking_amount_start (lower than end)
-        &1000,     // taking_amount_end
-        &1000,     // auction_start_time
-        &2000,     // auction_end_time
-    );
-
-    assert_eq!(result, Err(Ok(Error::InvalidAmountRange)));
-}
-
-#[test]
-fn test_calculate_making_amount_midway() {
-    let env = Env::default();
-    let contract = create_dutch_auction_contract(&env);
-    
-    // Set time to middle of auction (50% through)
-    env.ledger().with_mut(|li| {
-        li.timestamp = 1500;
-    });
-
-    let result = contract.calculate_making_amount(
-        &750,      // taking_amount
-        &100,      // making_amount_start (lower)
-        &200,      // making_amount_end (higher)
-        &1000,     // auction_start_time
-        &2000,     // auction_end_time
-    );
-
-    assert_eq!(result, 150); // Should be halfway: 100 + (100 * 0.5) = 150
-}
\ No newline at end of file
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    testutils::Ledger,
+    Env,
+};
+
+fn create_dutch_auction_contract(e: &Env) -> SorobanDutchAuctionClient<'_> {
+    SorobanDutchAuctionClient::new(e, &e.register(SorobanDutchAuction, ()))
+}
+
+#[test]
+fn test_calculate_taking_amount_at_start() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+    
+    // Set time to auction start
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1000;
+    });
+
+    let result = contract.calculate_taking_amount(
+        &100,      // making_amount
+        &1000,     // taking_amount_start (higher price)
+        &500,      // taking_amount_end (lower price)
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, 1000); // Should return start price
+}
+
+#[test]
+fn test_calculate_taking_amount_at_end() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+    
+    // Set time to after auction end
+    env.ledger().with_mut(|li| {
+        li.timestamp = 2500;
+    });
+
+    let result = contract.calculate_taking_amount(
+        &100,      // making_amount
+        &1000,     // taking_amount_start
+        &500,      // taking_amount_end
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, 500); // Should return end price
+}
+
+#[test]
+fn test_calculate_taking_amount_midway() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+    
+    // Set time to middle of auction (50% through)
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500;
+    });
+
+    let result = contract.calculate_taking_amount(
+        &100,      // making_amount
+        &1000,     // taking_amount_start
+        &500,      // taking_amount_end
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, 750); // Should be halfway: 1000 - (500 * 0.5) = 750
+}
+
+#[test]
+fn test_calculate_taking_amount_before_start() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+    
+    // Set time before auction start
+    env.ledger().with_mut(|li| {
+        li.timestamp = 500;
+    });
+
+    let result = contract.calculate_taking_amount(
+        &100,      // making_amount
+        &1000,     // taking_amount_start
+        &500,      // taking_amount_end
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, 1000); // Should return start price
+}
+
+#[test]
+fn test_invalid_time_range() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    let result = contract.try_calculate_taking_amount(
+        &100,      // making_amount
+        &1000,     // taking_amount_start
+        &500,      // taking_amount_end
+        &2000,     // auction_start_time (after end time)
+        &1000,     // auction_end_time
+    );
+
+    assert_eq!(result, Err(Ok(Error::InvalidTimeRange)));
+}
+
+#[test]
+fn test_invalid_amount_range() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    let result = contract.try_calculate_taking_amount(
+        &100,      // making_amount
+        &500,      // taking_amount_start (lower than end)
+        &1000,     // taking_amount_end
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, Err(Ok(Error::InvalidAmountRange)));
+}
+
+#[test]
+fn test_calculate_making_amount_midway() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+    
+    // Set time to middle of auction (50% through)
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500;
+    });
+
+    let result = contract.calculate_making_amount(
+        &750,      // taking_amount
+        &100,      // making_amount_start (lower)
+        &200,      // making_amount_end (higher)
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, 150); // Should be halfway: 100 + (100 * 0.5) = 150
+}
+#[test]
+fn test_calculate_taking_amount_rounded_reduces_error() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // Long auction with a small price range: almost all of the range has elapsed
+    env.ledger().with_mut(|li| {
+        li.timestamp = 999_999;
+    });
+
+    // Exact price is 103 - 3 * 999_999 / 1_000_000 = 100.000003
+    let truncated = contract.calculate_taking_amount(
+        &100,        // making_amount
+        &103,        // taking_amount_start
+        &100,        // taking_amount_end
+        &0,          // auction_start_time
+        &1_000_000,  // auction_end_time
+    );
+    let rounded = contract.calculate_taking_amount_rounded(
+        &100,        // making_amount
+        &103,        // taking_amount_start
+        &100,        // taking_amount_end
+        &0,          // auction_start_time
+        &1_000_000,  // auction_end_time
+    );
+
+    assert_eq!(truncated, 101); // Truncation loses almost a full unit
+    assert_eq!(rounded, 100);   // Rounded result is within half a unit
+
+    // Just under half a unit of reduction still rounds down
+    env.ledger().with_mut(|li| {
+        li.timestamp = 166_666;
+    });
+    assert_eq!(contract.calculate_taking_amount_rounded(&100, &103, &100, &0, &1_000_000), 103);
+}
+
+#[test]
+fn test_calculate_taking_amount_rounded_invalid_range() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    let result = contract.try_calculate_taking_amount_rounded(
+        &100,      // making_amount
+        &500,      // taking_amount_start
+        &1000,     // taking_amount_end (should be lower than start)
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, Err(Ok(Error::InvalidAmountRange)));
+}
+
+#[test]
+fn test_current_discount_bps_midway() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+    
+    // Set time to middle of auction (50% through)
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500;
+    });
+
+    let result = contract.current_discount_bps(
+        &1000,     // taking_amount_start
+        &500,      // taking_amount_end
+        &1000,     // auction_start_time
+        &2000,     // auction_end_time
+    );
+
+    assert_eq!(result, 2500); // Price is 750, 25% below the start price
+
+    // Validation is shared with calculate_taking_amount
+    assert_eq!(
+        contract.try_current_discount_bps(&500, &1000, &1000, &2000),
+        Err(Ok(Error::InvalidAmountRange))
+    );
+}
+
+#[test]
+fn test_calculate_taking_amount_by_rate() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // 1000 making at a rate falling from 3.0 to 1.5 taking per making
+    let making_amount = 1000;
+    let rate_start = 30_000_000;
+    let rate_end = 15_000_000;
+
+    // At start: 1000 * 3.0
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1000;
+    });
+    assert_eq!(
+        contract.calculate_taking_amount_by_rate(&making_amount, &rate_start, &rate_end, &1000, &2000),
+        3000
+    );
+
+    // Midway: rate 2.25
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500;
+    });
+    assert_eq!(
+        contract.calculate_taking_amount_by_rate(&making_amount, &rate_start, &rate_end, &1000, &2000),
+        2250
+    );
+
+    // At end: 1000 * 1.5
+    env.ledger().with_mut(|li| {
+        li.timestamp = 2000;
+    });
+    assert_eq!(
+        contract.calculate_taking_amount_by_rate(&making_amount, &rate_start, &rate_end, &1000, &2000),
+        1500
+    );
+}
+
+#[test]
+fn test_calculate_taking_amount_by_rate_invalid_range() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // Rate must decrease over the auction
+    let result = contract.try_calculate_taking_amount_by_rate(
+        &1000,
+        &15_000_000,
+        &30_000_000,
+        &1000,
+        &2000,
+    );
+
+    assert_eq!(result, Err(Ok(Error::InvalidAmountRange)));
+}
+
+#[test]
+fn test_auction_progress_bps() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // At start
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1000;
+    });
+    assert_eq!(contract.auction_progress_bps(&1000, &2000), 0);
+
+    // Midpoint
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500;
+    });
+    assert_eq!(contract.auction_progress_bps(&1000, &2000), 5000);
+
+    // After end
+    env.ledger().with_mut(|li| {
+        li.timestamp = 2500;
+    });
+    assert_eq!(contract.auction_progress_bps(&1000, &2000), 10_000);
+
+    // Invalid window
+    assert_eq!(
+        contract.try_auction_progress_bps(&2000, &1000),
+        Err(Ok(Error::InvalidTimeRange))
+    );
+}
+
+#[test]
+fn test_zero_duration_auction_returns_end_price() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // Before, at and after the instant the end price applies
+    for timestamp in [500, 1000, 1500] {
+        env.ledger().with_mut(|li| {
+            li.timestamp = timestamp;
+        });
+
+        let result = contract.calculate_taking_amount(
+            &100,      // making_amount
+            &1000,     // taking_amount_start
+            &500,      // taking_amount_end
+            &1000,     // auction_start_time
+            &1000,     // auction_end_time (same as start)
+        );
+
+        assert_eq!(result, 500);
+    }
+}
+
+#[test]
+fn test_blackout_freezes_price() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // 1000 -> 0 over 1000..2000, frozen during 1200..1500
+    let price_at = |timestamp: u64| {
+        env.ledger().with_mut(|li| {
+            li.timestamp = timestamp;
+        });
+        contract.calculate_taking_amount_blackout(
+            &100, &1000, &0, &1000, &2000, &1200, &1500,
+        )
+    };
+
+    // Before the blackout the schedule is unchanged
+    assert_eq!(price_at(1100), 900);
+
+    // Flat during the blackout at the blackout_start price
+    assert_eq!(price_at(1200), 800);
+    assert_eq!(price_at(1350), 800);
+    assert_eq!(price_at(1499), 800);
+
+    // Decay resumes afterwards, shifted by the 300s blackout
+    assert_eq!(price_at(1500), 800);
+    assert_eq!(price_at(1600), 700);
+    assert_eq!(price_at(2000), 300);
+    assert_eq!(price_at(2300), 0);
+
+    // Blackout outside the auction window is rejected
+    assert_eq!(
+        contract.try_calculate_taking_amount_blackout(
+            &100, &1000, &0, &1000, &2000, &1800, &2100,
+        ),
+        Err(Ok(Error::InvalidBlackout))
+    );
+}
+
+#[test]
+fn test_time_to_price() {
+    let env = Env::default();
+    let contract = create_dutch_auction_contract(&env);
+
+    // The midpoint price is reached at the midpoint timestamp
+    let timestamp = contract.time_to_price(&1000, &500, &1000, &2000, &750);
+    assert_eq!(timestamp, 1500);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = timestamp;
+    });
+    assert_eq!(contract.calculate_taking_amount(&100, &1000, &500, &1000, &2000), 750);
+
+    // The bounds map to the start and end of the auction
+    assert_eq!(contract.time_to_price(&1000, &500, &1000, &2000, &1000), 1000);
+    assert_eq!(contract.time_to_price(&1000, &500, &1000, &2000, &500), 2000);
+
+    // Targets outside the schedule are never reached
+    assert_eq!(
+        contract.try_time_to_price(&1000, &500, &1000, &2000, &499),
+        Err(Ok(Error::PriceOutOfRange))
+    );
+    assert_eq!(
+        contract.try_time_to_price(&1000, &500, &1000, &2000, &1001),
+        Err(Ok(Error::PriceOutOfRange))
+    );
+}
@@ -1,142 +1,381 @@
-#![no_std]
-use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, Address, BytesN, Env, IntoVal, Symbol, Vec,
-};
-
-// Define the Immutables struct locally to match the escrow contract exactly
-#[contracttype]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Immutables {
-    pub hashlock: BytesN<32>,
-    pub maker: Address,
-    pub taker: Address,
-    pub token: Address,
-    pub amount: i128,
-    pub cancellation_timestamp: u64,
-}
-
-#[contracttype]
-pub enum DataKey {
-    EscrowWasmHash,
-    Admin,
-}
-
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-#[repr(u32)]
-pub enum Error {
-    NotInitialized = 1,
-    AlreadyInitialized = 2,
-    NotAuthorized = 3,
-    DeploymentFailed = 4,
-}
-
-#[contract]
-pub struct SorobanEscrowFactory;
-
-#[contractimpl]
-impl SorobanEscrowFactory {
-    /// Initialize the factory with the escrow contract WASM hash
-    pub fn initialize(env: Env, admin: Address, escrow_wasm_hash: BytesN<32>) -> Result<(), Error> {
-        // Check if already initialized
-        if env.storage().instance().has(&DataKey::EscrowWasmHash) {
-            return Err(Error::AlreadyInitialized);
-        }
-
-        // Store the WASM hash and admin
-        env.storage().instance().set(&DataKey::EscrowWasmHash, &escrow_wasm_hash);
-        env.storage().instance().set(&DataKey::Admin, &admin);
-
-        Ok(())
-    }
-
-    /// Deploy a new escrow contract with deterministic address
-    pub fn deploy_escrow(
-        env: Env,
-        immutables: Immutables,
-        salt: BytesN<32>,
-    ) -> Result<Address, Error> {
-        // Get the stored WASM hash
-        let escrow_wasm_hash: BytesN<32> = env
-            .storage()
-            .instance()
-            .get(&DataKey::EscrowWasmHash)
-            .ok_or(Error::NotInitialized)?;
-
-        // Deploy the contract deterministically WITHOUT constructor parameters
-        let escrow_address = env
-            .deployer()
-            .with_address(env.current_contract_address(), salt)
-            .deploy_v2(escrow_wasm_hash, ());
-
-        // Initialize the escrow contract by calling its initialize function directly
-        let initialize_args = Vec::from_array(&env, [immutables.into_val(&env)]);
-        let result: Result<(), soroban_sdk::Error> = env.invoke_contract(&escrow_address, &Symbol::new(&env, "initialize"), initialize_args);
-        
-        match result {
-            Ok(_) => {},
-            Err(_) => return Err(Error::DeploymentFailed),
-        }
-
-        // Emit deployment event
-        env.events().publish(("deploy_escrow",), &escrow_address);
-
-        Ok(escrow_address)
-    }
-
-    /// Get the deterministic address of an escrow contract without deploying it
-    pub fn get_escrow_address(
-        env: Env,
-        salt: BytesN<32>,
-    ) -> Result<Address, Error> {
-        // Get the stored WASM hash (we don't use it but need to check if initialized)
-        let _escrow_wasm_hash: BytesN<32> = env
-            .storage()
-            .instance()
-            .get(&DataKey::EscrowWasmHash)
-            .ok_or(Error::NotInitialized)?;
-
-        // Compute the deterministic address
-        let escrow_address = env
-            .deployer()
-            .with_address(env.current_contract_address(), salt)
-            .deployed_address();
-
-        Ok(escrow_address)
-    }
-
-    /// Update the escrow WASM hash (admin only)
-    pub fn update_escrow_wasm_hash(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Error> {
-        // Check authorization
-        let admin: Address = env
-            .storage()
-            .instance()
-            .get(&DataKey::Admin)
-            .ok_or(Error::NotInitialized)?;
-        
-        admin.require_auth();
-
-        // Update the WASM hash
-        env.storage().instance().set(&DataKey::EscrowWasmHash, &new_wasm_hash);
-
-        Ok(())
-    }
-
-    /// Get the current escrow WASM hash
-    pub fn get_escrow_wasm_hash(env: Env) -> Result<BytesN<32>, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::EscrowWasmHash)
-            .ok_or(Error::NotInitialized)
-    }
-
-    /// Get the admin address
-    pub fn get_admin(env: Env) -> Result<Address, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::Admin)
-            .ok_or(Error::NotInitialized)
-    }
-}
-
+#![no_std]
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, token, Address, Bytes, BytesN, Env, IntoVal,
+    Symbol, Vec,
+};
+
+// Define the Timelocks struct locally to match the escrow contract exactly
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Timelocks {
+    pub deployed_at: u64, // Stage offsets below are seconds after this timestamp
+    pub withdrawal: u64, // Finality lock ends; the taker can withdraw
+    pub public_withdrawal: u64, // Anyone with the secret can push funds to the taker
+    pub cancellation: u64, // Withdrawals close; the maker can cancel
+    pub public_cancellation: u64, // Anyone can cancel, refunding the maker
+}
+
+// Define the Immutables struct locally to match the escrow contract exactly
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Immutables {
+    pub hashlock: BytesN<32>,
+    pub maker: Address,
+    pub taker: Address,
+    pub token: Address,
+    pub amount: i128,
+    pub timelocks: Timelocks,
+    pub safety_deposit: i128, // Paid in deposit_token to whoever finalizes the escrow
+    pub deposit_token: Address,
+    pub hash_algorithm: u32, // Hash applied to secrets: 0 = sha256, 1 = keccak256 (EVM-compatible)
+    pub arbiter: Option<Address>, // When set, must also approve every release to the taker
+}
+
+// Define the CancelFee struct locally to match the escrow contract exactly
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CancelFee {
+    pub cancel_fee_bps: u32, // Basis points of each cancellation refund sent to the treasury
+    pub treasury: Address,
+}
+
+#[contracttype]
+pub enum DataKey {
+    EscrowWasmHash,
+    Admin,
+    CancelFee, // Fee every deployed escrow takes from cancellation refunds
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    NotAuthorized = 3,
+    DeploymentFailed = 4,
+    WasmHashMismatch = 5,
+    RolloverFailed = 6,
+    InvalidFee = 7,
+}
+
+// Reason passed to the old escrow's `cancel` on rollover (normal)
+const ROLLOVER_CANCEL_REASON: u32 = 0;
+
+// Largest cancellation fee escrows accept, in basis points
+const MAX_CANCEL_FEE_BPS: u32 = 500;
+
+#[contract]
+pub struct SorobanEscrowFactory;
+
+#[contractimpl]
+impl SorobanEscrowFactory {
+    /// Initialize the factory with the escrow contract WASM hash
+    pub fn initialize(env: Env, admin: Address, escrow_wasm_hash: BytesN<32>) -> Result<(), Error> {
+        // Check if already initialized
+        if env.storage().instance().has(&DataKey::EscrowWasmHash) {
+            return Err(Error::AlreadyInitialized);
+        }
+
+        // Store the WASM hash and admin
+        env.storage().instance().set(&DataKey::EscrowWasmHash, &escrow_wasm_hash);
+        env.storage().instance().set(&DataKey::Admin, &admin);
+
+        Ok(())
+    }
+
+    /// Deploy a new escrow contract with deterministic address
+    pub fn deploy_escrow(
+        env: Env,
+        immutables: Immutables,
+        salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Get the stored WASM hash
+        let escrow_wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::EscrowWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        deploy_escrow_internal(&env, escrow_wasm_hash, immutables, salt)
+    }
+
+    /// Deploy a new escrow only if the stored WASM hash matches the expected one
+    /// Guards a predicted address against an admin hash update before deployment
+    pub fn deploy_escrow_pinned(
+        env: Env,
+        immutables: Immutables,
+        salt: BytesN<32>,
+        expected_wasm_hash: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Get the stored WASM hash
+        let escrow_wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::EscrowWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // Reject if the implementation changed since the address was predicted
+        if escrow_wasm_hash != expected_wasm_hash {
+            return Err(Error::WasmHashMismatch);
+        }
+
+        deploy_escrow_internal(&env, escrow_wasm_hash, immutables, salt)
+    }
+
+    /// Cancel an expired escrow and roll the maker's refund into a new escrow (maker only)
+    /// The new escrow is deployed under `new_salt` and funded with `new_immutables.amount`
+    /// plus its safety deposit
+    pub fn rollover_escrow(
+        env: Env,
+        old_escrow: Address,
+        new_immutables: Immutables,
+        new_salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Get the stored WASM hash
+        let escrow_wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::EscrowWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // Require authorization from maker
+        new_immutables.maker.require_auth();
+
+        // The old escrow must belong to the same maker
+        let old_immutables = match env.try_invoke_contract::<Immutables, soroban_sdk::Error>(
+            &old_escrow,
+            &Symbol::new(&env, "get_immutables"),
+            Vec::new(&env),
+        ) {
+            Ok(Ok(immutables)) => immutables,
+            _ => return Err(Error::RolloverFailed),
+        };
+
+        if old_immutables.maker != new_immutables.maker {
+            return Err(Error::NotAuthorized);
+        }
+
+        // Cancel the old escrow, refunding the maker (fails before its deadline)
+        match env.try_invoke_contract::<(), soroban_sdk::Error>(
+            &old_escrow,
+            &Symbol::new(&env, "cancel"),
+            (Some(ROLLOVER_CANCEL_REASON),).into_val(&env),
+        ) {
+            Ok(Ok(())) => {},
+            _ => return Err(Error::RolloverFailed),
+        }
+
+        // Deploy the new escrow and fund it from the maker
+        let escrow_address =
+            deploy_escrow_internal(&env, escrow_wasm_hash, new_immutables.clone(), new_salt)?;
+
+        let token_client = token::Client::new(&env, &new_immutables.token);
+        token_client.transfer(&new_immutables.maker, &escrow_address, &new_immutables.amount);
+        if new_immutables.safety_deposit > 0 {
+            let deposit_client = token::Client::new(&env, &new_immutables.deposit_token);
+            deposit_client.transfer(&new_immutables.maker, &escrow_address, &new_immutables.safety_deposit);
+        }
+
+        // Emit rollover event
+        env.events().publish(("rollover_escrow",), (old_escrow, escrow_address.clone()));
+
+        Ok(escrow_address)
+    }
+
+    /// Get the deterministic address of an escrow contract without deploying it
+    pub fn get_escrow_address(
+        env: Env,
+        salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Get the stored WASM hash (we don't use it but need to check if initialized)
+        let _escrow_wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::EscrowWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // Compute the deterministic address
+        let escrow_address = env
+            .deployer()
+            .with_address(env.current_contract_address(), salt)
+            .deployed_address();
+
+        Ok(escrow_address)
+    }
+
+    /// Get the address `deployer` would deploy an escrow to under `salt`
+    /// Mirrors the `deploy_v2` derivation for any deployer, so addresses can be reproduced without
+    /// this factory; Soroban addresses depend only on deployer and salt, not on `immutables`
+    pub fn address_for_immutables(
+        env: Env,
+        deployer: Address,
+        _immutables: Immutables,
+        salt: BytesN<32>,
+    ) -> Address {
+        env.deployer().with_address(deployer, salt).deployed_address()
+    }
+
+    /// Derive the escrow salt for one leg of a cross-chain swap
+    /// `sha256(swap_id || leg)` with the leg as a big-endian u32, so both sides compute matching salts
+    pub fn derive_leg_salt(env: Env, swap_id: BytesN<32>, leg: u32) -> BytesN<32> {
+        let mut data = Bytes::from_array(&env, &swap_id.to_array());
+        data.extend_from_array(&leg.to_be_bytes());
+        env.crypto().sha256(&data).into()
+    }
+
+    /// Check that two legs of a cross-chain swap are consistent: both lock the same hashlock
+    /// and the source leg's maker is the destination leg's taker (and vice versa)
+    pub fn verify_swap_legs(_env: Env, source_immutables: Immutables, dest_immutables: Immutables) -> bool {
+        source_immutables.hashlock == dest_immutables.hashlock
+            && source_immutables.maker == dest_immutables.taker
+            && source_immutables.taker == dest_immutables.maker
+    }
+
+    /// Verify that `claimed_addr` is the escrow this factory deploys for `salt`
+    /// and that it was initialized with exactly `immutables`
+    pub fn verify_escrow_address(
+        env: Env,
+        immutables: Immutables,
+        salt: BytesN<32>,
+        claimed_addr: Address,
+    ) -> bool {
+        // Recompute the deterministic address
+        let escrow_address = env
+            .deployer()
+            .with_address(env.current_contract_address(), salt)
+            .deployed_address();
+
+        if escrow_address != claimed_addr {
+            return false;
+        }
+
+        // The escrow must exist and hold the agreed immutables
+        let result = env.try_invoke_contract::<Immutables, soroban_sdk::Error>(
+            &claimed_addr,
+            &Symbol::new(&env, "get_immutables"),
+            Vec::new(&env),
+        );
+
+        matches!(result, Ok(Ok(deployed)) if deployed == immutables)
+    }
+
+    /// Update the escrow WASM hash (admin only)
+    pub fn update_escrow_wasm_hash(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+        
+        admin.require_auth();
+
+        // Update the WASM hash
+        env.storage().instance().set(&DataKey::EscrowWasmHash, &new_wasm_hash);
+
+        // Emit admin action event
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "update_escrow_wasm_hash")),
+            &new_wasm_hash,
+        );
+
+        Ok(())
+    }
+
+    /// Set the fee escrows deployed from now on take from cancellation refunds (admin only)
+    /// `None` stops charging it; at most `MAX_CANCEL_FEE_BPS`
+    pub fn set_cancel_fee(env: Env, cancel_fee: Option<CancelFee>) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        match &cancel_fee {
+            Some(fee) if fee.cancel_fee_bps > MAX_CANCEL_FEE_BPS => return Err(Error::InvalidFee),
+            Some(fee) => env.storage().instance().set(&DataKey::CancelFee, fee),
+            None => env.storage().instance().remove(&DataKey::CancelFee),
+        }
+
+        // Emit admin action event
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "set_cancel_fee")),
+            cancel_fee,
+        );
+
+        Ok(())
+    }
+
+    /// Get the fee deployed escrows take from cancellation refunds, if any
+    pub fn get_cancel_fee(env: Env) -> Option<CancelFee> {
+        env.storage().instance().get(&DataKey::CancelFee)
+    }
+
+    /// Get the current escrow WASM hash
+    pub fn get_escrow_wasm_hash(env: Env) -> Result<BytesN<32>, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::EscrowWasmHash)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the admin address
+    pub fn get_admin(env: Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the symbolic name of an error code (e.g. 7 -> "InvalidFee")
+    /// Returns "Unknown" for codes not defined by this contract
+    pub fn error_name(env: Env, code: u32) -> Symbol {
+        let name = match code {
+            1 => "NotInitialized",
+            2 => "AlreadyInitialized",
+            3 => "NotAuthorized",
+            4 => "DeploymentFailed",
+            5 => "WasmHashMismatch",
+            6 => "RolloverFailed",
+            7 => "InvalidFee",
+            _ => "Unknown",
+        };
+        Symbol::new(&env, name)
+    }
+}
+
+/// Internal helper function to deploy and initialize an escrow contract
+fn deploy_escrow_internal(
+    env: &Env,
+    escrow_wasm_hash: BytesN<32>,
+    immutables: Immutables,
+    salt: BytesN<32>,
+) -> Result<Address, Error> {
+    // Deploy the contract deterministically WITHOUT constructor parameters
+    let escrow_address = env
+        .deployer()
+        .with_address(env.current_contract_address(), salt)
+        .deploy_v2(escrow_wasm_hash, ());
+
+    // Initialize the escrow contract by calling its initialize function directly,
+    // charging the admin-configured cancellation fee
+    let cancel_fee = SorobanEscrowFactory::get_cancel_fee(env.clone());
+    let initialize_args = Vec::from_array(env, [immutables.into_val(env), cancel_fee.into_val(env)]);
+    let result: Result<(), soroban_sdk::Error> = env.invoke_contract(&escrow_address, &Symbol::new(env, "initialize"), initialize_args);
+    
+    match result {
+        Ok(_) => {},
+        Err(_) => return Err(Error::DeploymentFailed),
+    }
+
+    // Emit deployment event
+    env.events().publish(("deploy_escrow",), &escrow_address);
+
+    Ok(escrow_address)
+}
+
 mod test;
\ No newline at end of file
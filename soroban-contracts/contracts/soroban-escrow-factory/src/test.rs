@@ -1,242 +1,586 @@
-#![cfg(test)]
-extern crate std;
-
-use soroban_sdk::{
-    testutils::{Address as _, Ledger},
-    token, Address, Bytes, BytesN, Env,
-};
-
-// ---------- Adjust these imports to your paths if needed ----------
-mod factory {
-    // If factory is another crate/artifact, point to its compiled WASM:
-    // e.g. "../../target/wasm32v1-none/release/soroban_escrow_factory_contract.wasm"
-    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/soroban_escrow_factory_contract.wasm");
-}
-mod escrow {
-    // If THIS crate is the escrow contract, you can REMOVE this import and
-    // instead use the generated in-crate client type (e.g., SorobanEscrowClient).
-    // Otherwise, import the escrow wasm like this:
-    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/soroban_escrow_contract.wasm");
-}
-
-// Mirror the Immutables struct the factory expects (must match your contract)
-#[derive(Clone)]
-struct Immutables {
-    hashlock: BytesN<32>,
-    maker: Address,
-    taker: Address,
-    token: Address,
-    amount: i128,
-    cancellation_timestamp: u64,
-}
-
-// Helpers
-fn create_accounts(env: &Env) -> (Address, Address, Address) {
-    let admin = Address::generate(env);
-    let maker = Address::generate(env);
-    let taker = Address::generate(env);
-    (admin, maker, taker)
-}
-
-fn create_token_contract<'a>(
-    env: &Env,
-    admin: &Address,
-) -> (token::Client<'a>, token::StellarAssetClient<'a>, Address) {
-    // Register a Stellar Asset Contract (SAC v2)
-    let sac = env.register_stellar_asset_contract_v2(admin.clone());
-    let token = token::Client::new(env, &sac.address());
-    let admin_client = token::StellarAssetClient::new(env, &sac.address());
-    (token, admin_client, sac.address())
-}
-
-fn sha256_bytes32(env: &Env, secret_32: &[u8; 32]) -> BytesN<32> {
-    let b = Bytes::from_array(env, secret_32);
-    env.crypto().sha256(&b).into()
-}
-
-fn build_immutables(
-    env: &Env,
-    token_addr: &Address,
-    maker: &Address,
-    taker: &Address,
-    amount: i128,
-    cancel_ts: u64,
-    secret: &[u8; 32],
-) -> (Immutables, BytesN<32>) {
-    let hashlock = sha256_bytes32(env, secret);
-    (
-        Immutables {
-            hashlock: hashlock.clone(),
-            maker: maker.clone(),
-            taker: taker.clone(),
-            token: token_addr.clone(),
-            amount,
-            cancellation_timestamp: cancel_ts,
-        },
-        hashlock,
-    )
-}
-
-fn as_bytesn32(env: &Env, fill: u8) -> BytesN<32> {
-    BytesN::from_array(env, &[fill; 32])
-}
-
-#[test]
-fn deploy_and_initialize_works() {
-    let env = Env::default();
-
-    // Time zero
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10_000;
-    });
-
-    // Accounts and token
-    let (admin, maker, taker) = create_accounts(&env);
-    let (token, _token_admin, token_addr) = create_token_contract(&env, &admin);
-
-    // Register factory
-    let factory_id = env.register_contract_wasm(None, factory::WASM);
-    let factory = factory::Client::new(&env, &factory_id);
-
-    // Upload escrow WASM and initialize factory
-    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
-    factory.initialize(&admin, &escrow_wasm_hash);
-
-    // Build immutables (secret -> hashlock)
-    let secret = [7u8; 32];
-    let (immutables, _hashlock) =
-        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
-
-    // Salt for deterministic address
-    let salt = as_bytesn32(&env, 1);
-
-    // Deploy escrow via factory (new factory returns Address of new escrow)
-    let escrow_addr = factory.deploy_escrow(
-        &factory::Immutables {
-            hashlock: immutables.hashlock.clone(),
-            maker: immutables.maker.clone(),
-            taker: immutables.taker.clone(),
-            token: immutables.token.clone(),
-            amount: immutables.amount,
-            cancellation_timestamp: immutables.cancellation_timestamp,
-        },
-        &salt,
-    );
-
-    // Escrow client (imported or in-crate)
-    let escrow = escrow::Client::new(&env, &escrow_addr);
-
-    // Sanity: escrow was deployed, not equal to factory address
-    assert_ne!(escrow_addr, factory_id);
-
-    // (Optional) assert initialized flag/immutables if your escrow exposes getters
-    // e.g., let got = escrow.get_immutables(); assert_eq!(got.amount, 1_000);
-    // Otherwise, mint and check flows in the next tests.
-    // Just verify zero balance initially.
-    assert_eq!(token.balance(&escrow_addr), 0);
-}
-
-#[test]
-fn withdraw_before_deadline_works() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    env.ledger().with_mut(|li| {
-        li.timestamp = 12_000; // before cancel window
-    });
-
-    let (admin, maker, taker) = create_accounts(&env);
-    let (token, token_admin, token_addr) = create_token_contract(&env, &admin);
-
-    let factory_id = env.register_contract_wasm(None, factory::WASM);
-    let factory = factory::Client::new(&env, &factory_id);
-
-    // Upload escrow WASM and initialize factory
-    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
-    factory.initialize(&admin, &escrow_wasm_hash);
-
-    let secret = [9u8; 32];
-    let (immutables, _hashlock) =
-        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 20_000, &secret);
-
-    let salt = as_bytesn32(&env, 2);
-    let escrow_addr = factory.deploy_escrow(
-        &factory::Immutables {
-            hashlock: immutables.hashlock.clone(),
-            maker: immutables.maker.clone(),
-            taker: immutables.taker.clone(),
-            token: immutables.token.clone(),
-            amount: immutables.amount,
-            cancellation_timestamp: immutables.cancellation_timestamp,
-        },
-        &salt,
-    );
-
-    // fund escrow with tokens
-    token_admin.mint(&escrow_addr, &immutables.amount);
-    assert_eq!(token.balance(&escrow_addr), 1_000);
-
-    // taker withdraws by providing secret (must be authorized as taker)
-    let escrow = escrow::Client::new(&env, &escrow_addr);
-    let secret_bn = BytesN::from_array(&env, &secret);
-
-    escrow.withdraw(&secret_bn);
-
-    assert_eq!(token.balance(&escrow_addr), 0);
-    assert_eq!(token.balance(&taker), 1_000);
-}
-
-#[test]
-fn cancel_after_deadline_refunds_maker() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    env.ledger().with_mut(|li| {
-        li.timestamp = 14_000;
-    });
-
-    let (admin, maker, taker) = create_accounts(&env);
-    let (token, token_admin, token_addr) = create_token_contract(&env, &admin);
-
-    let factory_id = env.register_contract_wasm(None, factory::WASM);
-    let factory = factory::Client::new(&env, &factory_id);
-
-    // Upload escrow WASM and initialize factory
-    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
-    factory.initialize(&admin, &escrow_wasm_hash);
-
-    let secret = [5u8; 32];
-    let (immutables, _hashlock) =
-        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
-
-    let salt = as_bytesn32(&env, 3);
-    let escrow_addr = factory.deploy_escrow(
-        &factory::Immutables {
-            hashlock: immutables.hashlock.clone(),
-            maker: immutables.maker.clone(),
-            taker: immutables.taker.clone(),
-            token: immutables.token.clone(),
-            amount: immutables.amount,
-            cancellation_timestamp: immutables.cancellation_timestamp,
-        },
-        &salt,
-    );
-
-    // fund escrow
-    token_admin.mint(&escrow_addr, &immutables.amount);
-    assert_eq!(token.balance(&escrow_addr), 1_000);
-
-    // advance time beyond cancellation timestamp
-    env.ledger().with_mut(|li| {
-        li.timestamp = 16_000;
-    });
-
-    // maker cancels -> refund to maker
-    let escrow = escrow::Client::new(&env, &escrow_addr);
-
-    // If your escrow method is named `refund()`, replace `.cancel()` with `.refund()`.
-    escrow.cancel();
-
-    assert_eq!(token.balance(&maker), 1_000);
-    assert_eq!(token.balance(&escrow_addr), 0);
-}
+#![cfg(test)]
+extern crate std;
+
+use soroban_sdk::{
+    testutils::{Address as _, Events, Ledger},
+    token, vec, Address, Bytes, BytesN, Env, IntoVal, Symbol,
+};
+
+// ---------- Adjust these imports to your paths if needed ----------
+mod factory {
+    // If factory is another crate/artifact, point to its compiled WASM:
+    // e.g. "../../target/wasm32v1-none/release/soroban_escrow_factory_contract.wasm"
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/soroban_escrow_factory_contract.wasm");
+}
+mod escrow {
+    // If THIS crate is the escrow contract, you can REMOVE this import and
+    // instead use the generated in-crate client type (e.g., SorobanEscrowClient).
+    // Otherwise, import the escrow wasm like this:
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/soroban_escrow_contract.wasm");
+}
+
+// Mirror the Immutables struct the factory expects (must match your contract)
+#[derive(Clone)]
+struct Immutables {
+    hashlock: BytesN<32>,
+    maker: Address,
+    taker: Address,
+    token: Address,
+    amount: i128,
+    timelocks: factory::Timelocks,
+    safety_deposit: i128,
+    deposit_token: Address,
+    hash_algorithm: u32,
+    arbiter: Option<Address>,
+}
+
+// Helpers
+fn create_accounts(env: &Env) -> (Address, Address, Address) {
+    let admin = Address::generate(env);
+    let maker = Address::generate(env);
+    let taker = Address::generate(env);
+    (admin, maker, taker)
+}
+
+fn create_token_contract<'a>(
+    env: &Env,
+    admin: &Address,
+) -> (token::Client<'a>, token::StellarAssetClient<'a>, Address) {
+    // Register a Stellar Asset Contract (SAC v2)
+    let sac = env.register_stellar_asset_contract_v2(admin.clone());
+    let token = token::Client::new(env, &sac.address());
+    let admin_client = token::StellarAssetClient::new(env, &sac.address());
+    (token, admin_client, sac.address())
+}
+
+fn sha256_bytes32(env: &Env, secret_32: &[u8; 32]) -> BytesN<32> {
+    let b = Bytes::from_array(env, secret_32);
+    env.crypto().sha256(&b).into()
+}
+
+fn build_immutables(
+    env: &Env,
+    token_addr: &Address,
+    maker: &Address,
+    taker: &Address,
+    amount: i128,
+    cancel_ts: u64,
+    secret: &[u8; 32],
+) -> (Immutables, BytesN<32>) {
+    let hashlock = sha256_bytes32(env, secret);
+    (
+        Immutables {
+            hashlock: hashlock.clone(),
+            maker: maker.clone(),
+            taker: taker.clone(),
+            token: token_addr.clone(),
+            amount,
+            timelocks: factory::Timelocks {
+                deployed_at: 0,
+                withdrawal: 0,
+                public_withdrawal: cancel_ts,
+                cancellation: cancel_ts,
+                public_cancellation: cancel_ts,
+            },
+            safety_deposit: 0,
+            deposit_token: token_addr.clone(),
+            hash_algorithm: 0,
+            arbiter: None,
+        },
+        hashlock,
+    )
+}
+
+// Convert the mirror into the factory's own Immutables type
+fn to_factory_immutables(immutables: &Immutables) -> factory::Immutables {
+    factory::Immutables {
+        hashlock: immutables.hashlock.clone(),
+        maker: immutables.maker.clone(),
+        taker: immutables.taker.clone(),
+        token: immutables.token.clone(),
+        amount: immutables.amount,
+        timelocks: immutables.timelocks.clone(),
+        safety_deposit: immutables.safety_deposit,
+        deposit_token: immutables.deposit_token.clone(),
+        hash_algorithm: immutables.hash_algorithm,
+        arbiter: immutables.arbiter.clone(),
+    }
+}
+
+fn as_bytesn32(env: &Env, fill: u8) -> BytesN<32> {
+    BytesN::from_array(env, &[fill; 32])
+}
+
+#[test]
+fn deploy_and_initialize_works() {
+    let env = Env::default();
+
+    // Time zero
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
+
+    // Accounts and token
+    let (admin, maker, taker) = create_accounts(&env);
+    let (token, _token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    // Register factory
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    // Upload escrow WASM and initialize factory
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    // Build immutables (secret -> hashlock)
+    let secret = [7u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+
+    // Salt for deterministic address
+    let salt = as_bytesn32(&env, 1);
+
+    // Deploy escrow via factory (new factory returns Address of new escrow)
+    let escrow_addr = factory.deploy_escrow(
+        &to_factory_immutables(&immutables),
+        &salt,
+    );
+
+    // Escrow client (imported or in-crate)
+    let escrow = escrow::Client::new(&env, &escrow_addr);
+
+    // Sanity: escrow was deployed, not equal to factory address
+    assert_ne!(escrow_addr, factory_id);
+
+    // Escrow was initialized with the immutables passed to the factory
+    let got = escrow.get_immutables();
+    assert_eq!(got.amount, 1_000);
+
+    // Just verify zero balance initially.
+    assert_eq!(token.balance(&escrow_addr), 0);
+}
+
+#[test]
+fn withdraw_before_deadline_works() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12_000; // before cancel window
+    });
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (token, token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    // Upload escrow WASM and initialize factory
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    let secret = [9u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 20_000, &secret);
+
+    let salt = as_bytesn32(&env, 2);
+    let escrow_addr = factory.deploy_escrow(
+        &to_factory_immutables(&immutables),
+        &salt,
+    );
+
+    // fund escrow with tokens
+    token_admin.mint(&escrow_addr, &immutables.amount);
+    assert_eq!(token.balance(&escrow_addr), 1_000);
+
+    // taker withdraws by providing secret (must be authorized as taker)
+    let escrow = escrow::Client::new(&env, &escrow_addr);
+    let secret_bn = BytesN::from_array(&env, &secret);
+
+    escrow.withdraw(&secret_bn, &None);
+
+    assert_eq!(token.balance(&escrow_addr), 0);
+    assert_eq!(token.balance(&taker), 1_000);
+}
+
+#[test]
+fn cancel_after_deadline_refunds_maker() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 14_000;
+    });
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (token, token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    // Upload escrow WASM and initialize factory
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    let secret = [5u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+
+    let salt = as_bytesn32(&env, 3);
+    let escrow_addr = factory.deploy_escrow(
+        &to_factory_immutables(&immutables),
+        &salt,
+    );
+
+    // fund escrow
+    token_admin.mint(&escrow_addr, &immutables.amount);
+    assert_eq!(token.balance(&escrow_addr), 1_000);
+
+    // advance time beyond cancellation timestamp
+    env.ledger().with_mut(|li| {
+        li.timestamp = 16_000;
+    });
+
+    // maker cancels -> refund to maker
+    let escrow = escrow::Client::new(&env, &escrow_addr);
+
+    // If your escrow method is named `refund()`, replace `.cancel()` with `.refund()`.
+    escrow.cancel(&None);
+
+    assert_eq!(token.balance(&maker), 1_000);
+    assert_eq!(token.balance(&escrow_addr), 0);
+}
+
+#[test]
+fn deploy_pinned_rejects_changed_wasm_hash() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (_token, _token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    // Upload escrow WASM and initialize factory
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    let secret = [4u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+    let factory_immutables = to_factory_immutables(&immutables);
+
+    // Resolver predicts the address assuming the current WASM
+    let salt = as_bytesn32(&env, 4);
+    let predicted = factory.get_escrow_address(&salt);
+
+    // Admin swaps the implementation before the deploy lands
+    factory.update_escrow_wasm_hash(&as_bytesn32(&env, 9));
+
+    // Pinned deploy refuses to deploy unexpected code
+    assert_eq!(
+        factory.try_deploy_escrow_pinned(&factory_immutables, &salt, &escrow_wasm_hash),
+        Err(Ok(factory::Error::WasmHashMismatch.into()))
+    );
+
+    // Restoring the expected hash lets the pinned deploy through
+    factory.update_escrow_wasm_hash(&escrow_wasm_hash);
+    let escrow_addr =
+        factory.deploy_escrow_pinned(&factory_immutables, &salt, &escrow_wasm_hash);
+    assert_eq!(escrow_addr, predicted);
+}
+
+#[test]
+fn update_wasm_hash_emits_admin_action() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (admin, _maker, _taker) = create_accounts(&env);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+    factory.initialize(&admin, &as_bytesn32(&env, 1));
+
+    let new_wasm_hash = as_bytesn32(&env, 2);
+    factory.update_escrow_wasm_hash(&new_wasm_hash);
+
+    assert_eq!(
+        env.events().all(),
+        vec![
+            &env,
+            (
+                factory_id.clone(),
+                ("admin_action", Symbol::new(&env, "update_escrow_wasm_hash")).into_val(&env),
+                new_wasm_hash.into_val(&env),
+            ),
+        ]
+    );
+}
+
+#[test]
+fn verify_escrow_address_matches_deployment() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (_token, _token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    let secret = [6u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+    let factory_immutables = to_factory_immutables(&immutables);
+
+    let salt = as_bytesn32(&env, 5);
+
+    // Nothing deployed yet at the predicted address
+    let predicted = factory.get_escrow_address(&salt);
+    assert!(!factory.verify_escrow_address(&factory_immutables, &salt, &predicted));
+
+    let escrow_addr = factory.deploy_escrow(&factory_immutables, &salt);
+
+    // Matching claim
+    assert!(factory.verify_escrow_address(&factory_immutables, &salt, &escrow_addr));
+
+    // Mismatched claimed address
+    let other_addr = Address::generate(&env);
+    assert!(!factory.verify_escrow_address(&factory_immutables, &salt, &other_addr));
+
+    // Right address but different immutables
+    let mut other_immutables = factory_immutables.clone();
+    other_immutables.amount = 2_000;
+    assert!(!factory.verify_escrow_address(&other_immutables, &salt, &escrow_addr));
+}
+
+#[test]
+fn rollover_expired_escrow_funds_new_escrow() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 14_000;
+    });
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (token, token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    // Original escrow, funded and then expired
+    let secret = [7u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+    let old_escrow = factory.deploy_escrow(
+        &to_factory_immutables(&immutables),
+        &as_bytesn32(&env, 6),
+    );
+    token_admin.mint(&old_escrow, &1_000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 16_000;
+    });
+
+    // Re-negotiated terms with a later deadline
+    let new_secret = [8u8; 32];
+    let (new_immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 30_000, &new_secret);
+    let new_factory_immutables = to_factory_immutables(&new_immutables);
+
+    let new_escrow =
+        factory.rollover_escrow(&old_escrow, &new_factory_immutables, &as_bytesn32(&env, 7));
+
+    // Funds moved from the old escrow into the new one
+    assert_eq!(token.balance(&old_escrow), 0);
+    assert_eq!(token.balance(&new_escrow), 1_000);
+    assert_eq!(token.balance(&maker), 0);
+
+    let escrow = escrow::Client::new(&env, &new_escrow);
+    assert_eq!(escrow.get_immutables().timelocks.cancellation, 30_000);
+}
+
+#[test]
+fn derive_leg_salt_is_distinct_per_leg() {
+    let env = Env::default();
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    let swap_id = as_bytesn32(&env, 9);
+    let leg0 = factory.derive_leg_salt(&swap_id, &0);
+    let leg1 = factory.derive_leg_salt(&swap_id, &1);
+
+    // Each leg gets its own salt
+    assert_ne!(leg0, leg1);
+
+    // Reproducible from the swap id alone
+    assert_eq!(factory.derive_leg_salt(&swap_id, &0), leg0);
+    assert_eq!(factory.derive_leg_salt(&swap_id, &1), leg1);
+
+    // Matches sha256(swap_id || leg)
+    let mut data = Bytes::from_array(&env, &[9u8; 32]);
+    data.extend_from_array(&1u32.to_be_bytes());
+    let expected: BytesN<32> = env.crypto().sha256(&data).into();
+    assert_eq!(leg1, expected);
+}
+
+#[test]
+fn address_for_immutables_matches_deployment() {
+    let env = Env::default();
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (_token, _token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    let secret = [7u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+    let factory_immutables = to_factory_immutables(&immutables);
+    let salt = as_bytesn32(&env, 8);
+
+    // Computed before deployment with the factory as deployer
+    let predicted = factory.address_for_immutables(&factory_id, &factory_immutables, &salt);
+    let escrow_addr = factory.deploy_escrow(&factory_immutables, &salt);
+    assert_eq!(predicted, escrow_addr);
+
+    // A different deployer gets a different address for the same salt
+    let other_deployer = Address::generate(&env);
+    assert_ne!(
+        factory.address_for_immutables(&other_deployer, &factory_immutables, &salt),
+        escrow_addr
+    );
+}
+
+#[test]
+fn verify_swap_legs_checks_hashlock_and_roles() {
+    let env = Env::default();
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (_token, _token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    let source = factory::Immutables {
+        hashlock: sha256_bytes32(&env, &[5u8; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token_addr.clone(),
+        amount: 1_000,
+        timelocks: factory::Timelocks {
+            deployed_at: 0,
+            withdrawal: 0,
+            public_withdrawal: 20_000,
+            cancellation: 20_000,
+            public_cancellation: 20_000,
+        },
+        safety_deposit: 0,
+        deposit_token: token_addr.clone(),
+        hash_algorithm: 0,
+        arbiter: None,
+    };
+    // The destination leg swaps roles and may differ in token, amount and deadline
+    let dest = factory::Immutables {
+        hashlock: source.hashlock.clone(),
+        maker: taker.clone(),
+        taker: maker.clone(),
+        token: Address::generate(&env),
+        amount: 2_000,
+        timelocks: factory::Timelocks {
+            deployed_at: 0,
+            withdrawal: 0,
+            public_withdrawal: 10_000,
+            cancellation: 10_000,
+            public_cancellation: 10_000,
+        },
+        safety_deposit: 0,
+        deposit_token: token_addr.clone(),
+        hash_algorithm: 0,
+        arbiter: None,
+    };
+    assert!(factory.verify_swap_legs(&source, &dest));
+
+    // A different hashlock breaks the link between the legs
+    let mut other_hashlock = dest.clone();
+    other_hashlock.hashlock = sha256_bytes32(&env, &[6u8; 32]);
+    assert!(!factory.verify_swap_legs(&source, &other_hashlock));
+
+    // Both legs paying the same taker is not a swap
+    let mut same_roles = dest.clone();
+    same_roles.maker = maker.clone();
+    same_roles.taker = taker.clone();
+    assert!(!factory.verify_swap_legs(&source, &same_roles));
+}
+
+#[test]
+fn deployed_escrows_take_the_admin_cancel_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (admin, maker, taker) = create_accounts(&env);
+    let (_token, _token_admin, token_addr) = create_token_contract(&env, &admin);
+
+    let factory_id = env.register(factory::WASM, ());
+    let factory = factory::Client::new(&env, &factory_id);
+
+    let escrow_wasm_hash = env.deployer().upload_contract_wasm(escrow::WASM);
+    factory.initialize(&admin, &escrow_wasm_hash);
+
+    let secret = [8u8; 32];
+    let (immutables, _hashlock) =
+        build_immutables(&env, &token_addr, &maker, &taker, 1_000, 15_000, &secret);
+    let factory_immutables = to_factory_immutables(&immutables);
+
+    // The fee is capped like the escrow's
+    let treasury = Address::generate(&env);
+    let mut cancel_fee = factory::CancelFee {
+        cancel_fee_bps: 501,
+        treasury: treasury.clone(),
+    };
+    assert_eq!(
+        factory.try_set_cancel_fee(&Some(cancel_fee.clone())),
+        Err(Ok(factory::Error::InvalidFee.into()))
+    );
+    cancel_fee.cancel_fee_bps = 250;
+    factory.set_cancel_fee(&Some(cancel_fee.clone()));
+    assert_eq!(env.auths()[0].0, admin);
+    assert_eq!(factory.get_cancel_fee(), Some(cancel_fee));
+
+    let escrow_addr = factory.deploy_escrow(&factory_immutables, &as_bytesn32(&env, 10));
+    let escrow = escrow::Client::new(&env, &escrow_addr);
+    assert_eq!(
+        escrow.get_cancel_fee(),
+        Some(escrow::CancelFee {
+            cancel_fee_bps: 250,
+            treasury,
+        })
+    );
+
+    // Clearing the fee only affects escrows deployed afterwards
+    factory.set_cancel_fee(&None);
+    let uncharged_addr = factory.deploy_escrow(&factory_immutables, &as_bytesn32(&env, 11));
+    assert_eq!(escrow::Client::new(&env, &uncharged_addr).get_cancel_fee(), None);
+    assert_eq!(escrow.get_cancel_fee().unwrap().cancel_fee_bps, 250);
+}
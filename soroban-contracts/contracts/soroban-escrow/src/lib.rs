@@ -1,129 +1,1152 @@
-#![no_std]
-use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, token, Address, BytesN, Env,
-};
-
-#[contracttype]
-pub enum DataKey {
-    Immutables,
-    Initialized,
-}
-
-#[contracttype]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Immutables {
-    pub hashlock: BytesN<32>,
-    pub maker: Address,
-    pub taker: Address,
-    pub token: Address,
-    pub amount: i128,
-    pub cancellation_timestamp: u64,
-}
-
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-#[repr(u32)]
-pub enum Error {
-    AlreadyInitialized = 1,
-    NotInitialized = 2,
-    InvalidSecret = 3,
-    NotAuthorized = 4,
-    TimePredicateNotMet = 5,
-    NegativeAmount = 6,
-}
-
-#[contract]
-pub struct SorobanEscrow;
-
-#[contractimpl]
-impl SorobanEscrow {
-    /// Initialize the escrow with immutable parameters
-    /// Can only be called once after deployment
-    pub fn initialize(env: Env, immutables: Immutables) -> Result<(), Error> {
-        // Check if already initialized
-        if env.storage().instance().has(&DataKey::Initialized) {
-            return Err(Error::AlreadyInitialized);
-        }
-
-        // Validate amount is non-negative
-        if immutables.amount < 0 {
-            return Err(Error::NegativeAmount);
-        }
-
-        // Store immutables and mark as initialized
-        env.storage().instance().set(&DataKey::Immutables, &immutables);
-        env.storage().instance().set(&DataKey::Initialized, &true);
-
-        Ok(())
-    }
-
-    /// Withdraw funds by providing the correct secret
-    /// Can only be called by the taker before cancellation timestamp
-    pub fn withdraw(env: Env, secret: BytesN<32>) -> Result<(), Error> {
-        let immutables = Self::get_immutables(&env)?;
-        
-        // Check authorization - only taker can withdraw
-        immutables.taker.require_auth();
-
-        // Check time predicate - must be before cancellation timestamp
-        let current_timestamp = env.ledger().timestamp();
-        if current_timestamp >= immutables.cancellation_timestamp {
-            return Err(Error::TimePredicateNotMet);
-        }
-
-        // Verify secret matches hashlock
-        let secret_hash = env.crypto().sha256(&secret.into());
-        if BytesN::from_array(&env, &secret_hash.into()) != immutables.hashlock {
-            return Err(Error::InvalidSecret);
-        }
-
-        // Transfer tokens to taker
-        Self::transfer_tokens(&env, &immutables.token, &immutables.taker, immutables.amount);
-
-        // Emit event
-        env.events().publish(("withdraw",), &immutables.taker);
-
-        Ok(())
-    }
-
-    /// Cancel the escrow and return funds to maker
-    /// Can only be called by the maker after cancellation timestamp
-    pub fn cancel(env: Env) -> Result<(), Error> {
-        let immutables = Self::get_immutables(&env)?;
-        
-        // Check authorization - only maker can cancel
-        immutables.maker.require_auth();
-
-        // Check time predicate - must be after cancellation timestamp
-        let current_timestamp = env.ledger().timestamp();
-        if current_timestamp < immutables.cancellation_timestamp {
-            return Err(Error::TimePredicateNotMet);
-        }
-
-        // Transfer tokens back to maker
-        Self::transfer_tokens(&env, &immutables.token, &immutables.maker, immutables.amount);
-
-        // Emit event
-        env.events().publish(("cancel",), &immutables.maker);
-
-        Ok(())
-    }
-
-    /// Get the immutable parameters of this escrow
-    pub fn get_immutables(env: &Env) -> Result<Immutables, Error> {
-        if !env.storage().instance().has(&DataKey::Initialized) {
-            return Err(Error::NotInitialized);
-        }
-        
-        let immutables: Immutables = env.storage().instance().get(&DataKey::Immutables).unwrap();
-        Ok(immutables)
-    }
-
-    /// Helper function to transfer tokens
-    fn transfer_tokens(env: &Env, token: &Address, to: &Address, amount: i128) {
-        let token_client = token::Client::new(env, token);
-        token_client.transfer(&env.current_contract_address(), to, &amount);
-    }
-}
-
+#![no_std]
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, token,
+    xdr::{FromXdr, ToXdr},
+    Address, Bytes, BytesN, Env, IntoVal, Symbol, Vec,
+};
+
+#[contracttype]
+pub enum DataKey {
+    Immutables,
+    Initialized,
+    CancelShortfall,
+    RemainingPrincipal,
+    CancellationDeadline, // Cancellation stage after partial-withdrawal extensions
+    PartialExtension,
+    State,
+    TimeTolerance, // Seconds of clock skew allowed past the cancellation deadline
+    CancelFee,
+    Reason, // Caller-supplied reason recorded by `withdraw` or `cancel`
+    SafetyDepositPaid, // bool; set once the safety deposit has been awarded
+    PublicWithdrawers, // Vec<Address> allowed to call `public_withdraw` (empty = anyone)
+    Withdrawn, // i128; principal paid out to the taker so far
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EscrowState {
+    Active,
+    Withdrawn,
+    Cancelled,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Eligibility {
+    pub can_withdraw_now: bool,
+    pub can_cancel_now: bool,
+    pub seconds_to_cancel: u64, // 0 once cancellation has opened
+    pub state: EscrowState,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SecretPolicy {
+    pub secret_length: u32, // Preimage length in bytes
+    pub hash_algo: Symbol,  // Hash applied to the preimage to match the hashlock
+    pub hashlock: BytesN<32>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalReceipt {
+    pub recipient: Address,
+    pub amount: i128,
+    pub revealed_secret: BytesN<32>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartialExtension {
+    pub extend_on_partial: u64,         // Seconds added to the deadline per partial withdrawal
+    pub max_cancellation_timestamp: u64, // Deadline never extends past this
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CancelFee {
+    pub cancel_fee_bps: u32, // Basis points of each cancellation refund sent to the treasury
+    pub treasury: Address,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Timelocks {
+    pub deployed_at: u64, // Stage offsets below are seconds after this timestamp
+    pub withdrawal: u64, // Finality lock ends; the taker can withdraw
+    pub public_withdrawal: u64, // Anyone with the secret can push funds to the taker
+    pub cancellation: u64, // Withdrawals close; the maker can cancel
+    pub public_cancellation: u64, // Anyone can cancel, refunding the maker
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Immutables {
+    pub hashlock: BytesN<32>,
+    pub maker: Address,
+    pub taker: Address,
+    pub token: Address,
+    pub amount: i128,
+    pub timelocks: Timelocks,
+    pub safety_deposit: i128, // Paid in deposit_token to whoever finalizes the escrow
+    pub deposit_token: Address,
+    pub hash_algorithm: u32, // Hash applied to secrets: 0 = sha256, 1 = keccak256 (EVM-compatible)
+    pub arbiter: Option<Address>, // When set, must also approve every release to the taker
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    InvalidSecret = 3,
+    NotAuthorized = 4,
+    TimePredicateNotMet = 5,
+    NegativeAmount = 6,
+    InvalidEncoding = 7,
+    InvalidAmount = 8,
+    SwapUnderperformed = 9,
+    InvalidTolerance = 10,
+    InvalidFee = 11,
+    PublicWindowNotOpen = 12,
+    RescueTooEarly = 13,
+    InvalidProof = 14,
+    UnsupportedHashAlgorithm = 15,
+    InvalidTimelocks = 16,
+    FullyWithdrawn = 17,
+    EscrowNotActive = 18,
+}
+
+// Largest clock-skew tolerance the parties can agree on, in seconds
+const MAX_TIME_TOLERANCE: u64 = 60;
+
+// Seconds after public cancellation opens before the maker can rescue tokens (7 days)
+const RESCUE_DELAY: u64 = 7 * 24 * 60 * 60;
+
+// Largest cancellation fee, in basis points
+const MAX_CANCEL_FEE_BPS: u32 = 500;
+const BPS_DENOMINATOR: i128 = 10_000;
+
+// Reasons callers can record when settling through `withdraw` or `cancel`
+pub const REASON_NORMAL: u32 = 0;
+pub const REASON_TIMEOUT: u32 = 1;
+pub const REASON_DISPUTE: u32 = 2;
+
+// Deepest Merkle proof accepted by `withdraw_partial` (up to 2^16 secrets)
+const MAX_PROOF_DEPTH: u32 = 16;
+
+// Withdrawals take a 32-byte secret whose hash (per `hash_algorithm`) must equal the hashlock
+const SECRET_LENGTH: u32 = 32;
+pub const HASH_ALGORITHM_SHA256: u32 = 0;
+pub const HASH_ALGORITHM_KECCAK256: u32 = 1;
+
+// Size of one word in the EVM ABI-compatible immutables encoding
+const ABI_WORD_SIZE: u32 = 32;
+// hashlock | maker | taker | token | amount | deployed_at | timelock offsets |
+// safety_deposit | deposit_token | hash_algorithm | arbiter | address kinds
+const ENCODED_IMMUTABLES_LEN: u32 = ABI_WORD_SIZE * 12;
+// XDR discriminants used to rebuild an Address from its 32-byte payload
+const SCV_ADDRESS: u8 = 18;
+const SC_ADDRESS_TYPE_ACCOUNT: u8 = 0;
+const SC_ADDRESS_TYPE_CONTRACT: u8 = 1;
+
+#[contract]
+pub struct SorobanEscrow;
+
+#[contractimpl]
+impl SorobanEscrow {
+    /// Initialize the escrow with immutable parameters
+    /// Can only be called once after deployment; `cancel_fee` is the protocol's cut of
+    /// cancellation refunds, supplied by the factory from its admin configuration
+    pub fn initialize(env: Env, immutables: Immutables, cancel_fee: Option<CancelFee>) -> Result<(), Error> {
+        // Check if already initialized
+        if env.storage().instance().has(&DataKey::Initialized) {
+            return Err(Error::AlreadyInitialized);
+        }
+
+        // Validate amount and safety deposit are non-negative
+        if immutables.amount < 0 || immutables.safety_deposit < 0 {
+            return Err(Error::NegativeAmount);
+        }
+
+        // Reject hash algorithms withdrawals couldn't check
+        Self::hash_algo_name(&immutables)?;
+
+        // Stages must open in order and stay within u64 timestamps
+        let timelocks = &immutables.timelocks;
+        if timelocks.withdrawal > timelocks.public_withdrawal
+            || timelocks.public_withdrawal > timelocks.cancellation
+            || timelocks.cancellation > timelocks.public_cancellation
+            || timelocks.deployed_at.checked_add(timelocks.public_cancellation).is_none()
+        {
+            return Err(Error::InvalidTimelocks);
+        }
+
+        if let Some(cancel_fee) = cancel_fee {
+            if cancel_fee.cancel_fee_bps > MAX_CANCEL_FEE_BPS {
+                return Err(Error::InvalidFee);
+            }
+            env.storage().instance().set(&DataKey::CancelFee, &cancel_fee);
+        }
+
+        // Store immutables and mark as initialized
+        env.storage().instance().set(&DataKey::Immutables, &immutables);
+        env.storage().instance().set(&DataKey::Initialized, &true);
+
+        Ok(())
+    }
+
+    /// Withdraw funds by providing the correct secret
+    /// Can only be called by the taker before cancellation timestamp
+    /// Returns the settlement details so callers don't have to parse the event
+    /// `reason` is recorded and emitted (0 = normal, 1 = timeout, 2 = dispute); defaults to normal
+    pub fn withdraw(env: Env, secret: BytesN<32>, reason: Option<u32>) -> Result<WithdrawalReceipt, Error> {
+        let immutables = Self::get_immutables(&env)?;
+        let reason = reason.unwrap_or(REASON_NORMAL);
+        
+        Self::verify_withdrawal(&env, &immutables, secret.clone())?;
+
+        // Transfer the remaining principal to taker
+        let amount = Self::remaining_principal(&env, &immutables);
+        Self::transfer_tokens(&env, &immutables.token, &immutables.taker, amount);
+        Self::record_withdrawal(&env, amount, amount);
+        env.storage().instance().set(&DataKey::State, &EscrowState::Withdrawn);
+        env.storage().instance().set(&DataKey::Reason, &reason);
+        Self::pay_safety_deposit(&env, &immutables, &immutables.taker);
+
+        // Emit event
+        env.events().publish(("withdraw",), (&immutables.taker, reason));
+
+        Ok(WithdrawalReceipt {
+            recipient: immutables.taker,
+            amount,
+            revealed_secret: secret,
+        })
+    }
+
+    /// Push the funds to the taker by providing the correct secret, callable by anyone
+    /// Opens at the public withdrawal stage and closes with the private withdrawal window,
+    /// so a revealed secret still settles if the taker goes offline; `caller` earns the safety deposit
+    pub fn public_withdraw(env: Env, secret: BytesN<32>, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+        let immutables = Self::get_immutables(&env)?;
+        Self::require_active(&env)?;
+        Self::require_arbiter(&immutables);
+
+        if env.ledger().timestamp() < Self::stage(&immutables, immutables.timelocks.public_withdrawal) {
+            return Err(Error::PublicWindowNotOpen);
+        }
+        let public_withdrawers = Self::get_public_withdrawers(env.clone());
+        if !public_withdrawers.is_empty() && !public_withdrawers.contains(&caller) {
+            return Err(Error::NotAuthorized);
+        }
+        if !Self::withdraw_window_open(&env, &immutables) {
+            return Err(Error::TimePredicateNotMet);
+        }
+        Self::check_secret(&env, &immutables, secret)?;
+
+        // Transfer the remaining principal to taker
+        let amount = Self::remaining_principal(&env, &immutables);
+        Self::transfer_tokens(&env, &immutables.token, &immutables.taker, amount);
+        Self::record_withdrawal(&env, amount, amount);
+        env.storage().instance().set(&DataKey::State, &EscrowState::Withdrawn);
+        Self::pay_safety_deposit(&env, &immutables, &caller);
+
+        // Emit event
+        env.events().publish(("public_withdraw",), (&immutables.taker, amount));
+
+        Ok(())
+    }
+
+    /// Withdraw by providing the correct secret and forward the funds to a settlement contract
+    /// Calls `settlement.on_escrow_settled(hashlock, amount, taker)` after the transfer;
+    /// if the callback fails the whole withdrawal reverts
+    pub fn withdraw_and_settle(env: Env, secret: BytesN<32>, settlement: Address) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+
+        Self::verify_withdrawal(&env, &immutables, secret)?;
+
+        // Transfer the remaining principal to the settlement contract
+        let amount = Self::remaining_principal(&env, &immutables);
+        Self::transfer_tokens(&env, &immutables.token, &settlement, amount);
+        Self::record_withdrawal(&env, amount, amount);
+        env.storage().instance().set(&DataKey::State, &EscrowState::Withdrawn);
+        Self::pay_safety_deposit(&env, &immutables, &immutables.taker);
+
+        // Notify the settlement contract
+        env.invoke_contract::<()>(
+            &settlement,
+            &Symbol::new(&env, "on_escrow_settled"),
+            (immutables.hashlock.clone(), amount, immutables.taker.clone()).into_val(&env),
+        );
+
+        // Emit event
+        env.events().publish(("withdraw_and_settle",), (&immutables.taker, &settlement));
+
+        Ok(())
+    }
+
+    /// Withdraw part of the principal by providing one of the escrow's secrets
+    /// With a non-empty `proof` the hashlock is the Merkle root of `2^proof.len()` secret hashes and
+    /// secret `index` unlocks cumulative withdrawals up to `amount * (index + 1) / 2^proof.len()`;
+    /// an empty proof checks the secret against the hashlock directly.
+    /// Pushes the cancellation deadline forward if a partial extension is configured
+    pub fn withdraw_partial(
+        env: Env,
+        secret: BytesN<32>,
+        proof: Vec<BytesN<32>>,
+        index: u32,
+        amount: i128,
+    ) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+        Self::require_active(&env)?;
+
+        // Check authorization - only taker can withdraw, with the arbiter's approval if set
+        immutables.taker.require_auth();
+        Self::require_arbiter(&immutables);
+
+        // Check time predicate - must be before cancellation timestamp (within tolerance)
+        if !Self::withdraw_window_open(&env, &immutables) {
+            return Err(Error::TimePredicateNotMet);
+        }
+
+        let parts = Self::check_secret_proof(&env, &immutables, secret, &proof, index)?;
+
+        // The secret's index caps how much can have been withdrawn in total
+        let remaining = Self::remaining_principal(&env, &immutables);
+        let unlocked = immutables
+            .amount
+            .checked_mul(index as i128 + 1)
+            .ok_or(Error::InvalidAmount)?
+            / parts;
+        let withdrawn = immutables.amount - remaining;
+        if amount <= 0 || amount > remaining || withdrawn + amount > unlocked {
+            return Err(Error::InvalidAmount);
+        }
+
+        Self::release_partial(&env, &immutables, remaining, amount);
+
+        // Emit event
+        env.events().publish(("withdraw_partial",), (&immutables.taker, amount));
+
+        Ok(())
+    }
+
+    /// Withdraw the principal in tranches with the escrow's secret (taker only)
+    /// Each call moves up to `amount`, capped at the principal left, like a single-secret
+    /// `withdraw_partial`; the draw that empties the escrow settles it, after which calls
+    /// return `FullyWithdrawn`
+    pub fn withdraw_amount(env: Env, secret: BytesN<32>, amount: i128) -> Result<i128, Error> {
+        let immutables = Self::get_immutables(&env)?;
+
+        // An emptied escrow reports `FullyWithdrawn` rather than `EscrowNotActive`
+        let state: EscrowState = env
+            .storage()
+            .instance()
+            .get(&DataKey::State)
+            .unwrap_or(EscrowState::Active);
+        if state == EscrowState::Withdrawn {
+            return Err(Error::FullyWithdrawn);
+        }
+
+        Self::verify_withdrawal(&env, &immutables, secret)?;
+
+        let remaining = Self::remaining_principal(&env, &immutables);
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        let drawn = amount.min(remaining);
+        Self::release_partial(&env, &immutables, remaining, drawn);
+
+        // Emit event
+        env.events().publish(("withdraw_amount",), (&immutables.taker, drawn));
+
+        Ok(drawn)
+    }
+
+    /// Get the principal withdrawn to the taker so far
+    pub fn get_withdrawn(env: Env) -> Result<i128, Error> {
+        Self::get_immutables(&env)?;
+        Ok(env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0))
+    }
+
+    /// Configure how far each partial withdrawal extends the cancellation deadline (maker only)
+    /// Extensions are capped at `max_cancellation_timestamp`
+    pub fn set_partial_extension(
+        env: Env,
+        extend_on_partial: u64,
+        max_cancellation_timestamp: u64,
+    ) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+
+        immutables.maker.require_auth();
+
+        env.storage().instance().set(
+            &DataKey::PartialExtension,
+            &PartialExtension {
+                extend_on_partial,
+                max_cancellation_timestamp,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Get the current cancellation deadline, including partial-withdrawal extensions
+    pub fn get_cancellation_timestamp(env: Env) -> Result<u64, Error> {
+        let immutables = Self::get_immutables(&env)?;
+        Ok(Self::cancellation_deadline(&env, &immutables))
+    }
+
+    /// Get the escrow's stage offsets and the `deployed_at` they are relative to
+    pub fn get_timelocks(env: Env) -> Result<Timelocks, Error> {
+        Ok(Self::get_immutables(&env)?.timelocks)
+    }
+
+    /// Set the clock-skew tolerance applied at the cancellation deadline (maker and taker)
+    /// Withdrawals stay open until `deadline + tolerance` and cancellation opens at that same
+    /// moment, so the windows never overlap; only the withdrawal window widens, cancellation is
+    /// delayed. Capped at `MAX_TIME_TOLERANCE` seconds
+    pub fn set_time_tolerance(env: Env, tolerance: u64) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+
+        // Both sides must agree since the grace delays cancellation
+        immutables.maker.require_auth();
+        immutables.taker.require_auth();
+
+        if tolerance > MAX_TIME_TOLERANCE {
+            return Err(Error::InvalidTolerance);
+        }
+
+        env.storage().instance().set(&DataKey::TimeTolerance, &tolerance);
+
+        Ok(())
+    }
+
+    /// Get the clock-skew tolerance in seconds (0 if unset)
+    pub fn get_time_tolerance(env: Env) -> u64 {
+        Self::time_tolerance(&env)
+    }
+
+    /// Restrict `public_withdraw` to `withdrawers` (maker and taker); an empty list opens it to anyone
+    pub fn set_public_withdrawers(env: Env, withdrawers: Vec<Address>) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+
+        // Both sides must agree on who may finalize the escrow
+        immutables.maker.require_auth();
+        immutables.taker.require_auth();
+
+        if withdrawers.is_empty() {
+            env.storage().instance().remove(&DataKey::PublicWithdrawers);
+        } else {
+            env.storage().instance().set(&DataKey::PublicWithdrawers, &withdrawers);
+        }
+
+        Ok(())
+    }
+
+    /// Get the addresses allowed to call `public_withdraw` (empty = anyone)
+    pub fn get_public_withdrawers(env: Env) -> Vec<Address> {
+        env.storage()
+            .instance()
+            .get(&DataKey::PublicWithdrawers)
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Get the cancellation fee configuration, if any
+    pub fn get_cancel_fee(env: Env) -> Option<CancelFee> {
+        env.storage().instance().get(&DataKey::CancelFee)
+    }
+
+    /// Get the safety deposit awarded to whoever finalizes the escrow
+    pub fn get_safety_deposit(env: Env) -> Result<i128, Error> {
+        Ok(Self::get_immutables(&env)?.safety_deposit)
+    }
+
+    /// Get the token the safety deposit is paid in
+    pub fn get_deposit_token(env: Env) -> Result<Address, Error> {
+        Ok(Self::get_immutables(&env)?.deposit_token)
+    }
+
+    /// Check whether the safety deposit has been awarded
+    pub fn is_safety_deposit_paid(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::SafetyDepositPaid)
+            .unwrap_or(false)
+    }
+
+    /// Get the reason recorded by `withdraw` or `cancel`, if either has run
+    pub fn get_reason(env: Env) -> Option<u32> {
+        env.storage().instance().get(&DataKey::Reason)
+    }
+
+    /// Get the principal not yet withdrawn (0 once the escrow is cancelled)
+    pub fn get_remaining_principal(env: Env) -> Result<i128, Error> {
+        let immutables = Self::get_immutables(&env)?;
+        Ok(Self::remaining_principal(&env, &immutables))
+    }
+
+    /// Cancel the escrow and return funds to maker
+    /// Can only be called by the maker after cancellation timestamp
+    /// Refunds whatever was actually funded (up to `amount`) and records any shortfall
+    /// `reason` is recorded and emitted (0 = normal, 1 = timeout, 2 = dispute); defaults to normal
+    pub fn cancel(env: Env, reason: Option<u32>) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+        let reason = reason.unwrap_or(REASON_NORMAL);
+        let refund_amount = Self::prepare_cancel(&env, &immutables)?;
+        env.storage().instance().set(&DataKey::Reason, &reason);
+        let refund_amount = Self::pay_cancel_fee(&env, &immutables, refund_amount);
+
+        if refund_amount > 0 {
+            Self::transfer_tokens(&env, &immutables.token, &immutables.maker, refund_amount);
+        }
+        Self::pay_safety_deposit(&env, &immutables, &immutables.maker);
+
+        // Emit event
+        env.events().publish(("cancel",), (&immutables.maker, refund_amount, reason));
+
+        Ok(())
+    }
+
+    /// Cancel the escrow on the maker's behalf, callable by anyone
+    /// Opens at the public cancellation stage (never before the cancellation deadline), so funds
+    /// don't stay locked if the maker goes offline; `caller` earns the safety deposit
+    pub fn public_cancel(env: Env, caller: Address) -> Result<(), Error> {
+        caller.require_auth();
+        let immutables = Self::get_immutables(&env)?;
+
+        let opens_at = Self::stage(&immutables, immutables.timelocks.public_cancellation)
+            .max(Self::cancel_opens_at(&env, &immutables));
+        if env.ledger().timestamp() < opens_at {
+            return Err(Error::PublicWindowNotOpen);
+        }
+
+        let refund_amount = Self::record_cancel(&env, &immutables)?;
+        let refund_amount = Self::pay_cancel_fee(&env, &immutables, refund_amount);
+        if refund_amount > 0 {
+            Self::transfer_tokens(&env, &immutables.token, &immutables.maker, refund_amount);
+        }
+        Self::pay_safety_deposit(&env, &immutables, &caller);
+
+        // Emit event
+        env.events().publish(("public_cancel",), (&immutables.maker, refund_amount));
+
+        Ok(())
+    }
+
+    /// Cancel what is left of a partially withdrawn escrow and return it to the maker
+    /// Same as `cancel` with the normal reason: only the unwithdrawn principal is refunded,
+    /// leaving earlier partial withdrawals settled
+    pub fn cancel_remaining(env: Env) -> Result<(), Error> {
+        Self::cancel(env, None)
+    }
+
+    /// Cancel the escrow and refund the maker in `out_token` by swapping through `router`
+    /// The router receives the principal and must pay at least `min_out` of `out_token`
+    /// to the maker via `swap(token_in, token_out, amount_in, to)`; otherwise the cancel reverts
+    pub fn cancel_to_token(
+        env: Env,
+        out_token: Address,
+        router: Address,
+        min_out: i128,
+    ) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+        let refund_amount = Self::prepare_cancel(&env, &immutables)?;
+        let refund_amount = Self::pay_cancel_fee(&env, &immutables, refund_amount);
+
+        // Hand the principal to the router and let it pay the maker
+        let out_client = token::Client::new(&env, &out_token);
+        let balance_before = out_client.balance(&immutables.maker);
+        if refund_amount > 0 {
+            Self::transfer_tokens(&env, &immutables.token, &router, refund_amount);
+            env.invoke_contract::<i128>(
+                &router,
+                &Symbol::new(&env, "swap"),
+                (
+                    immutables.token.clone(),
+                    out_token.clone(),
+                    refund_amount,
+                    immutables.maker.clone(),
+                )
+                    .into_val(&env),
+            );
+        }
+
+        // Enforce the minimum on what the maker actually received
+        let amount_out = out_client.balance(&immutables.maker) - balance_before;
+        if amount_out < min_out {
+            return Err(Error::SwapUnderperformed);
+        }
+        Self::pay_safety_deposit(&env, &immutables, &immutables.maker);
+
+        // Emit event
+        env.events().publish(
+            ("cancel_to_token",),
+            (&immutables.maker, &out_token, amount_out),
+        );
+
+        Ok(())
+    }
+
+    /// Recover `amount` of any `token` held by the escrow, such as tokens sent by mistake (maker only)
+    /// Opens `RESCUE_DELAY` (7 days) after public cancellation opens; the unwithdrawn principal and
+    /// an unpaid safety deposit can't be rescued and must still settle through withdraw or cancel
+    pub fn rescue(env: Env, token: Address, amount: i128) -> Result<(), Error> {
+        let immutables = Self::get_immutables(&env)?;
+
+        // Check authorization - only maker can rescue
+        immutables.maker.require_auth();
+
+        let public_cancel_opens = Self::stage(&immutables, immutables.timelocks.public_cancellation)
+            .max(Self::cancel_opens_at(&env, &immutables));
+        if env.ledger().timestamp() < public_cancel_opens.saturating_add(RESCUE_DELAY) {
+            return Err(Error::RescueTooEarly);
+        }
+
+        // Only the balance beyond what the escrow still owes is stray
+        let state: EscrowState = env
+            .storage()
+            .instance()
+            .get(&DataKey::State)
+            .unwrap_or(EscrowState::Active);
+        let mut reserved = 0;
+        if token == immutables.token && state == EscrowState::Active {
+            reserved += Self::remaining_principal(&env, &immutables);
+        }
+        if token == immutables.deposit_token && !Self::is_safety_deposit_paid(env.clone()) {
+            reserved += immutables.safety_deposit;
+        }
+        let balance = token::Client::new(&env, &token).balance(&env.current_contract_address());
+        if amount <= 0 || amount > balance - reserved {
+            return Err(Error::InvalidAmount);
+        }
+
+        Self::transfer_tokens(&env, &token, &immutables.maker, amount);
+
+        // Emit event
+        env.events().publish(("rescue",), (&token, amount));
+
+        Ok(())
+    }
+
+    /// Get the immutable parameters of this escrow
+    pub fn get_immutables(env: &Env) -> Result<Immutables, Error> {
+        if !env.storage().instance().has(&DataKey::Initialized) {
+            return Err(Error::NotInitialized);
+        }
+        
+        let immutables: Immutables = env.storage().instance().get(&DataKey::Immutables).unwrap();
+        Ok(immutables)
+    }
+
+    /// Get the preimage requirements withdrawals enforce, for auditing
+    pub fn get_secret_policy(env: Env) -> Result<SecretPolicy, Error> {
+        let immutables = Self::get_immutables(&env)?;
+        Ok(SecretPolicy {
+            secret_length: SECRET_LENGTH,
+            hash_algo: Symbol::new(&env, Self::hash_algo_name(&immutables)?),
+            hashlock: immutables.hashlock,
+        })
+    }
+
+    /// Get what can be done with the escrow right now, for driving UI state
+    pub fn get_eligibility(env: Env) -> Result<Eligibility, Error> {
+        let immutables = Self::get_immutables(&env)?;
+        let state: EscrowState = env
+            .storage()
+            .instance()
+            .get(&DataKey::State)
+            .unwrap_or(EscrowState::Active);
+
+        let current_timestamp = env.ledger().timestamp();
+        let cancel_opens_at = Self::cancel_opens_at(&env, &immutables);
+        let is_active = state == EscrowState::Active;
+
+        Ok(Eligibility {
+            can_withdraw_now: is_active && Self::withdraw_window_open(&env, &immutables),
+            can_cancel_now: is_active && Self::cancel_window_open(&env, &immutables),
+            seconds_to_cancel: cancel_opens_at.saturating_sub(current_timestamp),
+            state,
+        })
+    }
+
+    /// Get the escrow's balance of each candidate token
+    /// Tokens can't be enumerated on-chain, so the caller supplies the candidates
+    pub fn held_tokens(env: Env, candidates: Vec<Address>) -> Vec<(Address, i128)> {
+        let mut balances = Vec::new(&env);
+        for token in candidates.iter() {
+            let balance = token::Client::new(&env, &token).balance(&env.current_contract_address());
+            balances.push_back((token, balance));
+        }
+        balances
+    }
+
+    /// Get the unfunded portion of `amount` recorded at cancellation (0 if fully funded or not cancelled)
+    pub fn get_cancel_shortfall(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::CancelShortfall)
+            .unwrap_or(0)
+    }
+
+    /// Serialize immutables into a fixed layout mirroring the EVM escrow ABI field ordering
+    /// Twelve 32-byte big-endian words, in declaration order:
+    /// hashlock | maker | taker | token | amount (int256) | deployed_at (uint256) |
+    /// timelock offsets (withdrawal | public_withdrawal | cancellation | public_cancellation, uint64 each) |
+    /// safety_deposit (int256) | deposit_token | hash_algorithm (uint256) | arbiter | address kinds
+    /// Addresses are written as their 32-byte account key or contract hash; the last word flags
+    /// which of them are contracts (bit 0 maker, bit 1 taker, bit 2 token, bit 3 deposit_token,
+    /// bit 4 arbiter) and whether there is an arbiter (bit 5; its word is zero otherwise)
+    pub fn serialize_immutables(env: Env, immutables: Immutables) -> Bytes {
+        let mut data = Bytes::new(&env);
+        let mut address_kinds: u8 = 0;
+
+        data.append(&immutables.hashlock.into());
+        for (i, address) in [&immutables.maker, &immutables.taker, &immutables.token].iter().enumerate() {
+            let (payload, is_contract) = Self::encode_address(&env, address);
+            data.append(&payload.into());
+            if is_contract {
+                address_kinds |= 1 << i;
+            }
+        }
+
+        // amount as a sign-extended int256
+        let sign_byte = if immutables.amount < 0 { 0xff } else { 0 };
+        data.extend_from_array(&[sign_byte; 16]);
+        data.extend_from_array(&immutables.amount.to_be_bytes());
+
+        // deployed_at as a uint256
+        let timelocks = &immutables.timelocks;
+        data.extend_from_array(&[0; 24]);
+        data.extend_from_array(&timelocks.deployed_at.to_be_bytes());
+
+        // Stage offsets packed into one word, earliest stage first
+        for offset in [
+            timelocks.withdrawal,
+            timelocks.public_withdrawal,
+            timelocks.cancellation,
+            timelocks.public_cancellation,
+        ] {
+            data.extend_from_array(&offset.to_be_bytes());
+        }
+
+        // safety_deposit as a sign-extended int256
+        let sign_byte = if immutables.safety_deposit < 0 { 0xff } else { 0 };
+        data.extend_from_array(&[sign_byte; 16]);
+        data.extend_from_array(&immutables.safety_deposit.to_be_bytes());
+
+        let (payload, is_contract) = Self::encode_address(&env, &immutables.deposit_token);
+        data.append(&payload.into());
+        if is_contract {
+            address_kinds |= 1 << 3;
+        }
+
+        // hash_algorithm as a uint256
+        data.extend_from_array(&[0; 28]);
+        data.extend_from_array(&immutables.hash_algorithm.to_be_bytes());
+
+        match &immutables.arbiter {
+            Some(arbiter) => {
+                let (payload, is_contract) = Self::encode_address(&env, arbiter);
+                data.append(&payload.into());
+                address_kinds |= 1 << 5;
+                if is_contract {
+                    address_kinds |= 1 << 4;
+                }
+            }
+            None => data.extend_from_array(&[0; 32]),
+        }
+
+        // address kinds as a uint256
+        data.extend_from_array(&[0; 31]);
+        data.push_back(address_kinds);
+
+        data
+    }
+
+    /// Deserialize immutables produced by `serialize_immutables`
+    pub fn deserialize_immutables(env: Env, data: Bytes) -> Result<Immutables, Error> {
+        if data.len() != ENCODED_IMMUTABLES_LEN {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let mut words = [[0u8; 32]; 12];
+        for (i, word) in words.iter_mut().enumerate() {
+            let start = i as u32 * ABI_WORD_SIZE;
+            data.slice(start..start + ABI_WORD_SIZE).copy_into_slice(word);
+        }
+
+        // Reject padding that doesn't match the fixed layout
+        let sign_byte = if words[4][16] & 0x80 != 0 { 0xff } else { 0 };
+        let deposit_sign_byte = if words[7][16] & 0x80 != 0 { 0xff } else { 0 };
+        if words[4][..16].iter().any(|b| *b != sign_byte)
+            || words[5][..24].iter().any(|b| *b != 0)
+            || words[7][..16].iter().any(|b| *b != deposit_sign_byte)
+            || words[9][..28].iter().any(|b| *b != 0)
+            || words[11][..31].iter().any(|b| *b != 0)
+            || words[11][31] > 0b11_1111
+        {
+            return Err(Error::InvalidEncoding);
+        }
+
+        let address_kinds = words[11][31];
+        let arbiter = if address_kinds & (1 << 5) != 0 {
+            Some(Self::decode_address(&env, &words[10], address_kinds & (1 << 4) != 0)?)
+        } else if address_kinds & (1 << 4) != 0 || words[10].iter().any(|b| *b != 0) {
+            return Err(Error::InvalidEncoding);
+        } else {
+            None
+        };
+        let mut amount_bytes = [0u8; 16];
+        amount_bytes.copy_from_slice(&words[4][16..]);
+        let mut offsets = [0u64; 5];
+        for (i, offset) in offsets.iter_mut().enumerate() {
+            // deployed_at is the low 8 bytes of word 5, the stage offsets fill word 6
+            let start = if i == 0 { 24 } else { (i - 1) * 8 };
+            let word = if i == 0 { &words[5] } else { &words[6] };
+            let mut offset_bytes = [0u8; 8];
+            offset_bytes.copy_from_slice(&word[start..start + 8]);
+            *offset = u64::from_be_bytes(offset_bytes);
+        }
+        let mut deposit_bytes = [0u8; 16];
+        deposit_bytes.copy_from_slice(&words[7][16..]);
+        let mut hash_algorithm_bytes = [0u8; 4];
+        hash_algorithm_bytes.copy_from_slice(&words[9][28..]);
+
+        Ok(Immutables {
+            hashlock: BytesN::from_array(&env, &words[0]),
+            maker: Self::decode_address(&env, &words[1], address_kinds & 1 != 0)?,
+            taker: Self::decode_address(&env, &words[2], address_kinds & (1 << 1) != 0)?,
+            token: Self::decode_address(&env, &words[3], address_kinds & (1 << 2) != 0)?,
+            amount: i128::from_be_bytes(amount_bytes),
+            timelocks: Timelocks {
+                deployed_at: offsets[0],
+                withdrawal: offsets[1],
+                public_withdrawal: offsets[2],
+                cancellation: offsets[3],
+                public_cancellation: offsets[4],
+            },
+            safety_deposit: i128::from_be_bytes(deposit_bytes),
+            deposit_token: Self::decode_address(&env, &words[8], address_kinds & (1 << 3) != 0)?,
+            hash_algorithm: u32::from_be_bytes(hash_algorithm_bytes),
+            arbiter,
+        })
+    }
+
+    /// Get the symbolic name of an error code (e.g. 18 -> "EscrowNotActive")
+    /// Returns "Unknown" for codes not defined by this contract
+    pub fn error_name(env: Env, code: u32) -> Symbol {
+        let name = match code {
+            1 => "AlreadyInitialized",
+            2 => "NotInitialized",
+            3 => "InvalidSecret",
+            4 => "NotAuthorized",
+            5 => "TimePredicateNotMet",
+            6 => "NegativeAmount",
+            7 => "InvalidEncoding",
+            8 => "InvalidAmount",
+            9 => "SwapUnderperformed",
+            10 => "InvalidTolerance",
+            11 => "InvalidFee",
+            12 => "PublicWindowNotOpen",
+            13 => "RescueTooEarly",
+            14 => "InvalidProof",
+            15 => "UnsupportedHashAlgorithm",
+            16 => "InvalidTimelocks",
+            17 => "FullyWithdrawn",
+            18 => "EscrowNotActive",
+            _ => "Unknown",
+        };
+        Symbol::new(&env, name)
+    }
+
+    /// Helper function to reject an escrow that has already been withdrawn or cancelled
+    fn require_active(env: &Env) -> Result<(), Error> {
+        let state: EscrowState = env
+            .storage()
+            .instance()
+            .get(&DataKey::State)
+            .unwrap_or(EscrowState::Active);
+        if state != EscrowState::Active {
+            return Err(Error::EscrowNotActive);
+        }
+        Ok(())
+    }
+
+    /// Helper function to check the escrow is active, the taker's authorization, the time predicate and the secret
+    fn verify_withdrawal(env: &Env, immutables: &Immutables, secret: BytesN<32>) -> Result<(), Error> {
+        Self::require_active(env)?;
+
+        // Check authorization - only taker can withdraw, with the arbiter's approval if set
+        immutables.taker.require_auth();
+        Self::require_arbiter(immutables);
+
+        // Check time predicate - must be before cancellation timestamp (within tolerance)
+        if !Self::withdraw_window_open(env, immutables) {
+            return Err(Error::TimePredicateNotMet);
+        }
+
+        Self::check_secret(env, immutables, secret)
+    }
+
+    /// Helper function to require the arbiter's approval, if the escrow has one
+    fn require_arbiter(immutables: &Immutables) {
+        if let Some(arbiter) = &immutables.arbiter {
+            arbiter.require_auth();
+        }
+    }
+
+    /// Helper function to verify a secret is leaf `index` of the Merkle tree rooted at the hashlock
+    /// Returns the number of leaves; an empty proof is a single-leaf tree whose root is the hashlock
+    fn check_secret_proof(
+        env: &Env,
+        immutables: &Immutables,
+        secret: BytesN<32>,
+        proof: &Vec<BytesN<32>>,
+        index: u32,
+    ) -> Result<i128, Error> {
+        if proof.is_empty() {
+            if index != 0 {
+                return Err(Error::InvalidProof);
+            }
+            Self::check_secret(env, immutables, secret)?;
+            return Ok(1);
+        }
+
+        if proof.len() > MAX_PROOF_DEPTH || (index as u64) >> proof.len() != 0 {
+            return Err(Error::InvalidProof);
+        }
+
+        // Hash up the tree; the index bit at each level says which side the node is on
+        let mut node = Self::hash_secret(env, immutables, &secret.into())?;
+        for (level, sibling) in proof.iter().enumerate() {
+            let mut data = Bytes::new(env);
+            if (index >> level) & 1 == 0 {
+                data.append(&node.into());
+                data.append(&sibling.into());
+            } else {
+                data.append(&sibling.into());
+                data.append(&node.into());
+            }
+            node = Self::hash_secret(env, immutables, &data)?;
+        }
+
+        if node != immutables.hashlock {
+            return Err(Error::InvalidProof);
+        }
+
+        Ok(1i128 << proof.len())
+    }
+
+    /// Helper function to verify the secret matches the hashlock
+    fn check_secret(env: &Env, immutables: &Immutables, secret: BytesN<32>) -> Result<(), Error> {
+        if Self::hash_secret(env, immutables, &secret.into())? != immutables.hashlock {
+            return Err(Error::InvalidSecret);
+        }
+
+        Ok(())
+    }
+
+    /// Helper function to hash data with the escrow's `hash_algorithm`
+    fn hash_secret(env: &Env, immutables: &Immutables, data: &Bytes) -> Result<BytesN<32>, Error> {
+        match immutables.hash_algorithm {
+            HASH_ALGORITHM_SHA256 => Ok(env.crypto().sha256(data).into()),
+            HASH_ALGORITHM_KECCAK256 => Ok(env.crypto().keccak256(data).into()),
+            _ => Err(Error::UnsupportedHashAlgorithm),
+        }
+    }
+
+    /// Helper function to name the escrow's `hash_algorithm`
+    fn hash_algo_name(immutables: &Immutables) -> Result<&'static str, Error> {
+        match immutables.hash_algorithm {
+            HASH_ALGORITHM_SHA256 => Ok("sha256"),
+            HASH_ALGORITHM_KECCAK256 => Ok("keccak256"),
+            _ => Err(Error::UnsupportedHashAlgorithm),
+        }
+    }
+
+    /// Helper function to check a cancellation and record any funding shortfall
+    /// Returns the amount to refund: what the escrow holds, up to the unwithdrawn principal
+    fn prepare_cancel(env: &Env, immutables: &Immutables) -> Result<i128, Error> {
+        // Check authorization - only maker can cancel
+        immutables.maker.require_auth();
+
+        // Check time predicate - must be after cancellation timestamp (within tolerance)
+        if !Self::cancel_window_open(env, immutables) {
+            return Err(Error::TimePredicateNotMet);
+        }
+
+        Self::record_cancel(env, immutables)
+    }
+
+    /// Helper function to mark the escrow cancelled and record any funding shortfall
+    /// Returns the amount to refund: what the escrow holds, up to the unwithdrawn principal;
+    /// errors once the escrow has been withdrawn or cancelled
+    fn record_cancel(env: &Env, immutables: &Immutables) -> Result<i128, Error> {
+        Self::require_active(env)?;
+
+        let remaining = Self::remaining_principal(env, immutables);
+        let balance = token::Client::new(env, &immutables.token).balance(&env.current_contract_address());
+        let refund_amount = remaining.min(balance);
+
+        // Record how much of the agreed amount was never funded
+        let shortfall = remaining - refund_amount;
+        env.storage().instance().set(&DataKey::CancelShortfall, &shortfall);
+        env.storage().instance().set(&DataKey::State, &EscrowState::Cancelled);
+
+        // Nothing is owed to the taker once the escrow is cancelled
+        env.storage().instance().set(&DataKey::RemainingPrincipal, &0i128);
+
+        Ok(refund_amount)
+    }
+
+    /// Helper function to send the cancellation fee on `refund_amount` to the treasury
+    /// Returns what is left for the maker
+    fn pay_cancel_fee(env: &Env, immutables: &Immutables, refund_amount: i128) -> i128 {
+        let cancel_fee: Option<CancelFee> = env.storage().instance().get(&DataKey::CancelFee);
+        let Some(cancel_fee) = cancel_fee else {
+            return refund_amount;
+        };
+
+        let fee = refund_amount * cancel_fee.cancel_fee_bps as i128 / BPS_DENOMINATOR;
+        if fee > 0 {
+            Self::transfer_tokens(env, &immutables.token, &cancel_fee.treasury, fee);
+            env.events()
+                .publish(("cancel_fee",), (&cancel_fee.treasury, fee));
+        }
+        refund_amount - fee
+    }
+
+    /// Helper function to send `amount` of the `remaining` principal to the taker
+    /// Settles the escrow once it is empty and otherwise applies any partial extension
+    fn release_partial(env: &Env, immutables: &Immutables, remaining: i128, amount: i128) {
+        Self::transfer_tokens(env, &immutables.token, &immutables.taker, amount);
+        Self::record_withdrawal(env, remaining, amount);
+        if remaining == amount {
+            env.storage().instance().set(&DataKey::State, &EscrowState::Withdrawn);
+            Self::pay_safety_deposit(env, immutables, &immutables.taker);
+            return;
+        }
+
+        // Give the taker time to complete the remaining withdrawals
+        if let Some(extension) = env
+            .storage()
+            .instance()
+            .get::<_, PartialExtension>(&DataKey::PartialExtension)
+        {
+            let deadline = Self::cancellation_deadline(env, immutables);
+            let extended = deadline
+                .saturating_add(extension.extend_on_partial)
+                .min(extension.max_cancellation_timestamp)
+                .max(deadline);
+            env.storage().instance().set(&DataKey::CancellationDeadline, &extended);
+        }
+    }
+
+    /// Helper function to award the safety deposit to whoever finalized the escrow, at most once
+    /// Pays what the escrow holds of the deposit token, up to `safety_deposit`
+    fn pay_safety_deposit(env: &Env, immutables: &Immutables, recipient: &Address) {
+        if immutables.safety_deposit <= 0 || Self::is_safety_deposit_paid(env.clone()) {
+            return;
+        }
+
+        let balance = token::Client::new(env, &immutables.deposit_token)
+            .balance(&env.current_contract_address());
+        let amount = immutables.safety_deposit.min(balance);
+        env.storage().instance().set(&DataKey::SafetyDepositPaid, &true);
+        if amount > 0 {
+            Self::transfer_tokens(env, &immutables.deposit_token, recipient, amount);
+            env.events()
+                .publish(("safety_deposit_paid",), (recipient, amount));
+        }
+    }
+
+    /// Helper function to move `amount` of the `remaining` principal to the withdrawn total
+    fn record_withdrawal(env: &Env, remaining: i128, amount: i128) {
+        let withdrawn: i128 = env.storage().instance().get(&DataKey::Withdrawn).unwrap_or(0);
+        env.storage().instance().set(&DataKey::Withdrawn, &(withdrawn + amount));
+        env.storage()
+            .instance()
+            .set(&DataKey::RemainingPrincipal, &(remaining - amount));
+    }
+
+    /// Helper function to read the principal not yet withdrawn
+    fn remaining_principal(env: &Env, immutables: &Immutables) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::RemainingPrincipal)
+            .unwrap_or(immutables.amount)
+    }
+
+    /// Helper function to read the cancellation deadline, including extensions
+    fn cancellation_deadline(env: &Env, immutables: &Immutables) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::CancellationDeadline)
+            .unwrap_or(Self::stage(immutables, immutables.timelocks.cancellation))
+    }
+
+    /// Helper function to turn a stage offset into a timestamp
+    fn stage(immutables: &Immutables, offset: u64) -> u64 {
+        immutables.timelocks.deployed_at.saturating_add(offset)
+    }
+
+    /// Helper function to check the withdraw predicate
+    /// Opens when the finality lock ends and closes when cancellation opens
+    fn withdraw_window_open(env: &Env, immutables: &Immutables) -> bool {
+        let now = env.ledger().timestamp();
+        now >= Self::stage(immutables, immutables.timelocks.withdrawal)
+            && now < Self::cancel_opens_at(env, immutables)
+    }
+
+    /// Helper function to check the cancel predicate
+    fn cancel_window_open(env: &Env, immutables: &Immutables) -> bool {
+        env.ledger().timestamp() >= Self::cancel_opens_at(env, immutables)
+    }
+
+    /// Helper function to get when cancellation opens: the deadline plus the time tolerance
+    fn cancel_opens_at(env: &Env, immutables: &Immutables) -> u64 {
+        Self::cancellation_deadline(env, immutables).saturating_add(Self::time_tolerance(env))
+    }
+
+    /// Helper function to read the clock-skew tolerance (0 if unset)
+    fn time_tolerance(env: &Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TimeTolerance)
+            .unwrap_or(0)
+    }
+
+    /// Helper function to split an address into its 32-byte payload and whether it is a contract
+    fn encode_address(env: &Env, address: &Address) -> (BytesN<32>, bool) {
+        let xdr = address.clone().to_xdr(env);
+        let is_contract = xdr.get(7) == Some(SC_ADDRESS_TYPE_CONTRACT);
+        let payload: BytesN<32> = xdr.slice(xdr.len() - ABI_WORD_SIZE..).try_into().unwrap();
+        (payload, is_contract)
+    }
+
+    /// Helper function to rebuild an address from its 32-byte payload
+    fn decode_address(env: &Env, payload: &[u8; 32], is_contract: bool) -> Result<Address, Error> {
+        let mut xdr = Bytes::from_array(env, &[0, 0, 0, SCV_ADDRESS, 0, 0, 0]);
+        if is_contract {
+            xdr.push_back(SC_ADDRESS_TYPE_CONTRACT);
+        } else {
+            // Account ids are ed25519 public keys (PUBLIC_KEY_TYPE_ED25519 = 0)
+            xdr.push_back(SC_ADDRESS_TYPE_ACCOUNT);
+            xdr.extend_from_array(&[0; 4]);
+        }
+        xdr.extend_from_array(payload);
+        Address::from_xdr(env, &xdr).map_err(|_| Error::InvalidEncoding)
+    }
+
+    /// Helper function to transfer tokens
+    fn transfer_tokens(env: &Env, token: &Address, to: &Address, amount: i128) {
+        let token_client = token::Client::new(env, token);
+        token_client.transfer(&env.current_contract_address(), to, &amount);
+    }
+}
+
 mod test;
\ No newline at end of file
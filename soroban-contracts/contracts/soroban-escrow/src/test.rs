@@ -1,248 +1,1444 @@
-#![cfg(test)]
-extern crate std;
-
-use super::*;
-use soroban_sdk::{
-    testutils::{Address as _, Ledger},
-    token, Address, Env,
-};
-
-fn create_token_contract<'a>(
-    e: &Env,
-    admin: &Address,
-) -> (token::Client<'a>, token::StellarAssetClient<'a>) {
-    let sac = e.register_stellar_asset_contract_v2(admin.clone());
-    (
-        token::Client::new(e, &sac.address()),
-        token::StellarAssetClient::new(e, &sac.address()),
-    )
-}
-
-fn create_escrow_contract(e: &Env) -> SorobanEscrowClient {
-    SorobanEscrowClient::new(e, &e.register(SorobanEscrow, ()))
-}
-
-#[test]
-fn test_initialize() {
-    let env = Env::default();
-    let escrow = create_escrow_contract(&env);
-    
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, _) = create_token_contract(&env, &token_admin);
-    
-    let immutables = Immutables {
-        hashlock: BytesN::from_array(&env, &[1; 32]),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: 1000,
-        cancellation_timestamp: 12345,
-    };
-
-    // Should initialize successfully
-    assert_eq!(escrow.initialize(&immutables), ());
-    
-    // Should fail to initialize again
-    assert_eq!(escrow.try_initialize(&immutables), Err(Ok(Error::AlreadyInitialized)));
-}
-
-#[test]
-fn test_withdraw_success() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set ledger timestamp before cancellation
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
-
-    let escrow = create_escrow_contract(&env);
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
-    
-    // Create secret and its hash
-    let secret = BytesN::from_array(&env, &[42; 32]);
-    let secret_hash = env.crypto().sha256(&secret.clone().into());
-    
-    let immutables = Immutables {
-        hashlock: secret_hash.into(),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: 1000,
-        cancellation_timestamp: 12345,
-    };
-
-    // Initialize escrow
-    escrow.initialize(&immutables);
-    
-    // Fund the escrow contract
-    token_admin_client.mint(&escrow.address, &1000);
-    
-    // Withdraw should succeed
-    assert_eq!(escrow.withdraw(&secret), ());
-    
-    // Check token balance
-    assert_eq!(token.balance(&taker), 1000);
-    assert_eq!(token.balance(&escrow.address), 0);
-}
-
-#[test]
-fn test_withdraw_invalid_secret() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
-
-    let escrow = create_escrow_contract(&env);
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
-    
-    let immutables = Immutables {
-        hashlock: BytesN::from_array(&env, &[1; 32]),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: 1000,
-        cancellation_timestamp: 12345,
-    };
-
-    escrow.initialize(&immutables);
-    token_admin_client.mint(&escrow.address, &1000);
-    
-    // Wrong secret should fail
-    let wrong_secret = BytesN::from_array(&env, &[42; 32]);
-    assert_eq!(escrow.try_withdraw(&wrong_secret), Err(Ok(Error::InvalidSecret)));
-}
-
-#[test]
-fn test_withdraw_after_cancellation_time() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set ledger timestamp after cancellation
-    env.ledger().with_mut(|li| {
-        li.timestamp = 15000;
-    });
-
-    let escrow = create_escrow_contract(&env);
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, _) = create_token_contract(&env, &token_admin);
-    
-    let secret = BytesN::from_array(&env, &[42; 32]);
-    let secret_hash = env.crypto().sha256(&secret.clone().into());
-    
-    let immutables = Immutables {
-        hashlock: secret_hash.into(),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: 1000,
-        cancellation_timestamp: 12345,
-    };
-
-    escrow.initialize(&immutables);
-    
-    // Should fail due to time predicate
-    assert_eq!(escrow.try_withdraw(&secret), Err(Ok(Error::TimePredicateNotMet)));
-}
-
-#[test]
-fn test_cancel_success() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set ledger timestamp after cancellation
-    env.ledger().with_mut(|li| {
-        li.timestamp = 15000;
-    });
-
-    let escrow = create_escrow_contract(&env);
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
-    
-    let immutables = Immutables {
-        hashlock: BytesN::from_array(&env, &[1; 32]),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: 1000,
-        cancellation_timestamp: 12345,
-    };
-
-    escrow.initialize(&immutables);
-    token_admin_client.mint(&escrow.address, &1000);
-    
-    // Cancel should succeed
-    assert_eq!(escrow.cancel(), ());
-    
-    // Check token balance
-    assert_eq!(token.balance(&maker), 1000);
-    assert_eq!(token.balance(&escrow.address), 0);
-}
-
-#[test]
-fn test_cancel_before_cancellation_time() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set ledger timestamp before cancellation
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
-
-    let escrow = create_escrow_contract(&env);
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, _) = create_token_contract(&env, &token_admin);
-    
-    let immutables = Immutables {
-        hashlock: BytesN::from_array(&env, &[1; 32]),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: 1000,
-        cancellation_timestamp: 12345,
-    };
-
-    escrow.initialize(&immutables);
-    
-    // Should fail due to time predicate
-    assert_eq!(escrow.try_cancel(), Err(Ok(Error::TimePredicateNotMet)));
-}
-
-#[test]
-fn test_negative_amount() {
-    let env = Env::default();
-    let escrow = create_escrow_contract(&env);
-    
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token, _) = create_token_contract(&env, &token_admin);
-    
-    let immutables = Immutables {
-        hashlock: BytesN::from_array(&env, &[1; 32]),
-        maker: maker.clone(),
-        taker: taker.clone(),
-        token: token.address.clone(),
-        amount: -100, // Negative amount
-        cancellation_timestamp: 12345,
-    };
-
-    // Should fail with negative amount
-    assert_eq!(escrow.try_initialize(&immutables), Err(Ok(Error::NegativeAmount)));
-}
\ No newline at end of file
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    testutils::{Address as _, Events, Ledger, MockAuth, MockAuthInvoke},
+    token, vec, Address, Env, IntoVal,
+};
+
+fn create_token_contract<'a>(
+    e: &Env,
+    admin: &Address,
+) -> (token::Client<'a>, token::StellarAssetClient<'a>) {
+    let sac = e.register_stellar_asset_contract_v2(admin.clone());
+    (
+        token::Client::new(e, &sac.address()),
+        token::StellarAssetClient::new(e, &sac.address()),
+    )
+}
+
+// Settlement contract that records the callback it receives
+#[contract]
+pub struct MockSettlement;
+
+#[contractimpl]
+impl MockSettlement {
+    pub fn on_escrow_settled(env: Env, hashlock: BytesN<32>, amount: i128, taker: Address) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "settled"), &(hashlock, amount, taker));
+    }
+
+    pub fn settled(env: Env) -> Option<(BytesN<32>, i128, Address)> {
+        env.storage().instance().get(&Symbol::new(&env, "settled"))
+    }
+}
+
+// Router that pays out two `token_out` per `token_in` from its own balance
+#[contract]
+pub struct MockRouter;
+
+#[contractimpl]
+impl MockRouter {
+    pub fn swap(env: Env, _token_in: Address, token_out: Address, amount_in: i128, to: Address) -> i128 {
+        let amount_out = amount_in * 2;
+        token::Client::new(&env, &token_out).transfer(&env.current_contract_address(), &to, &amount_out);
+        amount_out
+    }
+}
+
+fn create_escrow_contract(e: &Env) -> SorobanEscrowClient<'_> {
+    SorobanEscrowClient::new(e, &e.register(SorobanEscrow, ()))
+}
+
+// Immutables for 1000 of `token` from `maker` to `taker` with every optional feature off:
+// withdrawable from deployment until cancellation at 12345; tests override what they exercise
+fn default_immutables(e: &Env, maker: &Address, taker: &Address, token: &Address) -> Immutables {
+    Immutables {
+        hashlock: BytesN::from_array(e, &[1; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.clone(),
+        amount: 1000,
+        timelocks: Timelocks {
+            deployed_at: 0,
+            withdrawal: 0,
+            public_withdrawal: 12000,
+            cancellation: 12345,
+            public_cancellation: 12345,
+        },
+        safety_deposit: 0,
+        deposit_token: token.clone(),
+        hash_algorithm: 0,
+        arbiter: None,
+    }
+}
+
+#[test]
+fn test_initialize() {
+    let env = Env::default();
+    let escrow = create_escrow_contract(&env);
+    
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, _) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        hashlock: BytesN::from_array(&env, &[1; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: 1000,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Should initialize successfully
+    assert_eq!(escrow.initialize(&immutables, &None), ());
+    
+    // Should fail to initialize again
+    assert_eq!(escrow.try_initialize(&immutables, &None), Err(Ok(Error::AlreadyInitialized)));
+}
+
+#[test]
+fn test_withdraw_success() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set ledger timestamp before cancellation
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    // Create secret and its hash
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+    
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: 1000,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Initialize escrow
+    escrow.initialize(&immutables, &None);
+    
+    // Fund the escrow contract
+    token_admin_client.mint(&escrow.address, &1000);
+    
+    // Withdraw should succeed
+    escrow.withdraw(&secret, &None);
+    
+    // Check token balance
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+}
+
+#[test]
+fn test_withdraw_keccak256_hashlock() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    // Hashlock computed the way the EVM side does
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().keccak256(&secret.clone().into());
+
+    let mut immutables = Immutables {
+        hashlock: secret_hash.into(),
+        hash_algorithm: 2,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Unknown algorithms are rejected up front
+    assert_eq!(escrow.try_initialize(&immutables, &None), Err(Ok(Error::UnsupportedHashAlgorithm)));
+
+    immutables.hash_algorithm = HASH_ALGORITHM_KECCAK256;
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    assert_eq!(escrow.get_secret_policy().hash_algo, Symbol::new(&env, "keccak256"));
+
+    // A wrong preimage is rejected; the keccak256 preimage unlocks the escrow
+    let wrong_secret = BytesN::from_array(&env, &[7; 32]);
+    assert_eq!(escrow.try_withdraw(&wrong_secret, &None), Err(Ok(Error::InvalidSecret)));
+    escrow.withdraw(&secret, &None);
+
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+}
+
+#[test]
+fn test_withdraw_invalid_secret() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        hashlock: BytesN::from_array(&env, &[1; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: 1000,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    
+    // Wrong secret should fail
+    let wrong_secret = BytesN::from_array(&env, &[42; 32]);
+    assert_eq!(escrow.try_withdraw(&wrong_secret, &None), Err(Ok(Error::InvalidSecret)));
+}
+
+#[test]
+fn test_withdraw_after_cancellation_time() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set ledger timestamp after cancellation
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, _) = create_token_contract(&env, &token_admin);
+    
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+    
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: 1000,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    
+    // Should fail due to time predicate
+    assert_eq!(escrow.try_withdraw(&secret, &None), Err(Ok(Error::TimePredicateNotMet)));
+}
+
+#[test]
+fn test_cancel_success() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set ledger timestamp after cancellation
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        hashlock: BytesN::from_array(&env, &[1; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: 1000,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    
+    // Cancel should succeed
+    assert_eq!(escrow.cancel(&None), ());
+    
+    // Check token balance
+    assert_eq!(token.balance(&maker), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+}
+
+#[test]
+fn test_cancel_after_settlement_fails() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let immutables = Immutables {
+        hashlock: env.crypto().sha256(&secret.clone().into()).into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    escrow.withdraw(&secret, &None);
+
+    // Tokens sent after the withdrawal can't be cancelled out to the maker
+    token_admin_client.mint(&escrow.address, &1000);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    assert_eq!(escrow.try_cancel(&None), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(escrow.try_public_cancel(&maker), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(token.balance(&maker), 0);
+    assert_eq!(token.balance(&taker), 1000);
+}
+
+#[test]
+fn test_cancel_twice_fails() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    escrow.initialize(&default_immutables(&env, &maker, &taker, &token.address), &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    escrow.cancel(&None);
+
+    // A second cancellation doesn't refund the principal again
+    token_admin_client.mint(&escrow.address, &1000);
+    assert_eq!(escrow.try_cancel(&None), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(escrow.try_public_cancel(&taker), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(token.balance(&maker), 1000);
+    assert_eq!(escrow.get_withdrawn(), 0);
+    assert_eq!(escrow.get_remaining_principal(), 0);
+}
+
+#[test]
+fn test_withdraw_twice_fails() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let immutables = Immutables {
+        hashlock: env.crypto().sha256(&secret.clone().into()).into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    escrow.withdraw(&secret, &None);
+
+    // Tokens sent after the withdrawal can't be withdrawn to the taker again by any path
+    token_admin_client.mint(&escrow.address, &1000);
+    let settlement = Address::generate(&env);
+    assert_eq!(escrow.try_withdraw(&secret, &None), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(escrow.try_public_withdraw(&secret, &maker), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(
+        escrow.try_withdraw_and_settle(&secret, &settlement),
+        Err(Ok(Error::EscrowNotActive))
+    );
+    assert_eq!(
+        escrow.try_withdraw_partial(&secret, &Vec::new(&env), &0, &100),
+        Err(Ok(Error::EscrowNotActive))
+    );
+    assert_eq!(escrow.try_withdraw_amount(&secret, &100), Err(Ok(Error::FullyWithdrawn)));
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(token.balance(&escrow.address), 1000);
+}
+
+#[test]
+fn test_withdraw_after_cancel_fails() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let immutables = Immutables {
+        hashlock: env.crypto().sha256(&secret.clone().into()).into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    escrow.cancel(&None);
+
+    // A cancelled escrow never pays the taker, even from later deposits
+    token_admin_client.mint(&escrow.address, &1000);
+    assert_eq!(escrow.try_withdraw(&secret, &None), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(escrow.try_withdraw_amount(&secret, &100), Err(Ok(Error::EscrowNotActive)));
+    assert_eq!(token.balance(&taker), 0);
+    assert_eq!(token.balance(&maker), 1000);
+}
+
+#[test]
+fn test_cancel_before_cancellation_time() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set ledger timestamp before cancellation
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, _) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        hashlock: BytesN::from_array(&env, &[1; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: 1000,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    
+    // Should fail due to time predicate
+    assert_eq!(escrow.try_cancel(&None), Err(Ok(Error::TimePredicateNotMet)));
+}
+
+#[test]
+fn test_negative_amount() {
+    let env = Env::default();
+    let escrow = create_escrow_contract(&env);
+    
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, _) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        hashlock: BytesN::from_array(&env, &[1; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token.address.clone(),
+        amount: -100, // Negative amount
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Should fail with negative amount
+    assert_eq!(escrow.try_initialize(&immutables, &None), Err(Ok(Error::NegativeAmount)));
+}
+#[test]
+fn test_cancel_underfunded_refunds_partial() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set ledger timestamp after cancellation
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+
+    // Only part of the agreed amount was funded
+    token_admin_client.mint(&escrow.address, &600);
+    
+    // Cancel should succeed and refund what is there
+    escrow.cancel(&None);
+    
+    assert_eq!(token.balance(&maker), 600);
+    assert_eq!(token.balance(&escrow.address), 0);
+    assert_eq!(escrow.get_cancel_shortfall(), 400);
+}
+
+#[test]
+fn test_serialize_immutables_layout() {
+    let env = Env::default();
+    let escrow = create_escrow_contract(&env);
+
+    // Account-type maker, contract-type taker and token
+    let maker = Address::from_str(&env, "GAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAWHF");
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, _) = create_token_contract(&env, &token_admin);
+
+    let immutables = Immutables {
+        hash_algorithm: HASH_ALGORITHM_KECCAK256,
+        arbiter: Some(taker.clone()),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    let encoded = escrow.serialize_immutables(&immutables);
+
+    // Twelve 32-byte words in EVM field order
+    assert_eq!(encoded.len(), 384);
+    assert_eq!(encoded.slice(0..32), Bytes::from_array(&env, &[1; 32]));
+    assert_eq!(encoded.slice(32..64), Bytes::from_array(&env, &[0; 32]));
+
+    let mut amount_word = [0u8; 32];
+    amount_word[30..].copy_from_slice(&1000u16.to_be_bytes());
+    assert_eq!(encoded.slice(128..160), Bytes::from_array(&env, &amount_word));
+
+    // deployed_at, then the stage offsets packed as four uint64s
+    assert_eq!(encoded.slice(160..192), Bytes::from_array(&env, &[0; 32]));
+    let mut offsets_word = [0u8; 32];
+    offsets_word[8..16].copy_from_slice(&12000u64.to_be_bytes());
+    offsets_word[16..24].copy_from_slice(&12345u64.to_be_bytes());
+    offsets_word[24..].copy_from_slice(&12345u64.to_be_bytes());
+    assert_eq!(encoded.slice(192..224), Bytes::from_array(&env, &offsets_word));
+
+    assert_eq!(encoded.slice(224..256), Bytes::from_array(&env, &[0; 32]));
+
+    let mut hash_algorithm_word = [0u8; 32];
+    hash_algorithm_word[31] = 1;
+    assert_eq!(encoded.slice(288..320), Bytes::from_array(&env, &hash_algorithm_word));
+
+    // The arbiter is written like any other address
+    assert_eq!(encoded.slice(320..352), encoded.slice(64..96));
+
+    // Taker, token, deposit token and arbiter are contracts, maker is an account; an arbiter is set
+    assert_eq!(encoded.get(383), Some(0b11_1110));
+
+    // Encoding is stable
+    assert_eq!(escrow.serialize_immutables(&immutables), encoded);
+
+    // Round-trips through the companion decoder
+    assert_eq!(escrow.deserialize_immutables(&encoded), immutables);
+}
+
+#[test]
+fn test_deserialize_immutables_invalid() {
+    let env = Env::default();
+    let escrow = create_escrow_contract(&env);
+
+    // Wrong length
+    assert_eq!(
+        escrow.try_deserialize_immutables(&Bytes::from_array(&env, &[0; 64])),
+        Err(Ok(Error::InvalidEncoding))
+    );
+
+    // Non-zero padding in the timestamp word
+    let mut data = [0u8; 384];
+    data[160] = 1;
+    assert_eq!(
+        escrow.try_deserialize_immutables(&Bytes::from_array(&env, &data)),
+        Err(Ok(Error::InvalidEncoding))
+    );
+}
+
+#[test]
+fn test_withdraw_and_settle() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let settlement = MockSettlementClient::new(&env, &env.register(MockSettlement, ()));
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let hashlock: BytesN<32> = env.crypto().sha256(&secret.clone().into()).into();
+    
+    let immutables = Immutables {
+        hashlock: hashlock.clone(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // Wrong secret is rejected before anything moves
+    assert_eq!(
+        escrow.try_withdraw_and_settle(&BytesN::from_array(&env, &[1; 32]), &settlement.address),
+        Err(Ok(Error::InvalidSecret))
+    );
+
+    escrow.withdraw_and_settle(&secret, &settlement.address);
+
+    // Settlement received the funds and the callback
+    assert_eq!(token.balance(&settlement.address), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+    assert_eq!(token.balance(&taker), 0);
+    assert_eq!(settlement.settled(), Some((hashlock, 1000, taker)));
+}
+
+#[test]
+fn test_withdraw_partial_extends_deadline() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+    
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // Each partial withdrawal pushes the deadline 1000s forward, up to 14000
+    escrow.set_partial_extension(&1000, &14000);
+
+    escrow.withdraw_partial(&secret, &vec![&env], &0, &400);
+    assert_eq!(token.balance(&taker), 400);
+    assert_eq!(escrow.get_remaining_principal(), 600);
+    assert_eq!(escrow.get_cancellation_timestamp(), 13345);
+
+    // Extension is capped at the configured maximum
+    escrow.withdraw_partial(&secret, &vec![&env], &0, &100);
+    assert_eq!(escrow.get_cancellation_timestamp(), 14000);
+
+    // Cannot withdraw more than what remains
+    assert_eq!(
+        escrow.try_withdraw_partial(&secret, &vec![&env], &0, &600),
+        Err(Ok(Error::InvalidAmount))
+    );
+
+    // The original deadline no longer allows cancellation
+    env.ledger().with_mut(|li| {
+        li.timestamp = 13000;
+    });
+    assert_eq!(escrow.try_cancel(&None), Err(Ok(Error::TimePredicateNotMet)));
+
+    // After the extended deadline the maker gets the unwithdrawn remainder
+    env.ledger().with_mut(|li| {
+        li.timestamp = 14000;
+    });
+    escrow.cancel(&None);
+    assert_eq!(token.balance(&maker), 500);
+    assert_eq!(escrow.get_cancel_shortfall(), 0);
+}
+
+#[test]
+fn test_cancel_to_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let escrow = create_escrow_contract(&env);
+    let router = env.register(MockRouter, ());
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    let (out_token, out_token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    out_token_admin_client.mint(&router, &5000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 13000;
+    });
+
+    // Router pays 2000, so asking for more reverts the whole cancel
+    assert_eq!(
+        escrow.try_cancel_to_token(&out_token.address, &router, &2001),
+        Err(Ok(Error::SwapUnderperformed))
+    );
+    assert_eq!(token.balance(&escrow.address), 1000);
+
+    escrow.cancel_to_token(&out_token.address, &router, &2000);
+
+    // Maker is refunded in the other token
+    assert_eq!(token.balance(&escrow.address), 0);
+    assert_eq!(token.balance(&router), 1000);
+    assert_eq!(token.balance(&maker), 0);
+    assert_eq!(out_token.balance(&maker), 2000);
+}
+
+#[test]
+fn test_get_eligibility() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    
+    let immutables = Immutables {
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // Withdrawal phase
+    assert_eq!(
+        escrow.get_eligibility(),
+        Eligibility {
+            can_withdraw_now: true,
+            can_cancel_now: false,
+            seconds_to_cancel: 2345,
+            state: EscrowState::Active,
+        }
+    );
+
+    // Cancel phase
+    env.ledger().with_mut(|li| {
+        li.timestamp = 13000;
+    });
+    assert_eq!(
+        escrow.get_eligibility(),
+        Eligibility {
+            can_withdraw_now: false,
+            can_cancel_now: true,
+            seconds_to_cancel: 0,
+            state: EscrowState::Active,
+        }
+    );
+
+    // Nothing is possible once cancelled
+    escrow.cancel(&None);
+    assert_eq!(
+        escrow.get_eligibility(),
+        Eligibility {
+            can_withdraw_now: false,
+            can_cancel_now: false,
+            seconds_to_cancel: 0,
+            state: EscrowState::Cancelled,
+        }
+    );
+}
+
+#[test]
+fn test_held_tokens() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let (token_c, _) = create_token_contract(&env, &token_admin);
+
+    // Fund two of the three candidates
+    token_a_admin.mint(&escrow.address, &1000);
+    token_b_admin.mint(&escrow.address, &250);
+
+    let held = escrow.held_tokens(&vec![
+        &env,
+        token_a.address.clone(),
+        token_b.address.clone(),
+        token_c.address.clone(),
+    ]);
+
+    assert_eq!(
+        held,
+        vec![
+            &env,
+            (token_a.address.clone(), 1000),
+            (token_b.address.clone(), 250),
+            (token_c.address.clone(), 0),
+        ]
+    );
+}
+
+#[test]
+fn test_cancel_remaining_after_partial_withdrawal() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // Taker settles one leaf
+    escrow.withdraw_partial(&secret, &vec![&env], &0, &300);
+
+    // Not cancellable before the deadline
+    assert_eq!(escrow.try_cancel_remaining(), Err(Ok(Error::TimePredicateNotMet)));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12345;
+    });
+
+    // Only the unwithdrawn remainder goes back to the maker
+    escrow.cancel_remaining();
+    assert_eq!(token.balance(&maker), 700);
+    assert_eq!(token.balance(&taker), 300);
+    assert_eq!(token.balance(&escrow.address), 0);
+    assert_eq!(escrow.get_withdrawn(), 300);
+    assert_eq!(escrow.get_remaining_principal(), 0);
+    assert_eq!(escrow.get_eligibility().state, EscrowState::Cancelled);
+    assert_eq!(escrow.get_reason(), Some(REASON_NORMAL));
+    assert_eq!(escrow.get_cancel_shortfall(), 0);
+
+    // Nothing left to cancel
+    assert_eq!(escrow.try_cancel_remaining(), Err(Ok(Error::EscrowNotActive)));
+}
+
+#[test]
+fn test_get_secret_policy() {
+    let env = Env::default();
+    let escrow = create_escrow_contract(&env);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, _) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash: BytesN<32> = env.crypto().sha256(&secret.into()).into();
+
+    let immutables = Immutables {
+        hashlock: secret_hash.clone(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Nothing to report before initialization
+    assert_eq!(escrow.try_get_secret_policy(), Err(Ok(Error::NotInitialized)));
+
+    escrow.initialize(&immutables, &None);
+
+    let policy = escrow.get_secret_policy();
+    assert_eq!(policy.secret_length, 32);
+    assert_eq!(policy.hash_algo, Symbol::new(&env, "sha256"));
+    assert_eq!(policy.hashlock, immutables.hashlock);
+}
+
+#[test]
+fn test_time_tolerance_shifts_deadline() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // Tolerance is capped
+    assert_eq!(escrow.try_set_time_tolerance(&61), Err(Ok(Error::InvalidTolerance)));
+    escrow.set_time_tolerance(&5);
+    assert_eq!(escrow.get_time_tolerance(), 5);
+
+    // Once the tolerance has passed cancellation opens and withdrawals close
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12350;
+    });
+    let eligibility = escrow.get_eligibility();
+    assert!(eligibility.can_cancel_now);
+    assert!(!eligibility.can_withdraw_now);
+
+    // Within the tolerance past the deadline only withdrawals are open
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12349;
+    });
+    let eligibility = escrow.get_eligibility();
+    assert!(eligibility.can_withdraw_now);
+    assert!(!eligibility.can_cancel_now);
+    assert_eq!(eligibility.seconds_to_cancel, 1);
+    assert_eq!(escrow.try_cancel(&None), Err(Ok(Error::TimePredicateNotMet)));
+
+    // A withdrawal two seconds after the deadline would fail without tolerance
+    escrow.withdraw(&secret, &None);
+    assert_eq!(token.balance(&taker), 1000);
+}
+
+#[test]
+fn test_withdraw_returns_receipt() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let escrow = create_escrow_contract(&env);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    let receipt = escrow.withdraw(&secret, &None);
+    assert_eq!(receipt.recipient, taker);
+    assert_eq!(receipt.amount, 1000);
+    assert_eq!(receipt.revealed_secret, secret);
+    assert_eq!(token.balance(&taker), 1000);
+}
+
+#[test]
+fn test_cancel_fee_split() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Fee is capped at 5%
+    let cancel_fee = CancelFee {
+        cancel_fee_bps: 501,
+        treasury: treasury.clone(),
+    };
+    assert_eq!(escrow.try_initialize(&immutables, &Some(cancel_fee.clone())), Err(Ok(Error::InvalidFee)));
+
+    escrow.initialize(&immutables, &Some(CancelFee { cancel_fee_bps: 250, ..cancel_fee }));
+    token_admin_client.mint(&escrow.address, &1000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12345;
+    });
+    escrow.cancel(&None);
+
+    // 2.5% goes to the treasury, the rest back to the maker
+    assert_eq!(token.balance(&treasury), 25);
+    assert_eq!(token.balance(&maker), 975);
+    assert_eq!(token.balance(&escrow.address), 0);
+}
+
+#[test]
+fn test_cancel_records_reason() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let immutables = Immutables {
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    assert_eq!(escrow.get_reason(), None);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12345;
+    });
+    escrow.cancel(&Some(REASON_DISPUTE));
+
+    // The reason is stored and carried on the cancel event
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![
+            &env,
+            (
+                escrow.address.clone(),
+                ("cancel",).into_val(&env),
+                (maker.clone(), 1000i128, REASON_DISPUTE).into_val(&env),
+            ),
+        ]
+    );
+    assert_eq!(escrow.get_reason(), Some(REASON_DISPUTE));
+    assert_eq!(token.balance(&maker), 1000);
+}
+
+#[test]
+fn test_public_withdraw_window() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let resolver = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    let (deposit_token, deposit_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        safety_deposit: 50,
+        deposit_token: deposit_token.address.clone(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    deposit_admin_client.mint(&escrow.address, &50);
+    assert_eq!(escrow.get_safety_deposit(), 50);
+    assert_eq!(escrow.get_deposit_token(), deposit_token.address);
+
+    // Closed before the public withdrawal timestamp
+    env.ledger().with_mut(|li| {
+        li.timestamp = 11999;
+    });
+    assert_eq!(
+        escrow.try_public_withdraw(&secret, &resolver),
+        Err(Ok(Error::PublicWindowNotOpen))
+    );
+
+    // Once open, anyone with the secret pushes the funds to the taker and earns the deposit
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12000;
+    });
+    let wrong_secret = BytesN::from_array(&env, &[7; 32]);
+    assert_eq!(
+        escrow.try_public_withdraw(&wrong_secret, &resolver),
+        Err(Ok(Error::InvalidSecret))
+    );
+    escrow.public_withdraw(&secret, &resolver);
+
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 2..events.len() - 1),
+        vec![
+            &env,
+            (
+                escrow.address.clone(),
+                ("safety_deposit_paid",).into_val(&env),
+                (resolver.clone(), 50i128).into_val(&env),
+            ),
+        ]
+    );
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(deposit_token.balance(&resolver), 50);
+    assert_eq!(deposit_token.balance(&escrow.address), 0);
+    assert!(escrow.is_safety_deposit_paid());
+    assert_eq!(escrow.get_eligibility().state, EscrowState::Withdrawn);
+}
+
+#[test]
+fn test_rescue_after_delay() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+    let (stray_token, stray_admin_client) = create_token_contract(&env, &token_admin);
+
+    let mut immutables = default_immutables(&env, &maker, &taker, &token.address);
+    immutables.timelocks.public_cancellation = 20000;
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1050);
+    stray_admin_client.mint(&escrow.address, &300);
+
+    // Public cancellation opens first; rescue waits another seven days
+    env.ledger().with_mut(|li| {
+        li.timestamp = 20000 + 7 * 24 * 60 * 60 - 1;
+    });
+    assert_eq!(
+        escrow.try_rescue(&stray_token.address, &300),
+        Err(Ok(Error::RescueTooEarly))
+    );
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 20000 + 7 * 24 * 60 * 60;
+    });
+    assert_eq!(escrow.try_rescue(&stray_token.address, &0), Err(Ok(Error::InvalidAmount)));
+    escrow.rescue(&stray_token.address, &300);
+
+    // Only the escrow token beyond the principal is stray
+    assert_eq!(escrow.try_rescue(&token.address, &51), Err(Ok(Error::InvalidAmount)));
+    escrow.rescue(&token.address, &50);
+
+    assert_eq!(stray_token.balance(&maker), 300);
+    assert_eq!(token.balance(&maker), 50);
+
+    // The principal still leaves through cancellation
+    escrow.cancel(&None);
+    assert_eq!(token.balance(&maker), 1050);
+    assert_eq!(token.balance(&escrow.address), 0);
+}
+
+#[test]
+fn test_public_withdraw_whitelist() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let approved = Address::generate(&env);
+    let outsider = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    assert_eq!(escrow.get_public_withdrawers(), vec![&env]);
+    escrow.set_public_withdrawers(&vec![&env, approved.clone()]);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 12100;
+    });
+
+    // Only whitelisted callers can finalize during the public window
+    assert_eq!(
+        escrow.try_public_withdraw(&secret, &outsider),
+        Err(Ok(Error::NotAuthorized))
+    );
+    escrow.public_withdraw(&secret, &approved);
+    assert_eq!(token.balance(&taker), 1000);
+}
+
+fn hash_pair(env: &Env, left: &BytesN<32>, right: &BytesN<32>) -> BytesN<32> {
+    let mut data = Bytes::from_array(env, &left.to_array());
+    data.extend_from_array(&right.to_array());
+    env.crypto().sha256(&data).into()
+}
+
+#[test]
+fn test_withdraw_partial_merkle_secrets() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    // Four secrets, each unlocking another quarter of the principal
+    let secrets: std::vec::Vec<BytesN<32>> =
+        (0..4u8).map(|i| BytesN::from_array(&env, &[i + 1; 32])).collect();
+    let leaves: std::vec::Vec<BytesN<32>> = secrets
+        .iter()
+        .map(|secret| env.crypto().sha256(&secret.clone().into()).into())
+        .collect();
+    let left = hash_pair(&env, &leaves[0], &leaves[1]);
+    let right = hash_pair(&env, &leaves[2], &leaves[3]);
+    let root = hash_pair(&env, &left, &right);
+
+    let immutables = Immutables {
+        hashlock: root,
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // The first secret unlocks a quarter
+    let proof0 = vec![&env, leaves[1].clone(), right.clone()];
+    escrow.withdraw_partial(&secrets[0], &proof0, &0, &250);
+    assert_eq!(token.balance(&taker), 250);
+
+    // The second secret unlocks half in total, no more
+    let proof1 = vec![&env, leaves[0].clone(), right.clone()];
+    assert_eq!(
+        escrow.try_withdraw_partial(&secrets[1], &proof1, &1, &300),
+        Err(Ok(Error::InvalidAmount))
+    );
+    escrow.withdraw_partial(&secrets[1], &proof1, &1, &250);
+
+    // Proofs must lead to the root from the claimed index
+    assert_eq!(
+        escrow.try_withdraw_partial(&secrets[3], &proof1, &3, &100),
+        Err(Ok(Error::InvalidProof))
+    );
+    assert_eq!(
+        escrow.try_withdraw_partial(&secrets[3], &vec![&env, leaves[2].clone(), left.clone()], &2, &100),
+        Err(Ok(Error::InvalidProof))
+    );
+
+    // The last secret releases the rest
+    let proof3 = vec![&env, leaves[2].clone(), left.clone()];
+    escrow.withdraw_partial(&secrets[3], &proof3, &3, &500);
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(escrow.get_remaining_principal(), 0);
+    assert_eq!(escrow.get_eligibility().state, EscrowState::Withdrawn);
+}
+
+#[test]
+fn test_timelock_stages() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let resolver = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let mut immutables = Immutables {
+        hashlock: secret_hash.into(),
+        timelocks: Timelocks {
+            deployed_at: 10000,
+            withdrawal: 100,
+            public_withdrawal: 200,
+            cancellation: 150,
+            public_cancellation: 400,
+        },
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    // Stages must open in order
+    assert_eq!(escrow.try_initialize(&immutables, &None), Err(Ok(Error::InvalidTimelocks)));
+
+    immutables.timelocks.cancellation = 300;
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+    assert_eq!(escrow.get_timelocks(), immutables.timelocks);
+    assert_eq!(escrow.get_cancellation_timestamp(), 10300);
+
+    // Still in the finality lock
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10050;
+    });
+    assert_eq!(escrow.try_withdraw(&secret, &None), Err(Ok(Error::TimePredicateNotMet)));
+
+    // Maker can cancel from the cancellation stage, anyone only from the public cancellation stage
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10300;
+    });
+    assert_eq!(escrow.try_withdraw(&secret, &None), Err(Ok(Error::TimePredicateNotMet)));
+    assert_eq!(escrow.try_public_cancel(&resolver), Err(Ok(Error::PublicWindowNotOpen)));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10400;
+    });
+    escrow.public_cancel(&resolver);
+
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![
+            &env,
+            (
+                escrow.address.clone(),
+                ("public_cancel",).into_val(&env),
+                (maker.clone(), 1000i128).into_val(&env),
+            ),
+        ]
+    );
+    assert_eq!(token.balance(&maker), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+    assert_eq!(escrow.get_eligibility().state, EscrowState::Cancelled);
+}
+
+#[test]
+fn test_withdraw_requires_arbiter_approval() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let arbiter = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        arbiter: Some(arbiter.clone()),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // The taker's signature and the right secret aren't enough on their own
+    env.mock_auths(&[MockAuth {
+        address: &taker,
+        invoke: &MockAuthInvoke {
+            contract: &escrow.address,
+            fn_name: "withdraw",
+            args: (secret.clone(), None::<u32>).into_val(&env),
+            sub_invokes: &[],
+        },
+    }]);
+    assert!(escrow.try_withdraw(&secret, &None).is_err());
+    assert_eq!(token.balance(&escrow.address), 1000);
+
+    // With the arbiter's approval the funds are released
+    env.mock_all_auths();
+    escrow.withdraw(&secret, &None);
+    assert!(env.auths().iter().any(|(address, _)| *address == arbiter));
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+}
+
+#[test]
+fn test_withdraw_amount_in_tranches() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let escrow = create_escrow_contract(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token, token_admin_client) = create_token_contract(&env, &token_admin);
+
+    let secret = BytesN::from_array(&env, &[42; 32]);
+    let secret_hash = env.crypto().sha256(&secret.clone().into());
+
+    let immutables = Immutables {
+        hashlock: secret_hash.into(),
+        ..default_immutables(&env, &maker, &taker, &token.address)
+    };
+
+    escrow.initialize(&immutables, &None);
+    token_admin_client.mint(&escrow.address, &1000);
+
+    // The same secret draws repeatedly
+    let wrong_secret = BytesN::from_array(&env, &[7; 32]);
+    assert_eq!(escrow.try_withdraw_amount(&wrong_secret, &300), Err(Ok(Error::InvalidSecret)));
+    assert_eq!(escrow.try_withdraw_amount(&secret, &0), Err(Ok(Error::InvalidAmount)));
+    assert_eq!(escrow.withdraw_amount(&secret, &300), 300);
+    assert_eq!(escrow.withdraw_amount(&secret, &400), 400);
+    assert_eq!(escrow.get_withdrawn(), 700);
+    assert_eq!(escrow.get_eligibility().state, EscrowState::Active);
+
+    // The last draw is capped at what is left and settles the escrow
+    assert_eq!(escrow.withdraw_amount(&secret, &500), 300);
+    assert_eq!(escrow.get_withdrawn(), 1000);
+    assert_eq!(token.balance(&taker), 1000);
+    assert_eq!(token.balance(&escrow.address), 0);
+    assert_eq!(escrow.get_eligibility().state, EscrowState::Withdrawn);
+
+    assert_eq!(escrow.try_withdraw_amount(&secret, &1), Err(Ok(Error::FullyWithdrawn)));
+}
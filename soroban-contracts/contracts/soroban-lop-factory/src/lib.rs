@@ -1,229 +1,486 @@
-#![no_std]
-use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, Address, BytesN, Env,
-};
-
-// Import the LOP and Dutch auction contracts
-mod lop {
-    soroban_sdk::contractimport!(
-        file = "../../target/wasm32v1-none/release/soroban_lop_contract.wasm"
-    );
-}
-
-mod dutch_auction {
-    soroban_sdk::contractimport!(
-        file = "../../target/wasm32v1-none/release/soroban_dutch_auction_contract.wasm"
-    );
-}
-
-#[contracttype]
-pub enum DataKey {
-    LOPWasmHash,
-    DutchAuctionWasmHash,
-    Admin,
-}
-
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-#[repr(u32)]
-pub enum Error {
-    NotInitialized = 1,
-    AlreadyInitialized = 2,
-    NotAuthorized = 3,
-    DeploymentFailed = 4,
-}
-
-#[contract]
-pub struct SorobanLOPFactory;
-
-#[contractimpl]
-impl SorobanLOPFactory {
-    /// Initialize the factory with WASM hashes
-    pub fn initialize(
-        env: Env,
-        admin: Address,
-        lop_wasm_hash: BytesN<32>,
-        dutch_auction_wasm_hash: BytesN<32>,
-    ) -> Result<(), Error> {
-        // Check if already initialized
-        if env.storage().instance().has(&DataKey::Admin) {
-            return Err(Error::AlreadyInitialized);
-        }
-
-        // Store the WASM hashes and admin
-        env.storage().instance().set(&DataKey::LOPWasmHash, &lop_wasm_hash);
-        env.storage().instance().set(&DataKey::DutchAuctionWasmHash, &dutch_auction_wasm_hash);
-        env.storage().instance().set(&DataKey::Admin, &admin);
-
-        Ok(())
-    }
-
-    /// Deploy a new LOP contract with deterministic address
-    pub fn deploy_lop(
-        env: Env,
-        salt: BytesN<32>,
-        admin: Address,
-    ) -> Result<Address, Error> {
-        // Get the stored WASM hash
-        let lop_wasm_hash: BytesN<32> = env
-            .storage()
-            .instance()
-            .get(&DataKey::LOPWasmHash)
-            .ok_or(Error::NotInitialized)?;
-
-        // First deploy Dutch auction contract for this LOP instance
-        let dutch_auction_salt = env.crypto().sha256(&salt.clone().into()).into();
-        let dutch_auction_address = deploy_dutch_auction_internal(&env, dutch_auction_salt)?;
-
-        // Deploy the LOP contract deterministically
-        let lop_address = env
-            .deployer()
-            .with_address(env.current_contract_address(), salt)
-            .deploy_v2(lop_wasm_hash, ());
-
-        // Create client and initialize the deployed LOP
-        let lop_client = lop::Client::new(&env, &lop_address);
-
-        // Initialize the LOP contract
-        match lop_client.try_initialize(&admin, &dutch_auction_address) {
-            Ok(_) => {},
-            Err(_) => return Err(Error::DeploymentFailed),
-        }
-
-        // Emit deployment event
-        env.events().publish(("deploy_lop",), &lop_address);
-
-        Ok(lop_address)
-    }
-
-    /// Deploy a new Dutch auction contract with deterministic address
-    pub fn deploy_dutch_auction(
-        env: Env,
-        salt: BytesN<32>,
-    ) -> Result<Address, Error> {
-        deploy_dutch_auction_internal(&env, salt)
-    }
-
-    /// Get the deterministic address of a LOP contract without deploying it
-    pub fn get_lop_address(
-        env: Env,
-        salt: BytesN<32>,
-    ) -> Result<Address, Error> {
-        // Check if initialized
-        let _: BytesN<32> = env
-            .storage()
-            .instance()
-            .get(&DataKey::LOPWasmHash)
-            .ok_or(Error::NotInitialized)?;
-
-        // Compute the deterministic address
-        let lop_address = env
-            .deployer()
-            .with_address(env.current_contract_address(), salt)
-            .deployed_address();
-
-        Ok(lop_address)
-    }
-
-    /// Get the deterministic address of a Dutch auction contract without deploying it
-    pub fn get_dutch_auction_address(
-        env: Env,
-        salt: BytesN<32>,
-    ) -> Result<Address, Error> {
-        // Check if initialized
-        let _: BytesN<32> = env
-            .storage()
-            .instance()
-            .get(&DataKey::DutchAuctionWasmHash)
-            .ok_or(Error::NotInitialized)?;
-
-        // Compute the deterministic address
-        let dutch_auction_address = env
-            .deployer()
-            .with_address(env.current_contract_address(), salt)
-            .deployed_address();
-
-        Ok(dutch_auction_address)
-    }
-
-    /// Update the LOP WASM hash (admin only)
-    pub fn update_lop_wasm_hash(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Error> {
-        // Check authorization
-        let admin: Address = env
-            .storage()
-            .instance()
-            .get(&DataKey::Admin)
-            .ok_or(Error::NotInitialized)?;
-        
-        admin.require_auth();
-
-        // Update the WASM hash
-        env.storage().instance().set(&DataKey::LOPWasmHash, &new_wasm_hash);
-
-        Ok(())
-    }
-
-    /// Update the Dutch auction WASM hash (admin only)
-    pub fn update_dutch_auction_wasm_hash(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Error> {
-        // Check authorization
-        let admin: Address = env
-            .storage()
-            .instance()
-            .get(&DataKey::Admin)
-            .ok_or(Error::NotInitialized)?;
-        
-        admin.require_auth();
-
-        // Update the WASM hash
-        env.storage().instance().set(&DataKey::DutchAuctionWasmHash, &new_wasm_hash);
-
-        Ok(())
-    }
-
-    /// Get the current LOP WASM hash
-    pub fn get_lop_wasm_hash(env: Env) -> Result<BytesN<32>, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::LOPWasmHash)
-            .ok_or(Error::NotInitialized)
-    }
-
-    /// Get the current Dutch auction WASM hash
-    pub fn get_dutch_auction_wasm_hash(env: Env) -> Result<BytesN<32>, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::DutchAuctionWasmHash)
-            .ok_or(Error::NotInitialized)
-    }
-
-    /// Get the admin address
-    pub fn get_admin(env: Env) -> Result<Address, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::Admin)
-            .ok_or(Error::NotInitialized)
-    }
-}
-
-/// Internal helper function to deploy Dutch auction contract
-fn deploy_dutch_auction_internal(env: &Env, salt: BytesN<32>) -> Result<Address, Error> {
-    // Get the stored WASM hash
-    let dutch_auction_wasm_hash: BytesN<32> = env
-        .storage()
-        .instance()
-        .get(&DataKey::DutchAuctionWasmHash)
-        .ok_or(Error::NotInitialized)?;
-
-    // Deploy the contract deterministically
-    let dutch_auction_address = env
-        .deployer()
-        .with_address(env.current_contract_address(), salt)
-        .deploy_v2(dutch_auction_wasm_hash, ());
-
-    // Emit deployment event
-    env.events().publish(("deploy_dutch_auction",), &dutch_auction_address);
-
-    Ok(dutch_auction_address)
-}
-
+#![no_std]
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, Address, BytesN, Env, Map, Symbol, Vec,
+};
+
+// Import the LOP and Dutch auction contracts
+#[allow(clippy::too_many_arguments)]
+mod lop {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/soroban_lop_contract.wasm"
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+mod dutch_auction {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/soroban_dutch_auction_contract.wasm"
+    );
+}
+
+#[contracttype]
+pub enum DataKey {
+    LOPWasmHash,
+    DutchAuctionWasmHash,
+    Admin,
+    Roles,  // Map<Address, Role>
+    Paused,
+    SharedAuction, // Dutch auction shared by LOPs from deploy_lop_shared, once deployed
+}
+
+// Salt reserved for the shared Dutch auction; every deploy entry point rejects it
+const SHARED_AUCTION_SALT: [u8; 32] = [0xff; 32];
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    SuperAdmin,
+    Operator,
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    NotAuthorized = 3,
+    DeploymentFailed = 4,
+    Paused = 5,
+    FillFailed = 6,
+}
+
+#[contract]
+pub struct SorobanLOPFactory;
+
+#[contractimpl]
+impl SorobanLOPFactory {
+    /// Initialize the factory with WASM hashes
+    pub fn initialize(
+        env: Env,
+        admin: Address,
+        lop_wasm_hash: BytesN<32>,
+        dutch_auction_wasm_hash: BytesN<32>,
+    ) -> Result<(), Error> {
+        // Check if already initialized
+        if env.storage().instance().has(&DataKey::Admin) {
+            return Err(Error::AlreadyInitialized);
+        }
+
+        // Store the WASM hashes and admin
+        env.storage().instance().set(&DataKey::LOPWasmHash, &lop_wasm_hash);
+        env.storage().instance().set(&DataKey::DutchAuctionWasmHash, &dutch_auction_wasm_hash);
+        env.storage().instance().set(&DataKey::Admin, &admin);
+
+        let mut roles: Map<Address, Role> = Map::new(&env);
+        roles.set(admin, Role::SuperAdmin);
+        env.storage().instance().set(&DataKey::Roles, &roles);
+
+        Ok(())
+    }
+
+    /// Deploy a new LOP contract with deterministic address
+    pub fn deploy_lop(
+        env: Env,
+        salt: BytesN<32>,
+        admin: Address,
+    ) -> Result<Address, Error> {
+        ensure_not_paused_internal(&env)?;
+        ensure_not_reserved_salt_internal(&env, &salt)?;
+
+        // Get the stored WASM hash
+        let lop_wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::LOPWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // First deploy Dutch auction contract for this LOP instance
+        let dutch_auction_salt = env.crypto().sha256(&salt.clone().into()).into();
+        let dutch_auction_address = deploy_dutch_auction_internal(&env, dutch_auction_salt)?;
+
+        deploy_lop_internal(&env, lop_wasm_hash, salt, &admin, &dutch_auction_address)
+    }
+
+    /// Deploy a new LOP contract that prices with the factory's shared Dutch auction
+    /// The shared auction is deployed with the first such LOP
+    pub fn deploy_lop_shared(
+        env: Env,
+        salt: BytesN<32>,
+        admin: Address,
+    ) -> Result<Address, Error> {
+        ensure_not_paused_internal(&env)?;
+        ensure_not_reserved_salt_internal(&env, &salt)?;
+
+        // Get the stored WASM hash
+        let lop_wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::LOPWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        let shared_auction: Option<Address> = env.storage().instance().get(&DataKey::SharedAuction);
+        let dutch_auction_address = match shared_auction {
+            Some(address) => address,
+            None => {
+                let address =
+                    deploy_dutch_auction_internal(&env, BytesN::from_array(&env, &SHARED_AUCTION_SALT))?;
+                env.storage().instance().set(&DataKey::SharedAuction, &address);
+                address
+            }
+        };
+
+        deploy_lop_internal(&env, lop_wasm_hash, salt, &admin, &dutch_auction_address)
+    }
+
+    /// Deploy a new Dutch auction contract with deterministic address
+    pub fn deploy_dutch_auction(
+        env: Env,
+        salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        ensure_not_paused_internal(&env)?;
+        ensure_not_reserved_salt_internal(&env, &salt)?;
+
+        deploy_dutch_auction_internal(&env, salt)
+    }
+
+    /// Fill orders spread across LOP contracts as a single taker
+    /// Each `(lop, order, max_taking_amount)` fills what is left of the order through `lop.fill_order`;
+    /// the first failing fill reverts the whole batch with `FillFailed`
+    pub fn route_fill(
+        env: Env,
+        fills: Vec<(Address, lop::Order, i128)>,
+        taker: Address,
+    ) -> Result<Vec<(i128, i128)>, Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        let mut results = Vec::new(&env);
+        for (lop_address, order, max_taking_amount) in fills.iter() {
+            let lop_client = lop::Client::new(&env, &lop_address);
+            // Earlier partial fills leave less than `order.making_amount` to take
+            let remaining = match lop_client.try_get_remaining_making_amount(&order) {
+                Ok(Ok(remaining)) => remaining,
+                _ => return Err(Error::FillFailed),
+            };
+            match lop_client.try_fill_order(&order, &taker, &remaining, &max_taking_amount) {
+                Ok(Ok(amounts)) => results.push_back(amounts),
+                _ => return Err(Error::FillFailed),
+            }
+        }
+
+        // Emit routing event
+        env.events().publish(("route_fill",), (&taker, fills.len()));
+
+        Ok(results)
+    }
+
+    /// Get the deterministic address of a LOP contract without deploying it
+    pub fn get_lop_address(
+        env: Env,
+        salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Check if initialized
+        let _: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::LOPWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // Compute the deterministic address
+        let lop_address = env
+            .deployer()
+            .with_address(env.current_contract_address(), salt)
+            .deployed_address();
+
+        Ok(lop_address)
+    }
+
+    /// Get the deterministic address of a Dutch auction contract without deploying it
+    pub fn get_dutch_auction_address(
+        env: Env,
+        salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Check if initialized
+        let _: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::DutchAuctionWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // Compute the deterministic address
+        let dutch_auction_address = env
+            .deployer()
+            .with_address(env.current_contract_address(), salt)
+            .deployed_address();
+
+        Ok(dutch_auction_address)
+    }
+
+    /// Get the deterministic address of the shared Dutch auction, deployed or not
+    pub fn get_shared_auction_address(env: Env) -> Result<Address, Error> {
+        // Check if initialized
+        let _: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::DutchAuctionWasmHash)
+            .ok_or(Error::NotInitialized)?;
+
+        // Compute the deterministic address
+        let shared_auction_address = env
+            .deployer()
+            .with_address(env.current_contract_address(), BytesN::from_array(&env, &SHARED_AUCTION_SALT))
+            .deployed_address();
+
+        Ok(shared_auction_address)
+    }
+
+    /// Update the LOP WASM hash (operator or super admin)
+    pub fn update_lop_wasm_hash(
+        env: Env,
+        caller: Address,
+        new_wasm_hash: BytesN<32>,
+    ) -> Result<(), Error> {
+        // Check authorization
+        require_role_internal(&env, &caller, Role::Operator)?;
+
+        // Update the WASM hash
+        env.storage().instance().set(&DataKey::LOPWasmHash, &new_wasm_hash);
+
+        // Emit admin action event
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "update_lop_wasm_hash")),
+            &new_wasm_hash,
+        );
+
+        Ok(())
+    }
+
+    /// Update the Dutch auction WASM hash (operator or super admin)
+    pub fn update_dutch_auction_wasm_hash(
+        env: Env,
+        caller: Address,
+        new_wasm_hash: BytesN<32>,
+    ) -> Result<(), Error> {
+        // Check authorization
+        require_role_internal(&env, &caller, Role::Operator)?;
+
+        // Update the WASM hash
+        env.storage().instance().set(&DataKey::DutchAuctionWasmHash, &new_wasm_hash);
+
+        // Emit admin action event
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "update_dutch_auction_wasm_hash")),
+            &new_wasm_hash,
+        );
+
+        Ok(())
+    }
+
+    /// Grant the operator role to an address (super admin only)
+    pub fn add_operator(env: Env, caller: Address, operator: Address) -> Result<(), Error> {
+        require_role_internal(&env, &caller, Role::SuperAdmin)?;
+
+        let mut roles = get_roles_internal(&env)?;
+        if roles.get(operator.clone()) == Some(Role::SuperAdmin) {
+            return Err(Error::NotAuthorized);
+        }
+        roles.set(operator.clone(), Role::Operator);
+        env.storage().instance().set(&DataKey::Roles, &roles);
+
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "add_operator")),
+            &operator,
+        );
+
+        Ok(())
+    }
+
+    /// Revoke the operator role from an address (super admin only)
+    pub fn remove_operator(env: Env, caller: Address, operator: Address) -> Result<(), Error> {
+        require_role_internal(&env, &caller, Role::SuperAdmin)?;
+
+        let mut roles = get_roles_internal(&env)?;
+        if roles.get(operator.clone()) != Some(Role::Operator) {
+            return Err(Error::NotAuthorized);
+        }
+        roles.remove(operator.clone());
+        env.storage().instance().set(&DataKey::Roles, &roles);
+
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "remove_operator")),
+            &operator,
+        );
+
+        Ok(())
+    }
+
+    /// Transfer the super admin role to a new address (super admin only)
+    pub fn transfer_super_admin(env: Env, caller: Address, new_admin: Address) -> Result<(), Error> {
+        require_role_internal(&env, &caller, Role::SuperAdmin)?;
+
+        let mut roles = get_roles_internal(&env)?;
+        roles.remove(caller);
+        roles.set(new_admin.clone(), Role::SuperAdmin);
+        env.storage().instance().set(&DataKey::Roles, &roles);
+        env.storage().instance().set(&DataKey::Admin, &new_admin);
+
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "transfer_super_admin")),
+            &new_admin,
+        );
+
+        Ok(())
+    }
+
+    /// Pause or unpause deployments (operator or super admin)
+    pub fn set_paused(env: Env, caller: Address, paused: bool) -> Result<(), Error> {
+        require_role_internal(&env, &caller, Role::Operator)?;
+
+        env.storage().instance().set(&DataKey::Paused, &paused);
+
+        env.events().publish(
+            ("admin_action", Symbol::new(&env, "set_paused")),
+            paused,
+        );
+
+        Ok(())
+    }
+
+    /// Check whether deployments are paused
+    pub fn is_paused(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::Paused).unwrap_or(false)
+    }
+
+    /// Get the role of an address, if any
+    pub fn get_role(env: Env, account: Address) -> Result<Option<Role>, Error> {
+        Ok(get_roles_internal(&env)?.get(account))
+    }
+
+    /// Get the current LOP WASM hash
+    pub fn get_lop_wasm_hash(env: Env) -> Result<BytesN<32>, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::LOPWasmHash)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the current Dutch auction WASM hash
+    pub fn get_dutch_auction_wasm_hash(env: Env) -> Result<BytesN<32>, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::DutchAuctionWasmHash)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the super admin address
+    pub fn get_admin(env: Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the symbolic name of an error code (e.g. 6 -> "FillFailed")
+    /// Returns "Unknown" for codes not defined by this contract
+    pub fn error_name(env: Env, code: u32) -> Symbol {
+        let name = match code {
+            1 => "NotInitialized",
+            2 => "AlreadyInitialized",
+            3 => "NotAuthorized",
+            4 => "DeploymentFailed",
+            5 => "Paused",
+            6 => "FillFailed",
+            _ => "Unknown",
+        };
+        Symbol::new(&env, name)
+    }
+}
+
+/// Internal helper function to load the role map
+fn get_roles_internal(env: &Env) -> Result<Map<Address, Role>, Error> {
+    env.storage()
+        .instance()
+        .get(&DataKey::Roles)
+        .ok_or(Error::NotInitialized)
+}
+
+/// Internal helper function to check that `caller` authorized the call and holds `required`
+/// The super admin satisfies every role
+fn require_role_internal(env: &Env, caller: &Address, required: Role) -> Result<(), Error> {
+    caller.require_auth();
+
+    match (get_roles_internal(env)?.get(caller.clone()), required) {
+        (Some(Role::SuperAdmin), _) => Ok(()),
+        (Some(Role::Operator), Role::Operator) => Ok(()),
+        _ => Err(Error::NotAuthorized),
+    }
+}
+
+/// Internal helper function to reject deployments while paused
+fn ensure_not_paused_internal(env: &Env) -> Result<(), Error> {
+    if env.storage().instance().get(&DataKey::Paused).unwrap_or(false) {
+        return Err(Error::Paused);
+    }
+    Ok(())
+}
+
+/// Internal helper function to keep caller salts off the shared auction's address
+fn ensure_not_reserved_salt_internal(env: &Env, salt: &BytesN<32>) -> Result<(), Error> {
+    if *salt == BytesN::from_array(env, &SHARED_AUCTION_SALT) {
+        return Err(Error::DeploymentFailed);
+    }
+    Ok(())
+}
+
+/// Internal helper function to deploy and initialize a LOP contract
+fn deploy_lop_internal(
+    env: &Env,
+    lop_wasm_hash: BytesN<32>,
+    salt: BytesN<32>,
+    admin: &Address,
+    dutch_auction_address: &Address,
+) -> Result<Address, Error> {
+    // Deploy the LOP contract deterministically
+    let lop_address = env
+        .deployer()
+        .with_address(env.current_contract_address(), salt)
+        .deploy_v2(lop_wasm_hash, ());
+
+    // Create client and initialize the deployed LOP
+    let lop_client = lop::Client::new(env, &lop_address);
+
+    // Initialize the LOP contract with a 0 bps fill fee for the admin
+    match lop_client.try_initialize(admin, dutch_auction_address, &0, admin) {
+        Ok(_) => {},
+        Err(_) => return Err(Error::DeploymentFailed),
+    }
+
+    // Emit deployment event
+    env.events().publish(("deploy_lop",), &lop_address);
+
+    Ok(lop_address)
+}
+
+/// Internal helper function to deploy Dutch auction contract
+fn deploy_dutch_auction_internal(env: &Env, salt: BytesN<32>) -> Result<Address, Error> {
+    // Get the stored WASM hash
+    let dutch_auction_wasm_hash: BytesN<32> = env
+        .storage()
+        .instance()
+        .get(&DataKey::DutchAuctionWasmHash)
+        .ok_or(Error::NotInitialized)?;
+
+    // Deploy the contract deterministically
+    let dutch_auction_address = env
+        .deployer()
+        .with_address(env.current_contract_address(), salt)
+        .deploy_v2(dutch_auction_wasm_hash, ());
+
+    // Emit deployment event
+    env.events().publish(("deploy_dutch_auction",), &dutch_auction_address);
+
+    Ok(dutch_auction_address)
+}
+
 mod test;
\ No newline at end of file
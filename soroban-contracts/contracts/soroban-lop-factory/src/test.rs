@@ -1,149 +1,500 @@
-#![cfg(test)]
-extern crate std;
-
-use super::*;
-use soroban_sdk::{
-    testutils::{Address as _, Ledger},
-    Address, Env,
-};
-
-// Import the actual contract WASMs for testing
-const LOP_WASM: &[u8] = include_bytes!("../../../target/wasm32v1-none/release/soroban_lop_contract.wasm");
-const DUTCH_AUCTION_WASM: &[u8] = include_bytes!("../../../target/wasm32v1-none/release/soroban_dutch_auction_contract.wasm");
-
-fn create_factory_contract(e: &Env) -> SorobanLOPFactoryClient {
-    SorobanLOPFactoryClient::new(e, &e.register(SorobanLOPFactory, ()))
-}
-
-#[test]
-fn test_initialize() {
-    let env = Env::default();
-    let factory = create_factory_contract(&env);
-    let admin = Address::generate(&env);
-    let lop_wasm_hash = BytesN::from_array(&env, &[1; 32]);
-    let dutch_auction_wasm_hash = BytesN::from_array(&env, &[2; 32]);
-
-    // Should initialize successfully
-    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
-    
-    // Should fail to initialize again
-    assert_eq!(
-        factory.try_initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash),
-        Err(Ok(Error::AlreadyInitialized))
-    );
-
-    // Check stored values
-    assert_eq!(factory.get_admin(), admin);
-    assert_eq!(factory.get_lop_wasm_hash(), lop_wasm_hash);
-    assert_eq!(factory.get_dutch_auction_wasm_hash(), dutch_auction_wasm_hash);
-}
-
-#[test]
-fn test_deploy_lop() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set up factory
-    let factory = create_factory_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Upload the contract WASMs
-    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
-    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
-    
-    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
-
-    let salt = BytesN::from_array(&env, &[42; 32]);
-    let lop_admin = Address::generate(&env);
-
-    // Deploy LOP
-    let lop_address = factory.deploy_lop(&salt, &lop_admin);
-    
-    // Verify the LOP was deployed and initialized
-    let lop_client = lop::Client::new(&env, &lop_address);
-    
-    assert_eq!(lop_client.get_admin(), lop_admin);
-}
-
-#[test]
-fn test_deploy_dutch_auction() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set up factory
-    let factory = create_factory_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Upload the contract WASMs
-    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
-    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
-    
-    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
-
-    let salt = BytesN::from_array(&env, &[42; 32]);
-
-    // Deploy Dutch auction
-    let dutch_auction_address = factory.deploy_dutch_auction(&salt);
-    
-    // Verify the contract was deployed
-    let dutch_auction_client = dutch_auction::Client::new(&env, &dutch_auction_address);
-    
-    // Test that it works - set a timestamp first
-    env.ledger().with_mut(|li| { li.timestamp = 1500; });
-    let result = dutch_auction_client.calculate_taking_amount(
-        &100, &1000, &500, &1000, &2000
-    );
-    assert!(result > 0); // Should return a valid amount
-}
-
-#[test]
-fn test_get_addresses() {
-    let env = Env::default();
-    let factory = create_factory_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Upload the contract WASMs
-    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
-    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
-    
-    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
-
-    let lop_salt = BytesN::from_array(&env, &[42; 32]);
-    let dutch_auction_salt = BytesN::from_array(&env, &[43; 32]); // Use different salt
-
-    // Get predicted addresses
-    let predicted_lop_address = factory.get_lop_address(&lop_salt);
-    let predicted_dutch_auction_address = factory.get_dutch_auction_address(&dutch_auction_salt);
-
-    // Deploy contracts with respective salts
-    let lop_admin = Address::generate(&env);
-    let actual_lop_address = factory.deploy_lop(&lop_salt, &lop_admin);
-    let actual_dutch_auction_address = factory.deploy_dutch_auction(&dutch_auction_salt);
-
-    // Addresses should match predictions
-    assert_eq!(predicted_lop_address, actual_lop_address);
-    assert_eq!(predicted_dutch_auction_address, actual_dutch_auction_address);
-}
-
-#[test]
-fn test_update_wasm_hashes() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let factory = create_factory_contract(&env);
-    let admin = Address::generate(&env);
-    let initial_lop_wasm_hash = BytesN::from_array(&env, &[1; 32]);
-    let initial_dutch_auction_wasm_hash = BytesN::from_array(&env, &[2; 32]);
-    let new_lop_wasm_hash = BytesN::from_array(&env, &[3; 32]);
-    let new_dutch_auction_wasm_hash = BytesN::from_array(&env, &[4; 32]);
-
-    factory.initialize(&admin, &initial_lop_wasm_hash, &initial_dutch_auction_wasm_hash);
-    
-    // Update WASM hashes
-    factory.update_lop_wasm_hash(&new_lop_wasm_hash);
-    factory.update_dutch_auction_wasm_hash(&new_dutch_auction_wasm_hash);
-    
-    // Verify updates
-    assert_eq!(factory.get_lop_wasm_hash(), new_lop_wasm_hash);
-    assert_eq!(factory.get_dutch_auction_wasm_hash(), new_dutch_auction_wasm_hash);
-}
\ No newline at end of file
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    testutils::{Address as _, Events, Ledger},
+    vec, Address, Env, IntoVal,
+};
+
+// Import the actual contract WASMs for testing
+const LOP_WASM: &[u8] = include_bytes!("../../../target/wasm32v1-none/release/soroban_lop_contract.wasm");
+const DUTCH_AUCTION_WASM: &[u8] = include_bytes!("../../../target/wasm32v1-none/release/soroban_dutch_auction_contract.wasm");
+
+// LOP stand-in that records the last fill routed to it, tracks how much of each order
+// (by salt) is filled, and enforces the remainder and slippage bound
+#[contract]
+pub struct MockLop;
+
+#[contractimpl]
+impl MockLop {
+    pub fn fill_order(
+        env: Env,
+        order: lop::Order,
+        taker: Address,
+        requested_making_amount: i128,
+        max_taking_amount: i128,
+    ) -> (i128, i128) {
+        let remaining = Self::get_remaining_making_amount(env.clone(), order.clone());
+        if requested_making_amount <= 0 || requested_making_amount > remaining {
+            panic!("invalid fill amount");
+        }
+        let taking_amount = order.taking_amount * requested_making_amount / order.making_amount;
+        if taking_amount > max_taking_amount {
+            panic!("slippage exceeded");
+        }
+        env.storage().instance().set(
+            &(Symbol::new(&env, "filled"), order.salt),
+            &(order.making_amount - remaining + requested_making_amount),
+        );
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "last_fill"), &(taker, order.salt, requested_making_amount));
+        (requested_making_amount, taking_amount)
+    }
+
+    pub fn get_remaining_making_amount(env: Env, order: lop::Order) -> i128 {
+        let filled: i128 = env
+            .storage()
+            .instance()
+            .get(&(Symbol::new(&env, "filled"), order.salt))
+            .unwrap_or(0);
+        order.making_amount - filled
+    }
+
+    pub fn last_routed_fill(env: Env) -> Option<(Address, u64, i128)> {
+        env.storage().instance().get(&Symbol::new(&env, "last_fill"))
+    }
+}
+
+fn create_order(env: &Env, salt: u64, making_amount: i128, taking_amount: i128) -> lop::Order {
+    let maker = Address::generate(env);
+    lop::Order {
+        salt,
+        maker: maker.clone(),
+        receiver: maker,
+        maker_asset: Address::generate(env),
+        taker_asset: Address::generate(env),
+        making_amount,
+        taking_amount,
+        maker_traits: 0,
+        auction_start_time: 0,
+        auction_end_time: 0,
+        auction_duration: 0,
+        taking_amount_start: 0,
+        taking_amount_end: 0,
+        allowed_takers: Vec::new(env),
+        post_fill_hook: None,
+        post_fill_fn: None,
+        settle_token: None,
+        peg_rate: 0,
+        expiration: 0,
+        pre_interaction: None,
+        post_interaction: None,
+        min_fill_making_amount: 0,
+    }
+}
+
+fn create_factory_contract(e: &Env) -> SorobanLOPFactoryClient<'_> {
+    SorobanLOPFactoryClient::new(e, &e.register(SorobanLOPFactory, ()))
+}
+
+#[test]
+fn test_initialize() {
+    let env = Env::default();
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    let lop_wasm_hash = BytesN::from_array(&env, &[1; 32]);
+    let dutch_auction_wasm_hash = BytesN::from_array(&env, &[2; 32]);
+
+    // Should initialize successfully
+    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
+    
+    // Should fail to initialize again
+    assert_eq!(
+        factory.try_initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+
+    // Check stored values
+    assert_eq!(factory.get_admin(), admin);
+    assert_eq!(factory.get_lop_wasm_hash(), lop_wasm_hash);
+    assert_eq!(factory.get_dutch_auction_wasm_hash(), dutch_auction_wasm_hash);
+}
+
+#[test]
+fn test_deploy_lop() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set up factory
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Upload the contract WASMs
+    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
+    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
+    
+    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
+
+    let salt = BytesN::from_array(&env, &[42; 32]);
+    let lop_admin = Address::generate(&env);
+
+    // Deploy LOP
+    let lop_address = factory.deploy_lop(&salt, &lop_admin);
+    
+    // Verify the LOP was deployed and initialized
+    let lop_client = lop::Client::new(&env, &lop_address);
+    
+    assert_eq!(lop_client.get_admin(), lop_admin);
+}
+
+#[test]
+fn test_deploy_dutch_auction() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set up factory
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Upload the contract WASMs
+    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
+    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
+    
+    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
+
+    let salt = BytesN::from_array(&env, &[42; 32]);
+
+    // Deploy Dutch auction
+    let dutch_auction_address = factory.deploy_dutch_auction(&salt);
+    
+    // Verify the contract was deployed
+    let dutch_auction_client = dutch_auction::Client::new(&env, &dutch_auction_address);
+    
+    // Test that it works - set a timestamp first
+    env.ledger().with_mut(|li| { li.timestamp = 1500; });
+    let result = dutch_auction_client.calculate_taking_amount(
+        &100, &1000, &500, &1000, &2000
+    );
+    assert!(result > 0); // Should return a valid amount
+}
+
+#[test]
+fn test_get_addresses() {
+    let env = Env::default();
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Upload the contract WASMs
+    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
+    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
+    
+    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
+
+    let lop_salt = BytesN::from_array(&env, &[42; 32]);
+    let dutch_auction_salt = BytesN::from_array(&env, &[43; 32]); // Use different salt
+
+    // Get predicted addresses
+    let predicted_lop_address = factory.get_lop_address(&lop_salt);
+    let predicted_dutch_auction_address = factory.get_dutch_auction_address(&dutch_auction_salt);
+
+    // Deploy contracts with respective salts
+    let lop_admin = Address::generate(&env);
+    let actual_lop_address = factory.deploy_lop(&lop_salt, &lop_admin);
+    let actual_dutch_auction_address = factory.deploy_dutch_auction(&dutch_auction_salt);
+
+    // Addresses should match predictions
+    assert_eq!(predicted_lop_address, actual_lop_address);
+    assert_eq!(predicted_dutch_auction_address, actual_dutch_auction_address);
+}
+
+#[test]
+fn test_update_wasm_hashes() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    let initial_lop_wasm_hash = BytesN::from_array(&env, &[1; 32]);
+    let initial_dutch_auction_wasm_hash = BytesN::from_array(&env, &[2; 32]);
+    let new_lop_wasm_hash = BytesN::from_array(&env, &[3; 32]);
+    let new_dutch_auction_wasm_hash = BytesN::from_array(&env, &[4; 32]);
+
+    factory.initialize(&admin, &initial_lop_wasm_hash, &initial_dutch_auction_wasm_hash);
+    
+    // Update WASM hashes
+    factory.update_lop_wasm_hash(&admin, &new_lop_wasm_hash);
+    factory.update_dutch_auction_wasm_hash(&admin, &new_dutch_auction_wasm_hash);
+    
+    // Verify updates
+    assert_eq!(factory.get_lop_wasm_hash(), new_lop_wasm_hash);
+    assert_eq!(factory.get_dutch_auction_wasm_hash(), new_dutch_auction_wasm_hash);
+}
+#[test]
+fn test_admin_action_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    let new_lop_wasm_hash = BytesN::from_array(&env, &[3; 32]);
+    let new_dutch_auction_wasm_hash = BytesN::from_array(&env, &[4; 32]);
+
+    factory.initialize(
+        &admin,
+        &BytesN::from_array(&env, &[1; 32]),
+        &BytesN::from_array(&env, &[2; 32]),
+    );
+
+    // Each admin method publishes its own admin_action event
+    factory.update_lop_wasm_hash(&admin, &new_lop_wasm_hash);
+    assert_eq!(
+        env.events().all(),
+        vec![
+            &env,
+            (
+                factory.address.clone(),
+                ("admin_action", Symbol::new(&env, "update_lop_wasm_hash")).into_val(&env),
+                new_lop_wasm_hash.into_val(&env),
+            ),
+        ]
+    );
+
+    factory.update_dutch_auction_wasm_hash(&admin, &new_dutch_auction_wasm_hash);
+    assert_eq!(
+        env.events().all(),
+        vec![
+            &env,
+            (
+                factory.address.clone(),
+                ("admin_action", Symbol::new(&env, "update_dutch_auction_wasm_hash")).into_val(&env),
+                new_dutch_auction_wasm_hash.into_val(&env),
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_operator_can_update_wasm_hash() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    let operator = Address::generate(&env);
+    let new_lop_wasm_hash = BytesN::from_array(&env, &[3; 32]);
+
+    factory.initialize(
+        &admin,
+        &BytesN::from_array(&env, &[1; 32]),
+        &BytesN::from_array(&env, &[2; 32]),
+    );
+
+    // Unknown addresses have no privileges
+    assert_eq!(
+        factory.try_update_lop_wasm_hash(&operator, &new_lop_wasm_hash),
+        Err(Ok(Error::NotAuthorized))
+    );
+
+    factory.add_operator(&admin, &operator);
+    assert_eq!(factory.get_role(&operator), Some(Role::Operator));
+
+    // Operators can update WASM hashes and pause
+    factory.update_lop_wasm_hash(&operator, &new_lop_wasm_hash);
+    assert_eq!(factory.get_lop_wasm_hash(), new_lop_wasm_hash);
+
+    factory.set_paused(&operator, &true);
+    assert!(factory.is_paused());
+    assert_eq!(
+        factory.try_deploy_dutch_auction(&BytesN::from_array(&env, &[42; 32])),
+        Err(Ok(Error::Paused))
+    );
+
+    // Removed operators lose their privileges
+    factory.remove_operator(&admin, &operator);
+    assert_eq!(factory.get_role(&operator), None);
+    assert_eq!(
+        factory.try_set_paused(&operator, &false),
+        Err(Ok(Error::NotAuthorized))
+    );
+}
+
+#[test]
+fn test_operator_cannot_transfer_super_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+    let operator = Address::generate(&env);
+    let new_admin = Address::generate(&env);
+
+    factory.initialize(
+        &admin,
+        &BytesN::from_array(&env, &[1; 32]),
+        &BytesN::from_array(&env, &[2; 32]),
+    );
+    factory.add_operator(&admin, &operator);
+
+    // Operators cannot transfer the super admin role or manage operators
+    assert_eq!(
+        factory.try_transfer_super_admin(&operator, &operator),
+        Err(Ok(Error::NotAuthorized))
+    );
+    assert_eq!(
+        factory.try_add_operator(&operator, &new_admin),
+        Err(Ok(Error::NotAuthorized))
+    );
+    assert_eq!(factory.get_admin(), admin);
+
+    // The super admin can hand the role over
+    factory.transfer_super_admin(&admin, &new_admin);
+    assert_eq!(factory.get_admin(), new_admin);
+    assert_eq!(factory.get_role(&new_admin), Some(Role::SuperAdmin));
+    assert_eq!(factory.get_role(&admin), None);
+}
+
+#[test]
+fn test_route_fill_across_lops() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let factory = create_factory_contract(&env);
+    let taker = Address::generate(&env);
+    let lop_a = env.register(MockLop, ());
+    let lop_b = env.register(MockLop, ());
+
+    let order_a = create_order(&env, 1, 1000, 2000);
+    let order_b = create_order(&env, 2, 500, 700);
+
+    // Each LOP receives its own order
+    let results = factory.route_fill(
+        &vec![
+            &env,
+            (lop_a.clone(), order_a.clone(), 2000_i128),
+            (lop_b.clone(), order_b.clone(), i128::MAX),
+        ],
+        &taker,
+    );
+    assert_eq!(results, vec![&env, (1000_i128, 2000_i128), (500_i128, 700_i128)]);
+    assert_eq!(
+        MockLopClient::new(&env, &lop_a).last_routed_fill(),
+        Some((taker.clone(), 1, 1000))
+    );
+    assert_eq!(
+        MockLopClient::new(&env, &lop_b).last_routed_fill(),
+        Some((taker.clone(), 2, 500))
+    );
+
+    // One failing fill reverts the whole batch
+    let order_c = create_order(&env, 3, 100, 300);
+    assert_eq!(
+        factory.try_route_fill(
+            &vec![
+                &env,
+                (lop_a.clone(), order_c.clone(), 300_i128),
+                (lop_b.clone(), order_c.clone(), 299_i128),
+            ],
+            &taker,
+        ),
+        Err(Ok(Error::FillFailed))
+    );
+    assert_eq!(
+        MockLopClient::new(&env, &lop_a).last_routed_fill(),
+        Some((taker.clone(), 1, 1000))
+    );
+}
+
+#[test]
+fn test_route_fill_takes_remainder_of_partially_filled_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let factory = create_factory_contract(&env);
+    let taker = Address::generate(&env);
+    let lop_address = env.register(MockLop, ());
+    let lop_client = MockLopClient::new(&env, &lop_address);
+
+    // Another taker already took 400 of 1000 directly on the LOP
+    let order = create_order(&env, 1, 1000, 2000);
+    lop_client.fill_order(&order, &Address::generate(&env), &400, &i128::MAX);
+    assert_eq!(lop_client.get_remaining_making_amount(&order), 600);
+
+    // The router fills the 600 left at the pro-rata price
+    let results = factory.route_fill(&vec![&env, (lop_address.clone(), order.clone(), 1200_i128)], &taker);
+    assert_eq!(results, vec![&env, (600_i128, 1200_i128)]);
+    assert_eq!(lop_client.last_routed_fill(), Some((taker.clone(), 1, 600)));
+    assert_eq!(lop_client.get_remaining_making_amount(&order), 0);
+
+    // Nothing is left to route
+    assert_eq!(
+        factory.try_route_fill(&vec![&env, (lop_address, order, i128::MAX)], &taker),
+        Err(Ok(Error::FillFailed))
+    );
+}
+
+#[test]
+fn test_deploy_lops_with_shared_auction() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Upload the contract WASMs
+    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
+    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
+
+    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
+
+    // The shared auction address is known before the first LOP deploys it
+    let predicted_auction_address = factory.get_shared_auction_address();
+
+    let lop_admin = Address::generate(&env);
+    let lop_a = lop::Client::new(
+        &env,
+        &factory.deploy_lop_shared(&BytesN::from_array(&env, &[1; 32]), &lop_admin),
+    );
+    let lop_b = lop::Client::new(
+        &env,
+        &factory.deploy_lop_shared(&BytesN::from_array(&env, &[2; 32]), &lop_admin),
+    );
+
+    assert_eq!(lop_a.get_shared_auction(), predicted_auction_address);
+    assert_eq!(lop_b.get_shared_auction(), predicted_auction_address);
+
+    // The shared auction's salt can't be taken by a standalone deployment
+    assert_eq!(
+        factory.try_deploy_dutch_auction(&BytesN::from_array(&env, &[0xff; 32])),
+        Err(Ok(Error::DeploymentFailed))
+    );
+}
+
+#[test]
+fn test_shared_auction_salt_cannot_be_squatted() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let factory = create_factory_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Upload the contract WASMs
+    let lop_wasm_hash = env.deployer().upload_contract_wasm(LOP_WASM);
+    let dutch_auction_wasm_hash = env.deployer().upload_contract_wasm(DUTCH_AUCTION_WASM);
+
+    factory.initialize(&admin, &lop_wasm_hash, &dutch_auction_wasm_hash);
+
+    // No deploy entry point takes the reserved salt before the shared auction exists
+    let reserved_salt = BytesN::from_array(&env, &[0xff; 32]);
+    let lop_admin = Address::generate(&env);
+    assert_eq!(
+        factory.try_deploy_lop(&reserved_salt, &lop_admin),
+        Err(Ok(Error::DeploymentFailed))
+    );
+    assert_eq!(
+        factory.try_deploy_lop_shared(&reserved_salt, &lop_admin),
+        Err(Ok(Error::DeploymentFailed))
+    );
+    assert_eq!(
+        factory.try_deploy_dutch_auction(&reserved_salt),
+        Err(Ok(Error::DeploymentFailed))
+    );
+
+    // So the first shared LOP still gets the shared auction at its predicted address
+    let lop = lop::Client::new(
+        &env,
+        &factory.deploy_lop_shared(&BytesN::from_array(&env, &[1; 32]), &lop_admin),
+    );
+    assert_eq!(lop.get_shared_auction(), factory.get_shared_auction_address());
+}
@@ -1,293 +1,2777 @@
-#![no_std]
-use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, token, Address, BytesN, Env,
-};
-
-// Import the Dutch auction contract
-mod dutch_auction {
-    soroban_sdk::contractimport!(
-        file = "../../target/wasm32v1-none/release/soroban_dutch_auction_contract.wasm"
-    );
-}
-
-#[contracttype]
-pub enum DataKey {
-    OrderState(BytesN<32>), // order_hash -> OrderState
-    DutchAuctionContract,
-    Admin,
-}
-
-#[contracttype]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Order {
-    pub salt: u64,
-    pub maker: Address,
-    pub receiver: Address,
-    pub maker_asset: Address,
-    pub taker_asset: Address,
-    pub making_amount: i128,
-    pub taking_amount: i128,
-    pub maker_traits: u64,
-    // Dutch auction parameters (only used if IS_DUTCH_AUCTION flag is set)
-    pub auction_start_time: u64,
-    pub auction_end_time: u64,
-    pub taking_amount_start: i128,
-    pub taking_amount_end: i128,
-}
-
-#[contracttype]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub enum OrderState {
-    Active,
-    Filled,
-    Cancelled,
-}
-
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-#[repr(u32)]
-pub enum Error {
-    NotInitialized = 1,
-    AlreadyInitialized = 2,
-    NotAuthorized = 3,
-    OrderAlreadyFilled = 4,
-    OrderCancelled = 5,
-    InsufficientBalance = 6,
-    InvalidOrder = 7,
-    DutchAuctionError = 8,
-    TransferFailed = 9,
-}
-
-// Maker traits flags
-const IS_DUTCH_AUCTION: u64 = 1 << 0;
-const UNWRAP_WETH: u64 = 1 << 1;
-const ALLOW_PARTIAL_FILLS: u64 = 1 << 2;
-
-#[contract]
-pub struct SorobanLOP;
-
-#[contractimpl]
-impl SorobanLOP {
-    /// Initialize the LOP contract
-    pub fn initialize(
-        env: Env,
-        admin: Address,
-        dutch_auction_contract: Address,
-    ) -> Result<(), Error> {
-        // Check if already initialized
-        if env.storage().instance().has(&DataKey::Admin) {
-            return Err(Error::AlreadyInitialized);
-        }
-
-        // Store admin and Dutch auction contract address
-        env.storage().instance().set(&DataKey::Admin, &admin);
-        env.storage().instance().set(&DataKey::DutchAuctionContract, &dutch_auction_contract);
-
-        Ok(())
-    }
-
-    /// Fill an order
-    pub fn fill_order(
-        env: Env,
-        order: Order,
-        taker: Address,
-    ) -> Result<(), Error> {
-        // Require authorization from taker
-        taker.require_auth();
-
-        // Calculate order hash
-        let order_hash = Self::calculate_order_hash(&env, &order);
-
-        // Check order state
-        let order_state: OrderState = env
-            .storage()
-            .persistent()
-            .get(&DataKey::OrderState(order_hash.clone()))
-            .unwrap_or(OrderState::Active);
-
-        match order_state {
-            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
-            OrderState::Cancelled => return Err(Error::OrderCancelled),
-            OrderState::Active => {},
-        }
-
-        // Require authorization from maker for their assets
-        order.maker.require_auth();
-
-        // Calculate actual amounts
-        let (actual_making_amount, actual_taking_amount) = if Self::is_dutch_auction(&order) {
-            // Get Dutch auction contract
-            let dutch_auction_contract: Address = env
-                .storage()
-                .instance()
-                .get(&DataKey::DutchAuctionContract)
-                .ok_or(Error::NotInitialized)?;
-
-            let dutch_auction_client = dutch_auction::Client::new(&env, &dutch_auction_contract);
-
-            // Calculate current taking amount based on time
-            let calculated_taking_amount = dutch_auction_client
-                .calculate_taking_amount(
-                    &order.making_amount,
-                    &order.taking_amount_start,
-                    &order.taking_amount_end,
-                    &order.auction_start_time,
-                    &order.auction_end_time,
-                );
-
-            (order.making_amount, calculated_taking_amount)
-        } else {
-            // Regular order - use fixed amounts
-            (order.making_amount, order.taking_amount)
-        };
-
-        // Validate amounts are positive
-        if actual_making_amount <= 0 || actual_taking_amount <= 0 {
-            return Err(Error::InvalidOrder);
-        }
-
-        // Determine receiver (use order.receiver if specified, otherwise use taker)
-        let receiver = if order.receiver == env.current_contract_address() {
-            taker.clone()
-        } else {
-            order.receiver.clone()
-        };
-
-        // Execute token transfers
-        // Transfer maker asset from maker to receiver
-        let maker_token = token::Client::new(&env, &order.maker_asset);
-        maker_token.transfer(&order.maker, &receiver, &actual_making_amount);
-
-        // Transfer taker asset from taker to maker
-        let taker_token = token::Client::new(&env, &order.taker_asset);
-        taker_token.transfer(&taker, &order.maker, &actual_taking_amount);
-
-        // Mark order as filled
-        env.storage()
-            .persistent()
-            .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Filled);
-
-        // Extend TTL for the order state
-        env.storage()
-            .persistent()
-            .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
-
-        // Emit event
-        env.events().publish(
-            ("order_filled",),
-            (order_hash, actual_making_amount, actual_taking_amount),
-        );
-
-        Ok(())
-    }
-
-    /// Cancel an order (only by maker)
-    pub fn cancel_order(env: Env, order: Order) -> Result<(), Error> {
-        // Require authorization from maker
-        order.maker.require_auth();
-
-        // Calculate order hash
-        let order_hash = Self::calculate_order_hash(&env, &order);
-
-        // Check current state
-        let current_state: OrderState = env
-            .storage()
-            .persistent()
-            .get(&DataKey::OrderState(order_hash.clone()))
-            .unwrap_or(OrderState::Active);
-
-        match current_state {
-            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
-            OrderState::Cancelled => return Err(Error::OrderCancelled),
-            OrderState::Active => {},
-        }
-
-        // Mark order as cancelled
-        env.storage()
-            .persistent()
-            .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Cancelled);
-
-        // Extend TTL
-        env.storage()
-            .persistent()
-            .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
-
-        // Emit event
-        env.events().publish(("order_cancelled",), order_hash);
-
-        Ok(())
-    }
-
-    /// Get order state
-    pub fn get_order_state(env: Env, order: Order) -> OrderState {
-        let order_hash = Self::calculate_order_hash(&env, &order);
-        env.storage()
-            .persistent()
-            .get(&DataKey::OrderState(order_hash))
-            .unwrap_or(OrderState::Active)
-    }
-
-    /// Get current Dutch auction price for an order
-    pub fn get_current_price(env: Env, order: Order) -> Result<i128, Error> {
-        if !Self::is_dutch_auction(&order) {
-            return Ok(order.taking_amount);
-        }
-
-        let dutch_auction_contract: Address = env
-            .storage()
-            .instance()
-            .get(&DataKey::DutchAuctionContract)
-            .ok_or(Error::NotInitialized)?;
-
-        let dutch_auction_client = dutch_auction::Client::new(&env, &dutch_auction_contract);
-
-        let price = dutch_auction_client
-            .calculate_taking_amount(
-                &order.making_amount,
-                &order.taking_amount_start,
-                &order.taking_amount_end,
-                &order.auction_start_time,
-                &order.auction_end_time,
-            );
-
-        Ok(price)
-    }
-
-    /// Helper function to check if order is a Dutch auction
-    fn is_dutch_auction(order: &Order) -> bool {
-        order.maker_traits & IS_DUTCH_AUCTION != 0
-    }
-
-    /// Calculate order hash (simplified version)
-    fn calculate_order_hash(env: &Env, order: &Order) -> BytesN<32> {
-        // Create a simple hash of the order data by concatenating bytes
-        let mut data = soroban_sdk::Bytes::new(env);
-        
-        // Convert each field to bytes and append
-        data.extend_from_slice(&order.salt.to_be_bytes());
-        data.extend_from_slice(&order.making_amount.to_be_bytes());
-        data.extend_from_slice(&order.taking_amount.to_be_bytes());
-        data.extend_from_slice(&order.maker_traits.to_be_bytes());
-        
-        // Simple hash without complex string conversion
-        env.crypto().sha256(&data).into()
-    }
-
-    /// Get admin address
-    pub fn get_admin(env: Env) -> Result<Address, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::Admin)
-            .ok_or(Error::NotInitialized)
-    }
-
-    /// Get Dutch auction contract address
-    pub fn get_dutch_auction_contract(env: Env) -> Result<Address, Error> {
-        env.storage()
-            .instance()
-            .get(&DataKey::DutchAuctionContract)
-            .ok_or(Error::NotInitialized)
-    }
-}
-
+#![no_std]
+// `contractimpl` generates an argument builder per entry point outside the impl block,
+// so a wide entry point like `build_maker_traits` can't scope this to the function
+#![allow(clippy::too_many_arguments)]
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, token, xdr::ToXdr, Address, Bytes, BytesN, Env,
+    IntoVal, Symbol, Vec,
+};
+
+// Import the Dutch auction contract
+#[allow(clippy::too_many_arguments)]
+mod dutch_auction {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/soroban_dutch_auction_contract.wasm"
+    );
+}
+
+#[contracttype]
+pub enum DataKey {
+    OrderState(BytesN<32>), // order_hash -> OrderState
+    DutchAuctionContract,
+    Admin,
+    LockedFor(BytesN<32>), // order_hash -> bool (maker funds held by the LOP)
+    CreationDepositConfig,
+    CreationDeposit(BytesN<32>), // order_hash -> Deposit taken at create_order
+    RemainingAmount(BytesN<32>), // order_hash -> unfilled making amount (partial fills)
+    DefaultReceiver(Address), // maker -> receiver used when order.receiver is unset
+    FillBucketSize,
+    FillsInWindow(u64), // timestamp / bucket_size -> number of fills
+    MaxActiveOrdersPerMaker,
+    ActiveOrderCount(Address), // maker -> orders created and not yet filled or cancelled
+    FeeConfig,
+    ReferralFeeConfig,
+    DustThreshold(Address), // taker asset -> smallest remainder (in taker asset) a partial fill may leave
+    FeeExempt(Address), // maker or taker -> bool (no protocol fee on their fills)
+    NativeToken, // Stellar asset contract of native XLM, for UNWRAP_WETH orders
+    FillFeeConfig, // Set at initialize; deducted from the maker's proceeds
+    MakerOrders(Address), // maker -> hashes of touched orders not yet filled or cancelled
+    SaltIndex(Address, u64), // (maker, salt) -> order_hash of a created order
+    MaxTakingAmountStart, // Upper bound on a Dutch order's taking_amount_start (0 = no cap)
+    Paused, // bool; fills are rejected while set
+    AccruedFees(Address), // token -> fees held by the LOP and not yet collected
+    AuctionStart(BytesN<32>), // order_hash -> timestamp a RELATIVE_AUCTION order's clock started
+    OrderTimestamps(BytesN<32>), // order_hash -> OrderTimestamps
+    OrderMaker(BytesN<32>), // order_hash -> maker, recorded the first time the order is touched
+    FeeTiers, // Vec<(min_making_amount, bps)> sorted by min_making_amount; overrides the protocol fee bps
+    LockedRemaining(BytesN<32>), // order_hash -> locked maker funds not yet released to fills
+    FillAllowance(BytesN<32>, Address), // (order_hash, taker) -> making amount the taker may still fill
+    HasFillAllowances(BytesN<32>), // order_hash -> bool (fills are limited to allocated takers)
+    StrictAuctionFields, // bool; fills also reject non-Dutch orders carrying auction fields
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Deposit {
+    pub token: Address,
+    pub amount: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeConfig {
+    pub fee_bps: u32,           // Basis points of the taking amount
+    pub fee_recipient: Address,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OrderTimestamps {
+    pub created_at: u64,    // create_order (0 for orders filled without being created)
+    pub first_fill_at: u64, // First fill, partial or full
+    pub completed_at: u64,  // Fill that took the last of the order
+}
+
+// Define the escrow Timelocks struct locally to match the escrow contract exactly
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EscrowTimelocks {
+    pub deployed_at: u64, // Stage offsets below are seconds after this timestamp
+    pub withdrawal: u64, // Finality lock ends; the taker can withdraw
+    pub public_withdrawal: u64, // Anyone with the secret can push funds to the taker
+    pub cancellation: u64, // Withdrawals close; the maker can cancel
+    pub public_cancellation: u64, // Anyone can cancel, refunding the maker
+}
+
+// Define the escrow Immutables struct locally to match the escrow contract exactly
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EscrowImmutables {
+    pub hashlock: BytesN<32>,
+    pub maker: Address,
+    pub taker: Address,
+    pub token: Address,
+    pub amount: i128,
+    pub timelocks: EscrowTimelocks,
+    pub safety_deposit: i128, // Paid in deposit_token to whoever finalizes the escrow
+    pub deposit_token: Address,
+    pub hash_algorithm: u32, // Hash applied to secrets: 0 = sha256, 1 = keccak256 (EVM-compatible)
+    pub arbiter: Option<Address>, // When set, must also approve every release to the taker
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FullQuote {
+    pub taking_amount: i128,  // Paid to the maker
+    pub protocol_fee: i128,
+    pub referral_fee: i128,
+    pub total_outlay: i128,   // Everything the taker pays
+    pub fill_fee: i128,       // Withheld from the taking amount
+    pub maker_receives: i128, // Taking amount less the fill fee
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Order {
+    pub salt: u64,
+    pub maker: Address,
+    pub receiver: Address,
+    pub maker_asset: Address,
+    pub taker_asset: Address,
+    pub making_amount: i128,
+    pub taking_amount: i128,
+    pub maker_traits: u64,
+    // Dutch auction parameters (only used if IS_DUTCH_AUCTION flag is set)
+    pub auction_start_time: u64,
+    pub auction_end_time: u64,
+    pub auction_duration: u64, // RELATIVE_AUCTION: window length from the first fill
+    pub taking_amount_start: i128,
+    pub taking_amount_end: i128,
+    // Takers allowed to fill (only used if HAS_ALLOWED_TAKERS flag is set)
+    pub allowed_takers: Vec<Address>,
+    // Contract and function called after each fill (both must be set to enable the hook)
+    pub post_fill_hook: Option<Address>,
+    pub post_fill_fn: Option<Symbol>,
+    // Alternate token the taker may pay in, at `peg_rate` settle units per taker unit (scaled by 1e7)
+    pub settle_token: Option<Address>,
+    pub peg_rate: i128,
+    pub expiration: u64, // Fills are rejected at or after this timestamp (0 = never expires)
+    // Contracts called around the token transfers (used if HAS_PRE_INTERACTION / HAS_POST_INTERACTION is set)
+    pub pre_interaction: Option<Address>,
+    pub post_interaction: Option<Address>,
+    pub min_fill_making_amount: i128, // Smallest partial fill, unless it takes the whole remainder (0 = no minimum)
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OrderState {
+    Active,
+    Filled,
+    Cancelled,
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    NotAuthorized = 3,
+    OrderAlreadyFilled = 4,
+    OrderCancelled = 5,
+    InsufficientBalance = 6,
+    InvalidOrder = 7,
+    DutchAuctionError = 8,
+    TransferFailed = 9,
+    InvalidDeposit = 10,
+    SenderNotAllowed = 11,
+    AuctionEnded = 12,
+    TooManyActiveOrders = 13,
+    InvalidFee = 14,
+    UnwrapFailed = 15,
+    OrderExpired = 16,
+    EscrowDeploymentFailed = 17,
+    OrderNotFound = 18,
+    Paused = 19,
+    ReceiverRejected = 20,
+    FillTooSmall = 21,
+    SlippageExceeded = 22,
+    IdenticalAssets = 23,
+    AllowanceExceeded = 24,
+}
+
+// Maker traits flags
+const IS_DUTCH_AUCTION: u64 = 1 << 0;
+const UNWRAP_WETH: u64 = 1 << 1;
+const ALLOW_PARTIAL_FILLS: u64 = 1 << 2;
+const HAS_ALLOWED_TAKERS: u64 = 1 << 3;
+const CUTOFF_AT_AUCTION_END: u64 = 1 << 4;
+const HAS_PRE_INTERACTION: u64 = 1 << 5;
+const HAS_POST_INTERACTION: u64 = 1 << 6;
+const RELATIVE_AUCTION: u64 = 1 << 14;
+
+// Fixed-point scale of an order's settle token peg rate
+const PEG_RATE_SCALE: i128 = 10_000_000;
+
+// Denominator for basis-point amounts
+const BPS_DENOMINATOR: i128 = 10_000;
+
+// Byte offset of the ed25519 key in the XDR of an account address
+const ACCOUNT_KEY_XDR_OFFSET: u32 = 12;
+
+// Maximum number of addresses in an order's taker whitelist
+const MAX_ALLOWED_TAKERS: u32 = 10;
+
+#[contract]
+pub struct SorobanLOP;
+
+#[contractimpl]
+impl SorobanLOP {
+    /// Initialize the LOP contract
+    /// `fee_bps` of every fill's taking amount is paid to `fee_recipient` out of the maker's proceeds
+    pub fn initialize(
+        env: Env,
+        admin: Address,
+        dutch_auction_contract: Address,
+        fee_bps: u32,
+        fee_recipient: Address,
+    ) -> Result<(), Error> {
+        // Check if already initialized
+        if env.storage().instance().has(&DataKey::Admin) {
+            return Err(Error::AlreadyInitialized);
+        }
+
+        if fee_bps as i128 > BPS_DENOMINATOR {
+            return Err(Error::InvalidFee);
+        }
+
+        // Store admin and Dutch auction contract address
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::DutchAuctionContract, &dutch_auction_contract);
+        env.storage()
+            .instance()
+            .set(&DataKey::FillFeeConfig, &FeeConfig { fee_bps, fee_recipient });
+
+        Ok(())
+    }
+
+    /// Pause all fills (admin only)
+    /// Cancellations and queries keep working so makers can exit
+    pub fn pause(env: Env) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::Paused, &true);
+
+        // Emit event
+        env.events().publish(("paused",), admin);
+
+        Ok(())
+    }
+
+    /// Resume fills after a pause (admin only)
+    pub fn unpause(env: Env) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::Paused, &false);
+
+        // Emit event
+        env.events().publish(("unpaused",), admin);
+
+        Ok(())
+    }
+
+    /// Check whether fills are paused
+    pub fn is_paused(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::Paused).unwrap_or(false)
+    }
+
+    /// Set the anti-spam deposit charged by `create_order` (admin only)
+    /// An amount of 0 disables the deposit
+    pub fn set_creation_deposit(env: Env, token: Address, amount: i128) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        if amount < 0 {
+            return Err(Error::InvalidDeposit);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::CreationDepositConfig, &Deposit { token, amount });
+
+        Ok(())
+    }
+
+    /// Get the deposit charged by `create_order`
+    pub fn get_creation_deposit(env: Env) -> Option<Deposit> {
+        env.storage().instance().get(&DataKey::CreationDepositConfig)
+    }
+
+    /// Set the maximum number of active created orders per maker (admin only)
+    /// A value of 0 disables the limit
+    pub fn set_max_active_orders_per_maker(env: Env, max_active_orders: u32) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage()
+            .instance()
+            .set(&DataKey::MaxActiveOrdersPerMaker, &max_active_orders);
+
+        Ok(())
+    }
+
+    /// Cap the starting taking amount of Dutch auction orders (admin only)
+    /// Guards against fat-fingered start prices; a value of 0 disables the cap
+    pub fn set_max_taking_amount_start(env: Env, max_taking_amount_start: i128) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        if max_taking_amount_start < 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::MaxTakingAmountStart, &max_taking_amount_start);
+
+        Ok(())
+    }
+
+    /// Get the cap on Dutch auction starting taking amounts (0 = no cap)
+    pub fn get_max_taking_amount_start(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MaxTakingAmountStart)
+            .unwrap_or(0)
+    }
+
+    /// Reject fills of non-Dutch orders carrying auction fields (admin only)
+    /// `create_order` always rejects them; off by default so orders signed before the check still fill
+    pub fn set_strict_auction_fields(env: Env, enabled: bool) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage()
+            .instance()
+            .set(&DataKey::StrictAuctionFields, &enabled);
+
+        Ok(())
+    }
+
+    /// Check whether fills reject non-Dutch orders carrying auction fields
+    pub fn get_strict_auction_fields(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::StrictAuctionFields)
+            .unwrap_or(false)
+    }
+
+    /// Get the number of created orders of a maker that are not yet filled or cancelled
+    pub fn get_active_order_count(env: Env, maker: Address) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ActiveOrderCount(maker))
+            .unwrap_or(0)
+    }
+
+    /// Get the hashes of a maker's orders that were created, locked or partially filled
+    /// and are not yet filled or cancelled
+    pub fn get_maker_order_hashes(env: Env, maker: Address) -> Vec<BytesN<32>> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::MakerOrders(maker))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Set the protocol fee charged to takers on each fill (admin only)
+    /// A fee of 0 bps disables the fee
+    pub fn set_protocol_fee(env: Env, fee_bps: u32, fee_recipient: Address) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        if fee_bps as i128 > BPS_DENOMINATOR {
+            return Err(Error::InvalidFee);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::FeeConfig, &FeeConfig { fee_bps, fee_recipient });
+
+        Ok(())
+    }
+
+    /// Get the protocol fee configuration
+    pub fn get_protocol_fee(env: Env) -> Option<FeeConfig> {
+        env.storage().instance().get(&DataKey::FeeConfig)
+    }
+
+    /// Set volume tiers for the protocol fee as `(min_amount, bps)` pairs (admin only)
+    /// A fill pays the bps of the largest tier whose `min_amount` is at most its making amount;
+    /// tiers must be sorted by strictly increasing `min_amount`. An empty table removes the tiers
+    pub fn set_fee_tiers(env: Env, tiers: Vec<(i128, u32)>) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        let mut previous_min: Option<i128> = None;
+        for (min_amount, bps) in tiers.iter() {
+            if min_amount < 0 || bps as i128 > BPS_DENOMINATOR {
+                return Err(Error::InvalidFee);
+            }
+            if previous_min.is_some_and(|previous| min_amount <= previous) {
+                return Err(Error::InvalidFee);
+            }
+            previous_min = Some(min_amount);
+        }
+
+        if tiers.is_empty() {
+            env.storage().instance().remove(&DataKey::FeeTiers);
+        } else {
+            env.storage().instance().set(&DataKey::FeeTiers, &tiers);
+        }
+
+        Ok(())
+    }
+
+    /// Get the protocol fee volume tiers
+    pub fn get_fee_tiers(env: Env) -> Vec<(i128, u32)> {
+        env.storage()
+            .instance()
+            .get(&DataKey::FeeTiers)
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Get the fill fee configured at initialization
+    pub fn get_fee_config(env: Env) -> Result<FeeConfig, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::FillFeeConfig)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the fees accrued by the LOP in `token` and not yet collected
+    /// Fees accrue when the LOP itself is a fee recipient
+    pub fn get_accrued_fees(env: Env, token: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::AccruedFees(token))
+            .unwrap_or(0)
+    }
+
+    /// Send the fees accrued in `token` to `to` (admin only)
+    /// Returns the amount collected
+    pub fn collect_fees(env: Env, token: Address, to: Address) -> Result<i128, Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        let amount = Self::get_accrued_fees(env.clone(), token.clone());
+        if amount > 0 {
+            token::Client::new(&env, &token).transfer(&env.current_contract_address(), &to, &amount);
+            env.storage().persistent().remove(&DataKey::AccruedFees(token.clone()));
+
+            // Emit event
+            env.events().publish(("fees_collected",), (token, to, amount));
+        }
+
+        Ok(amount)
+    }
+
+    /// Exempt a maker or taker from the protocol fee (admin only)
+    /// Fills where either side is exempt pay no protocol or fill fee
+    pub fn set_fee_exempt(env: Env, account: Address) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::FeeExempt(account.clone()), &true);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::FeeExempt(account), 100, 100);
+
+        Ok(())
+    }
+
+    /// Remove a protocol fee exemption (admin only)
+    pub fn unset_fee_exempt(env: Env, account: Address) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage().persistent().remove(&DataKey::FeeExempt(account));
+
+        Ok(())
+    }
+
+    /// Check if an account is exempt from the protocol fee
+    pub fn is_fee_exempt(env: Env, account: Address) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::FeeExempt(account))
+            .unwrap_or(false)
+    }
+
+    /// Set the referral fee charged to takers on each fill and paid to `referrer` (admin only)
+    /// A fee of 0 bps disables the fee
+    pub fn set_referral_fee(env: Env, fee_bps: u32, referrer: Address) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        if fee_bps as i128 > BPS_DENOMINATOR {
+            return Err(Error::InvalidFee);
+        }
+
+        env.storage().instance().set(
+            &DataKey::ReferralFeeConfig,
+            &FeeConfig { fee_bps, fee_recipient: referrer },
+        );
+
+        Ok(())
+    }
+
+    /// Get the referral fee configuration
+    pub fn get_referral_fee(env: Env) -> Option<FeeConfig> {
+        env.storage().instance().get(&DataKey::ReferralFeeConfig)
+    }
+
+    /// Register an order on-chain (only by maker)
+    /// Takes the configured creation deposit, refunded on cancel and kept by the protocol on fill
+    pub fn create_order(env: Env, order: Order) -> Result<BytesN<32>, Error> {
+        // Require authorization from maker
+        order.maker.require_auth();
+
+        Self::check_new_order(&env, &order)?;
+
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(&env, &order);
+
+        // An order can only be created once
+        if env.storage().persistent().has(&DataKey::OrderState(order_hash.clone())) {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Enforce the per-maker limit on active orders
+        let max_active_orders: u32 = env
+            .storage()
+            .instance()
+            .get(&DataKey::MaxActiveOrdersPerMaker)
+            .unwrap_or(0);
+        let active_orders = Self::get_active_order_count(env.clone(), order.maker.clone());
+        if max_active_orders > 0 && active_orders >= max_active_orders {
+            return Err(Error::TooManyActiveOrders);
+        }
+        Self::set_active_order_count(&env, &order.maker, active_orders + 1);
+        Self::track_maker_order(&env, &order.maker, &order_hash);
+
+        // Take the creation deposit if one is configured
+        Self::take_creation_deposit(&env, &order.maker, &order_hash);
+
+        // Store the order as active
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Active);
+
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
+
+        // Index the order by maker and salt
+        Self::set_salt_index(&env, &order.maker, order.salt, &order_hash);
+
+        Self::set_order_timestamps(
+            &env,
+            &order_hash,
+            &OrderTimestamps {
+                created_at: env.ledger().timestamp(),
+                ..Default::default()
+            },
+        );
+
+        // Emit event
+        env.events().publish(("order_created",), order_hash.clone());
+
+        Ok(order_hash)
+    }
+
+    /// Fill `requested_making_amount` of an order
+    /// Orders with `ALLOW_PARTIAL_FILLS` can be filled in parts; others must be filled in full
+    /// Reverts with `SlippageExceeded` if the taking amount exceeds `max_taking_amount`
+    /// (pass `i128::MAX` to opt out). Returns the `(making_amount, taking_amount)` actually transferred
+    pub fn fill_order(
+        env: Env,
+        order: Order,
+        taker: Address,
+        requested_making_amount: i128,
+        max_taking_amount: i128,
+    ) -> Result<(i128, i128), Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        // Without the partial fill flag the whole remainder must be taken at once
+        if order.maker_traits & ALLOW_PARTIAL_FILLS == 0 {
+            let order_hash = Self::calculate_order_hash(&env, &order);
+            let remaining_amount = Self::remaining_amount_internal(&env, &order_hash, &order);
+            if requested_making_amount != remaining_amount {
+                return Err(Error::InvalidOrder);
+            }
+        }
+
+        let (making_amount, taking_amount) =
+            Self::fill_internal(&env, &order, &taker, Some(requested_making_amount), false, None, None, false)?;
+
+        // The price may have moved since the taker's quote; reverting undoes the whole fill
+        if taking_amount > max_taking_amount {
+            return Err(Error::SlippageExceeded);
+        }
+
+        Ok((making_amount, taking_amount))
+    }
+
+    /// Fill an order by spending `taking_amount` of taker asset
+    /// The fill is what the budget buys at the current price; reverts with `SlippageExceeded` if a
+    /// dust widened fill would charge more than `taking_amount`
+    pub fn fill_order_by_taking(
+        env: Env,
+        order: Order,
+        taker: Address,
+        taking_amount: i128,
+    ) -> Result<(i128, i128), Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        let total_taking_amount = Self::get_current_price(env.clone(), order.clone())?;
+        if taking_amount <= 0 || order.making_amount <= 0 || total_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        // What the budget buys at the current price, rounded down so the charge stays within it
+        let making_amount = taking_amount
+            .checked_mul(order.making_amount)
+            .ok_or(Error::InvalidOrder)?
+            / total_taking_amount;
+
+        // The budget must fit in what is left of the order
+        let remaining_amount = Self::remaining_amount_internal(&env, &order_hash, &order);
+        if making_amount <= 0
+            || making_amount > remaining_amount
+            || (order.maker_traits & ALLOW_PARTIAL_FILLS == 0 && making_amount != remaining_amount)
+        {
+            return Err(Error::InvalidOrder);
+        }
+
+        let (making_amount, actual_taking_amount) =
+            Self::fill_internal(&env, &order, &taker, Some(making_amount), false, None, None, false)?;
+        if actual_taking_amount > taking_amount {
+            return Err(Error::SlippageExceeded);
+        }
+
+        Ok((making_amount, actual_taking_amount))
+    }
+
+    /// Fill the rest of an order, delivering the maker asset to `dest`
+    /// `dest` overrides both `order.receiver` and the maker's default receiver; the taker asset
+    /// is still pulled from `taker`. Returns the `(making_amount, taking_amount)` transferred
+    pub fn fill_order_to(
+        env: Env,
+        order: Order,
+        taker: Address,
+        dest: Address,
+    ) -> Result<(i128, i128), Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        Self::fill_internal(&env, &order, &taker, None, false, None, Some(&dest), false)
+    }
+
+    /// Fill the rest of every order in `orders` as a single taker
+    /// All or nothing: the first failing order reverts the whole batch with its error
+    pub fn fill_orders(env: Env, orders: Vec<Order>, taker: Address) -> Result<(), Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        // A maker can only authorize this call once, however many of its orders are in the batch
+        let mut authorized_makers: Vec<Address> = Vec::new(&env);
+        let mut total_taking_amount: i128 = 0;
+        for order in orders.iter() {
+            let order_hash = Self::calculate_order_hash(&env, &order);
+            if !Self::is_locked_internal(&env, &order_hash) && !authorized_makers.contains(&order.maker) {
+                order.maker.require_auth();
+                authorized_makers.push_back(order.maker.clone());
+            }
+
+            let (_, taking_amount) =
+                Self::fill_internal(&env, &order, &taker, None, false, None, None, true)?;
+            total_taking_amount = total_taking_amount
+                .checked_add(taking_amount)
+                .ok_or(Error::InvalidOrder)?;
+        }
+
+        // Emit aggregate event alongside the per-order ones
+        env.events()
+            .publish(("orders_filled",), (orders.len(), total_taking_amount));
+
+        Ok(())
+    }
+
+    /// Fill an order paying in its `settle_token` instead of `taker_asset`
+    /// The taking amount is converted at the order's fixed `peg_rate` (rounded up)
+    pub fn fill_order_in_settle_token(
+        env: Env,
+        order: Order,
+        taker: Address,
+    ) -> Result<(), Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        Self::fill_internal(&env, &order, &taker, None, true, None, None, false)?;
+
+        Ok(())
+    }
+
+    /// Fill an exact amount of maker asset from a partial-fill order
+    /// Charges the proportional taking amount (rounded up) and returns it
+    pub fn fill_order_buy(
+        env: Env,
+        order: Order,
+        taker: Address,
+        making_amount_wanted: i128,
+    ) -> Result<i128, Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        // Only partial-fill orders can be bought in arbitrary quantities
+        if order.maker_traits & ALLOW_PARTIAL_FILLS == 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let (_, taking_amount) =
+            Self::fill_internal(&env, &order, &taker, Some(making_amount_wanted), false, None, None, false)?;
+
+        Ok(taking_amount)
+    }
+
+    /// Fill the rest of an order, delivering the maker asset before the taker pays
+    /// Calls `callback.callback_fn(order_hash, making_amount, taking_amount)` in between so the
+    /// taker can source the payment; if the maker isn't paid afterwards the whole fill reverts
+    pub fn flash_fill(
+        env: Env,
+        order: Order,
+        taker: Address,
+        callback: Address,
+        callback_fn: Symbol,
+    ) -> Result<(), Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        Self::fill_internal(
+            &env,
+            &order,
+            &taker,
+            None,
+            false,
+            Some((&callback, &callback_fn)),
+            None,
+            false,
+        )?;
+
+        Ok(())
+    }
+
+    /// Fill the rest of an order, then deploy the destination escrow through `escrow_factory`
+    /// and fund it with `immutables.amount` of `immutables.token` from the taker
+    /// Either both happen or the whole call reverts; returns the escrow address
+    pub fn fill_and_escrow(
+        env: Env,
+        order: Order,
+        taker: Address,
+        escrow_factory: Address,
+        immutables: EscrowImmutables,
+        salt: BytesN<32>,
+    ) -> Result<Address, Error> {
+        // Require authorization from taker
+        taker.require_auth();
+
+        Self::fill_internal(&env, &order, &taker, None, false, None, None, false)?;
+
+        // Deploy the escrow for the cross-chain leg
+        let escrow_address = match env.try_invoke_contract::<Address, soroban_sdk::Error>(
+            &escrow_factory,
+            &Symbol::new(&env, "deploy_escrow"),
+            (immutables.clone(), salt).into_val(&env),
+        ) {
+            Ok(Ok(address)) => address,
+            _ => return Err(Error::EscrowDeploymentFailed),
+        };
+
+        // Fund the escrow from the taker
+        let escrow_token = token::Client::new(&env, &immutables.token);
+        match escrow_token.try_transfer(&taker, &escrow_address, &immutables.amount) {
+            Ok(Ok(())) => {},
+            _ => return Err(Error::InsufficientBalance),
+        }
+
+        env.events()
+            .publish(("fill_and_escrow",), (Self::calculate_order_hash(&env, &order), &escrow_address));
+
+        Ok(escrow_address)
+    }
+
+    /// Cancel an order (only by maker)
+    pub fn cancel_order(env: Env, order: Order) -> Result<(), Error> {
+        // Require authorization from maker
+        order.maker.require_auth();
+
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(&env, &order);
+
+        // Check current state
+        let current_state: OrderState = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash.clone()))
+            .unwrap_or(OrderState::Active);
+
+        match current_state {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        Self::refund_order_funds(&env, &order_hash, &order);
+        Self::release_active_order(&env, &order_hash, &order.maker);
+        Self::untrack_maker_order(&env, &order.maker, &order_hash);
+
+        // Mark order as cancelled
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Cancelled);
+
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
+
+        // Emit event
+        env.events().publish(("order_cancelled",), order_hash);
+
+        Ok(())
+    }
+
+    /// Cancel an order by its hash (only by the maker recorded when the order was first touched)
+    /// Orders with locked funds must go through `cancel_order` so the funds can be refunded
+    pub fn cancel_order_by_hash(env: Env, order_hash: BytesN<32>, maker: Address) -> Result<(), Error> {
+        maker.require_auth();
+
+        let stored_maker: Option<Address> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderMaker(order_hash.clone()));
+        if stored_maker != Some(maker.clone()) {
+            return Err(Error::NotAuthorized);
+        }
+
+        // Check current state
+        let current_state: OrderState = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash.clone()))
+            .unwrap_or(OrderState::Active);
+
+        match current_state {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        if Self::is_locked_internal(&env, &order_hash) {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Refund the creation deposit
+        let deposit: Option<Deposit> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::CreationDeposit(order_hash.clone()));
+        if let Some(deposit) = deposit {
+            let deposit_token = token::Client::new(&env, &deposit.token);
+            deposit_token.transfer(&env.current_contract_address(), &maker, &deposit.amount);
+            env.storage().persistent().remove(&DataKey::CreationDeposit(order_hash.clone()));
+        }
+
+        Self::release_active_order(&env, &order_hash, &maker);
+        Self::untrack_maker_order(&env, &maker, &order_hash);
+
+        // Mark order as cancelled
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Cancelled);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
+
+        env.events().publish(("order_cancelled",), order_hash);
+
+        Ok(())
+    }
+
+    /// Lower an order's remaining amount with the maker's off-chain signature
+    /// `signature` is the maker's Ed25519 signature over
+    /// `"reduce" || lop_address_xdr || order_hash || new_remaining`, so it can't be replayed on another LOP
+    /// Lets relayers process reductions without maker auth; locked funds above the new remainder are refunded
+    pub fn reduce_order_signed(
+        env: Env,
+        order: Order,
+        new_remaining: i128,
+        signature: BytesN<64>,
+    ) -> Result<(), Error> {
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(&env, &order);
+
+        match Self::get_order_state(env.clone(), order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        // Only strict reductions are allowed, which also prevents replays
+        let remaining_amount = Self::remaining_amount_internal(&env, &order_hash, &order);
+        if new_remaining <= 0 || new_remaining >= remaining_amount {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Verify the maker's signature (panics if invalid)
+        let maker_key = Self::account_public_key(&env, &order.maker)?;
+        let mut message = Bytes::from_slice(&env, b"reduce");
+        message.append(&env.current_contract_address().to_xdr(&env));
+        message.append(&order_hash.clone().into());
+        message.extend_from_slice(&new_remaining.to_be_bytes());
+        env.crypto().ed25519_verify(&maker_key, &message, &signature);
+
+        // Return locked funds that are no longer offered
+        if Self::is_locked_internal(&env, &order_hash) {
+            let released = remaining_amount - new_remaining;
+            let maker_token = token::Client::new(&env, &order.maker_asset);
+            maker_token.transfer(&env.current_contract_address(), &order.maker, &released);
+            let locked_remaining = Self::locked_remaining_internal(&env, &order_hash, &order);
+            Self::set_locked_remaining(&env, &order_hash, locked_remaining - released);
+        }
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::RemainingAmount(order_hash.clone()), &new_remaining);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::RemainingAmount(order_hash.clone()), 100, 100);
+
+        // Emit event
+        env.events().publish(("order_reduced",), (order_hash, new_remaining));
+
+        Ok(())
+    }
+
+    /// Atomically cancel `old_order` and register `new_order` in its place (only by maker)
+    /// `new_order` is validated like `create_order`. Locked funds of the old order are refunded; its
+    /// creation deposit moves to the new order, which pays the configured deposit if there is none to move
+    /// Returns the new order hash
+    pub fn replace_order(env: Env, old_order: Order, new_order: Order) -> Result<BytesN<32>, Error> {
+        // Require authorization from maker
+        old_order.maker.require_auth();
+
+        if new_order.maker != old_order.maker {
+            return Err(Error::NotAuthorized);
+        }
+        Self::check_new_order(&env, &new_order)?;
+
+        let old_hash = Self::calculate_order_hash(&env, &old_order);
+        match Self::get_order_state(env.clone(), old_order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        // The replacement must be a fresh order
+        let new_hash = Self::calculate_order_hash(&env, &new_order);
+        if new_hash == old_hash || env.storage().persistent().has(&DataKey::OrderState(new_hash.clone())) {
+            return Err(Error::InvalidOrder);
+        }
+
+        // The new order takes over the old one's active slot, or needs a slot of its own
+        if !env.storage().persistent().has(&DataKey::OrderState(old_hash.clone())) {
+            let max_active_orders: u32 = env
+                .storage()
+                .instance()
+                .get(&DataKey::MaxActiveOrdersPerMaker)
+                .unwrap_or(0);
+            let active_orders = Self::get_active_order_count(env.clone(), old_order.maker.clone());
+            if max_active_orders > 0 && active_orders >= max_active_orders {
+                return Err(Error::TooManyActiveOrders);
+            }
+            Self::set_active_order_count(&env, &old_order.maker, active_orders + 1);
+        }
+
+        // Refund any funds the maker locked for the old order that were not filled yet
+        if Self::is_locked_internal(&env, &old_hash) {
+            let locked_remaining = Self::locked_remaining_internal(&env, &old_hash, &old_order);
+            let maker_token = token::Client::new(&env, &old_order.maker_asset);
+            maker_token.transfer(&env.current_contract_address(), &old_order.maker, &locked_remaining);
+            env.storage().persistent().remove(&DataKey::LockedFor(old_hash.clone()));
+            env.storage().persistent().remove(&DataKey::LockedRemaining(old_hash.clone()));
+        }
+
+        // Move the creation deposit with the order, or take one like `create_order` would
+        let deposit: Option<Deposit> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::CreationDeposit(old_hash.clone()));
+        if let Some(deposit) = deposit {
+            env.storage()
+                .persistent()
+                .set(&DataKey::CreationDeposit(new_hash.clone()), &deposit);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::CreationDeposit(new_hash.clone()), 100, 100);
+            env.storage().persistent().remove(&DataKey::CreationDeposit(old_hash.clone()));
+        } else {
+            Self::take_creation_deposit(&env, &new_order.maker, &new_hash);
+        }
+
+        // Mark the old order as cancelled and the new one as active
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(old_hash.clone()), &OrderState::Cancelled);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(old_hash.clone()), 100, 100);
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(new_hash.clone()), &OrderState::Active);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(new_hash.clone()), 100, 100);
+
+        Self::untrack_maker_order(&env, &old_order.maker, &old_hash);
+        Self::track_maker_order(&env, &new_order.maker, &new_hash);
+        Self::set_salt_index(&env, &new_order.maker, new_order.salt, &new_hash);
+        Self::set_order_timestamps(
+            &env,
+            &new_hash,
+            &OrderTimestamps {
+                created_at: env.ledger().timestamp(),
+                ..Default::default()
+            },
+        );
+
+        // Emit event
+        env.events()
+            .publish(("order_replaced",), (old_hash, new_hash.clone()));
+
+        Ok(new_hash)
+    }
+
+    /// Replace an order with a copy at a new `taking_amount`, keeping its fill progress (only by maker)
+    /// The old hash is cancelled; its remaining amount, locked funds and creation deposit move to the
+    /// new hash. Returns the new order hash
+    pub fn reprice_order(
+        env: Env,
+        old_order: Order,
+        new_taking_amount: i128,
+    ) -> Result<BytesN<32>, Error> {
+        // Require authorization from maker
+        old_order.maker.require_auth();
+
+        if new_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let old_hash = Self::calculate_order_hash(&env, &old_order);
+        match Self::get_order_state(env.clone(), old_order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        let mut new_order = old_order.clone();
+        new_order.taking_amount = new_taking_amount;
+        let new_hash = Self::calculate_order_hash(&env, &new_order);
+
+        // The replacement must be a fresh order
+        if new_hash == old_hash || env.storage().persistent().has(&DataKey::OrderState(new_hash.clone())) {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Carry the fill progress over to the new hash
+        let remaining_amount = Self::remaining_amount_internal(&env, &old_hash, &old_order);
+        if env.storage().persistent().has(&DataKey::RemainingAmount(old_hash.clone())) {
+            env.storage()
+                .persistent()
+                .set(&DataKey::RemainingAmount(new_hash.clone()), &remaining_amount);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::RemainingAmount(new_hash.clone()), 100, 100);
+            env.storage().persistent().remove(&DataKey::RemainingAmount(old_hash.clone()));
+        }
+
+        // Move locked funds and the creation deposit with the order
+        if Self::is_locked_internal(&env, &old_hash) {
+            let locked_remaining = Self::locked_remaining_internal(&env, &old_hash, &old_order);
+            env.storage().persistent().set(&DataKey::LockedFor(new_hash.clone()), &true);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::LockedFor(new_hash.clone()), 100, 100);
+            Self::set_locked_remaining(&env, &new_hash, locked_remaining);
+            env.storage().persistent().remove(&DataKey::LockedFor(old_hash.clone()));
+            env.storage().persistent().remove(&DataKey::LockedRemaining(old_hash.clone()));
+        }
+
+        let deposit: Option<Deposit> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::CreationDeposit(old_hash.clone()));
+        if let Some(deposit) = deposit {
+            env.storage()
+                .persistent()
+                .set(&DataKey::CreationDeposit(new_hash.clone()), &deposit);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::CreationDeposit(new_hash.clone()), 100, 100);
+            env.storage().persistent().remove(&DataKey::CreationDeposit(old_hash.clone()));
+        }
+
+        // The amended order keeps its lifecycle timestamps
+        let timestamps: Option<OrderTimestamps> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderTimestamps(old_hash.clone()));
+        if let Some(timestamps) = timestamps {
+            Self::set_order_timestamps(&env, &new_hash, &timestamps);
+        }
+
+        // A created order stays created, so the maker's active order count is unchanged
+        if env.storage().persistent().has(&DataKey::OrderState(old_hash.clone())) {
+            env.storage()
+                .persistent()
+                .set(&DataKey::OrderState(new_hash.clone()), &OrderState::Active);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::OrderState(new_hash.clone()), 100, 100);
+            Self::set_salt_index(&env, &old_order.maker, old_order.salt, &new_hash);
+        }
+
+        // Mark the old order as cancelled
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(old_hash.clone()), &OrderState::Cancelled);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(old_hash.clone()), 100, 100);
+        Self::untrack_maker_order(&env, &old_order.maker, &old_hash);
+        Self::track_maker_order(&env, &old_order.maker, &new_hash);
+
+        // Emit event
+        env.events()
+            .publish(("order_repriced",), (old_hash, new_hash.clone(), remaining_amount));
+
+        Ok(new_hash)
+    }
+
+    /// Cancel any active order regardless of maker (admin only)
+    /// Governance backstop for abusive orders; moves no funds, so locked funds and the creation
+    /// deposit stay in the contract until the maker calls `reclaim_order_funds`
+    pub fn admin_cancel_order(env: Env, order: Order) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(&env, &order);
+
+        match Self::get_order_state(env.clone(), order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        Self::release_active_order(&env, &order_hash, &order.maker);
+        Self::untrack_maker_order(&env, &order.maker, &order_hash);
+
+        // Mark order as cancelled
+        env.storage()
+            .persistent()
+            .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Cancelled);
+
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
+
+        // Emit event
+        env.events().publish(("order_admin_cancelled",), order_hash);
+
+        Ok(())
+    }
+
+    /// Reclaim the unfilled locked funds and creation deposit of an admin-cancelled order (maker only)
+    pub fn reclaim_order_funds(env: Env, order: Order) -> Result<(), Error> {
+        // Require authorization from maker
+        order.maker.require_auth();
+
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(&env, &order);
+
+        if Self::get_order_state(env.clone(), order.clone()) != OrderState::Cancelled {
+            return Err(Error::InvalidOrder);
+        }
+        if !Self::is_locked_internal(&env, &order_hash)
+            && !env
+                .storage()
+                .persistent()
+                .has(&DataKey::CreationDeposit(order_hash.clone()))
+        {
+            return Err(Error::InvalidOrder);
+        }
+
+        Self::refund_order_funds(&env, &order_hash, &order);
+
+        // Emit event
+        env.events().publish(("order_funds_reclaimed",), order_hash);
+
+        Ok(())
+    }
+
+    /// Lock the maker's funds for a resting order (only by maker)
+    /// Fills of a locked order are paid from the LOP without further maker authorization
+    pub fn lock_funds(env: Env, order: Order) -> Result<(), Error> {
+        // Require authorization from maker
+        order.maker.require_auth();
+
+        if order.making_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(&env, &order);
+
+        // Check current state
+        let current_state: OrderState = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash.clone()))
+            .unwrap_or(OrderState::Active);
+
+        match current_state {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        // Funds can only be locked once per order
+        if Self::is_locked_internal(&env, &order_hash) {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Pull the maker asset into the LOP
+        let maker_token = token::Client::new(&env, &order.maker_asset);
+        maker_token.transfer(&order.maker, &env.current_contract_address(), &order.making_amount);
+
+        // Mark funds as locked
+        env.storage()
+            .persistent()
+            .set(&DataKey::LockedFor(order_hash.clone()), &true);
+
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::LockedFor(order_hash.clone()), 100, 100);
+        Self::set_locked_remaining(&env, &order_hash, order.making_amount);
+        Self::track_maker_order(&env, &order.maker, &order_hash);
+
+        // Emit event
+        env.events().publish(("funds_locked",), (order_hash, order.making_amount));
+
+        Ok(())
+    }
+
+    /// Check whether the maker's funds are locked in the LOP for an order
+    pub fn is_locked(env: Env, order: Order) -> bool {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        Self::is_locked_internal(&env, &order_hash)
+    }
+
+    /// Get the maker funds still locked in the LOP for an order (0 if none)
+    /// Each fill releases exactly the making amount it delivers
+    pub fn get_locked_remaining(env: Env, order: Order) -> i128 {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        if !Self::is_locked_internal(&env, &order_hash) {
+            return 0;
+        }
+        Self::locked_remaining_internal(&env, &order_hash, &order)
+    }
+
+    /// Allocate up to `making_amount` of an order to `taker` (only by maker)
+    /// Once any taker has an allocation, fills by takers beyond their allocation fail with `AllowanceExceeded`
+    pub fn set_fill_allowance(env: Env, order: Order, taker: Address, making_amount: i128) -> Result<(), Error> {
+        order.maker.require_auth();
+
+        if making_amount < 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        Self::set_fill_allowance_internal(&env, &order_hash, &taker, making_amount);
+
+        let key = DataKey::HasFillAllowances(order_hash.clone());
+        env.storage().persistent().set(&key, &true);
+        env.storage().persistent().extend_ttl(&key, 100, 100);
+
+        env.events()
+            .publish(("fill_allowance_set",), (order_hash, taker, making_amount));
+
+        Ok(())
+    }
+
+    /// Get the making amount `taker` may still fill of an allocated order (0 if none)
+    pub fn get_fill_allowance(env: Env, order: Order, taker: Address) -> i128 {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        Self::fill_allowance_internal(&env, &order_hash, &taker)
+    }
+
+    /// Register the receiver used for the maker's orders whose receiver is unset (only by maker)
+    pub fn set_default_receiver(env: Env, maker: Address, receiver: Address) {
+        // Require authorization from maker
+        maker.require_auth();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::DefaultReceiver(maker.clone()), &receiver);
+
+        // Extend TTL
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::DefaultReceiver(maker), 100, 100);
+    }
+
+    /// Get the maker's default receiver, if registered
+    pub fn get_default_receiver(env: Env, maker: Address) -> Option<Address> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::DefaultReceiver(maker))
+    }
+
+    /// Set the Stellar asset contract of native XLM used by `UNWRAP_WETH` orders (admin only)
+    pub fn set_native_token(env: Env, native_token: Address) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::NativeToken, &native_token);
+
+        Ok(())
+    }
+
+    /// Get the configured native XLM asset contract, if any
+    pub fn get_native_token(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::NativeToken)
+    }
+
+    /// Set the time bucket size in seconds used to count fills (admin only)
+    /// A size of 0 disables fill counting
+    pub fn set_fill_bucket_size(env: Env, bucket_size: u64) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::FillBucketSize, &bucket_size);
+
+        Ok(())
+    }
+
+    /// Get the number of fills in a time bucket (`timestamp / bucket_size`)
+    pub fn get_fills_in_window(env: Env, bucket: u64) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::FillsInWindow(bucket))
+            .unwrap_or(0)
+    }
+
+    /// Set the dust threshold for orders paid in `taker_asset` (admin only)
+    /// A partial fill that would leave a remainder worth less than `threshold` of the taker asset
+    /// takes the whole remainder instead; a threshold of 0 disables the rule
+    pub fn set_dust_threshold(env: Env, taker_asset: Address, threshold: i128) -> Result<(), Error> {
+        // Check authorization
+        let admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+
+        admin.require_auth();
+
+        if threshold < 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::DustThreshold(taker_asset), &threshold);
+
+        Ok(())
+    }
+
+    /// Get the dust threshold for orders paid in `taker_asset` (0 if unset)
+    pub fn get_dust_threshold(env: Env, taker_asset: Address) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::DustThreshold(taker_asset))
+            .unwrap_or(0)
+    }
+
+    /// Build the `maker_traits` bitfield for the requested order features
+    pub fn build_maker_traits(
+        _env: Env,
+        is_dutch: bool,
+        unwrap_weth: bool,
+        allow_partial: bool,
+        has_allowed_takers: bool,
+        cutoff_at_auction_end: bool,
+        has_pre_interaction: bool,
+        has_post_interaction: bool,
+        relative_auction: bool,
+    ) -> u64 {
+        let mut maker_traits = 0;
+        if is_dutch {
+            maker_traits |= IS_DUTCH_AUCTION;
+        }
+        if unwrap_weth {
+            maker_traits |= UNWRAP_WETH;
+        }
+        if allow_partial {
+            maker_traits |= ALLOW_PARTIAL_FILLS;
+        }
+        if has_allowed_takers {
+            maker_traits |= HAS_ALLOWED_TAKERS;
+        }
+        if cutoff_at_auction_end {
+            maker_traits |= CUTOFF_AT_AUCTION_END;
+        }
+        if has_pre_interaction {
+            maker_traits |= HAS_PRE_INTERACTION;
+        }
+        if has_post_interaction {
+            maker_traits |= HAS_POST_INTERACTION;
+        }
+        if relative_auction {
+            maker_traits |= RELATIVE_AUCTION;
+        }
+        maker_traits
+    }
+
+    /// Get order state
+    pub fn get_order_state(env: Env, order: Order) -> OrderState {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        env.storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash))
+            .unwrap_or(OrderState::Active)
+    }
+
+    /// Get when an order was created, first filled and completed (0 for what hasn't happened)
+    pub fn get_order_timestamps(env: Env, order: Order) -> OrderTimestamps {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        env.storage()
+            .persistent()
+            .get(&DataKey::OrderTimestamps(order_hash))
+            .unwrap_or_default()
+    }
+
+    /// Get the canonical hash identifying an order
+    /// Off-chain clients and other contracts use it to look up state or sign orders
+    pub fn hash_order(env: Env, order: Order) -> BytesN<32> {
+        // sha256 over every field in order; optional fields carry a presence tag
+        let mut data = soroban_sdk::Bytes::new(&env);
+        
+        data.extend_from_slice(&order.salt.to_be_bytes());
+        data.extend_from_slice(&order.making_amount.to_be_bytes());
+        data.extend_from_slice(&order.taking_amount.to_be_bytes());
+        data.extend_from_slice(&order.maker_traits.to_be_bytes());
+        data.append(&order.maker.clone().to_xdr(&env));
+        data.append(&order.receiver.clone().to_xdr(&env));
+        data.append(&order.maker_asset.clone().to_xdr(&env));
+        data.append(&order.taker_asset.clone().to_xdr(&env));
+        data.extend_from_slice(&order.auction_start_time.to_be_bytes());
+        data.extend_from_slice(&order.auction_end_time.to_be_bytes());
+        data.extend_from_slice(&order.auction_duration.to_be_bytes());
+        data.extend_from_slice(&order.taking_amount_start.to_be_bytes());
+        data.extend_from_slice(&order.taking_amount_end.to_be_bytes());
+        data.extend_from_slice(&order.expiration.to_be_bytes());
+        data.extend_from_slice(&order.min_fill_making_amount.to_be_bytes());
+        data.append(&order.allowed_takers.clone().to_xdr(&env));
+        Self::append_tagged(&env, &mut data, &order.post_fill_hook);
+        Self::append_tagged(&env, &mut data, &order.post_fill_fn);
+        Self::append_tagged(&env, &mut data, &order.settle_token);
+        data.extend_from_slice(&order.peg_rate.to_be_bytes());
+        Self::append_tagged(&env, &mut data, &order.pre_interaction);
+        Self::append_tagged(&env, &mut data, &order.post_interaction);
+
+        env.crypto().sha256(&data).into()
+    }
+
+    /// Check whether two orders hash to the same order hash, e.g. a reused salt
+    pub fn hashes_collide(env: Env, order_a: Order, order_b: Order) -> bool {
+        Self::calculate_order_hash(&env, &order_a) == Self::calculate_order_hash(&env, &order_b)
+    }
+
+    /// Get the maker asset still fillable on an order
+    /// The full making amount for untouched orders and 0 once filled or cancelled
+    pub fn get_remaining_making_amount(env: Env, order: Order) -> i128 {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        match Self::get_order_state(env.clone(), order.clone()) {
+            OrderState::Active => Self::remaining_amount_internal(&env, &order_hash, &order),
+            OrderState::Filled | OrderState::Cancelled => 0,
+        }
+    }
+
+    /// Get the state of a created order from its maker and salt
+    pub fn get_state_by_salt(env: Env, maker: Address, salt: u64) -> Result<OrderState, Error> {
+        let order_hash: BytesN<32> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SaltIndex(maker, salt))
+            .ok_or(Error::OrderNotFound)?;
+
+        Ok(env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash))
+            .unwrap_or(OrderState::Active))
+    }
+
+    /// Check whether an order would be fillable at `at_timestamp`
+    /// Order state is evaluated now; auction windows and cutoffs at `at_timestamp`
+    pub fn is_fillable_at(env: Env, order: Order, at_timestamp: u64) -> bool {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        let order_state: OrderState = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash.clone()))
+            .unwrap_or(OrderState::Active);
+
+        if order_state != OrderState::Active {
+            return false;
+        }
+
+        // Auction parameters the Dutch auction contract would reject
+        let (auction_start_time, auction_end_time) =
+            Self::auction_window(&env, &order_hash, &order, at_timestamp);
+        if Self::is_dutch_auction(&order)
+            && (auction_end_time < auction_start_time
+                || order.taking_amount_start <= order.taking_amount_end)
+        {
+            return false;
+        }
+
+        Self::check_time_window(&order, auction_end_time, at_timestamp).is_ok()
+    }
+
+    /// Get current Dutch auction price for an order
+    /// A RELATIVE_AUCTION order whose clock hasn't started is priced as if it started now
+    pub fn get_current_price(env: Env, order: Order) -> Result<i128, Error> {
+        if !Self::is_dutch_auction(&order) {
+            return Ok(order.taking_amount);
+        }
+
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        let (auction_start_time, auction_end_time) =
+            Self::auction_window(&env, &order_hash, &order, env.ledger().timestamp());
+
+        let dutch_auction_contract: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::DutchAuctionContract)
+            .ok_or(Error::NotInitialized)?;
+
+        let dutch_auction_client = dutch_auction::Client::new(&env, &dutch_auction_contract);
+
+        let price = dutch_auction_client
+            .calculate_taking_amount(
+                &order.making_amount,
+                &order.taking_amount_start,
+                &order.taking_amount_end,
+                &auction_start_time,
+                &auction_end_time,
+            );
+
+        Ok(price)
+    }
+
+    /// Compare the taking amount if filled now with the amount at auction end
+    /// Returns (price_now, price_at_end); non-auction orders return the fixed price twice
+    pub fn price_comparison(env: Env, order: Order) -> Result<(i128, i128), Error> {
+        if !Self::is_dutch_auction(&order) {
+            return Ok((order.taking_amount, order.taking_amount));
+        }
+
+        let price_now = Self::get_current_price(env, order.clone())?;
+
+        Ok((price_now, order.taking_amount_end))
+    }
+
+    /// Get an advisory fair value for the order: the mean of the current auction price and the
+    /// oracle's `quote_taking_amount(maker_asset, taker_asset, making_amount)`; not used in settlement
+    pub fn reference_price(env: Env, order: Order, oracle: Address) -> Result<i128, Error> {
+        let auction_price = Self::get_current_price(env.clone(), order.clone())?;
+        let oracle_price = env.invoke_contract::<i128>(
+            &oracle,
+            &Symbol::new(&env, "quote_taking_amount"),
+            (order.maker_asset, order.taker_asset, order.making_amount).into_val(&env),
+        );
+        if oracle_price <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        auction_price
+            .checked_add(oracle_price)
+            .map(|sum| sum / 2)
+            .ok_or(Error::InvalidOrder)
+    }
+
+    /// Get the effective rate a taker pays for a full fill, fees included
+    /// Returns `(taking_amount + fees) * scale / making_amount` at the current price
+    pub fn get_all_in_rate(env: Env, order: Order, scale: i128) -> Result<i128, Error> {
+        if scale <= 0 || order.making_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let taking_amount = Self::get_current_price(env.clone(), order.clone())?;
+        let fee_exempt = Self::is_fee_exempt(env.clone(), order.maker.clone());
+        let (protocol_fee, referral_fee) =
+            Self::taker_fees_internal(&env, order.making_amount, taking_amount, fee_exempt)?;
+
+        let all_in_rate = taking_amount
+            .checked_add(protocol_fee)
+            .and_then(|v| v.checked_add(referral_fee))
+            .and_then(|v| v.checked_mul(scale))
+            .ok_or(Error::InvalidOrder)?
+            / order.making_amount;
+
+        Ok(all_in_rate)
+    }
+
+    /// Get the largest making amount the taker can afford with their taker asset balance
+    /// Accounts for the taker fees and is bounded by the order's remaining amount
+    pub fn max_affordable_fill(env: Env, order: Order, taker: Address) -> Result<i128, Error> {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        match Self::get_order_state(env.clone(), order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        let total_taking_amount = Self::get_current_price(env.clone(), order.clone())?;
+        if order.making_amount <= 0 || total_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let balance = token::Client::new(&env, &order.taker_asset).balance(&taker);
+        let fee_exempt = Self::fee_exempt_internal(&env, &order.maker, &taker);
+
+        // Binary search the largest affordable amount; cost grows with the making amount
+        let mut low: i128 = 0;
+        let mut high = Self::remaining_amount_internal(&env, &order_hash, &order);
+        while low < high {
+            let mid = low + (high - low + 1) / 2;
+            let taking_amount = Self::proportional_taking_amount(&order, mid, total_taking_amount)?;
+            let (protocol_fee, referral_fee) =
+                Self::taker_fees_internal(&env, mid, taking_amount, fee_exempt)?;
+            if taking_amount + protocol_fee + referral_fee <= balance {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// Get the break-even price for a resolver filling the rest of the order
+    /// The current taking amount for the remaining making amount plus the safety `deposit`
+    /// (in taker asset units) the resolver must recover over this fill
+    pub fn break_even_price(env: Env, order: Order, deposit: i128) -> Result<i128, Error> {
+        if deposit < 0 {
+            return Err(Error::InvalidDeposit);
+        }
+
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        let total_taking_amount = Self::get_current_price(env.clone(), order.clone())?;
+        if order.making_amount <= 0 || total_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let remaining_amount = Self::remaining_amount_internal(&env, &order_hash, &order);
+        let taking_amount =
+            Self::proportional_taking_amount(&order, remaining_amount, total_taking_amount)?;
+
+        taking_amount.checked_add(deposit).ok_or(Error::InvalidOrder)
+    }
+
+    /// Get the full cost breakdown for `taker` filling the rest of the order at the current price
+    /// `total_outlay` is the taking amount plus the protocol and referral fees
+    pub fn get_full_quote(env: Env, order: Order, taker: Address) -> Result<FullQuote, Error> {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        match Self::get_order_state(env.clone(), order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        let total_taking_amount = Self::get_current_price(env.clone(), order.clone())?;
+        if order.making_amount <= 0 || total_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let remaining_amount = Self::remaining_amount_internal(&env, &order_hash, &order);
+        let taking_amount =
+            Self::proportional_taking_amount(&order, remaining_amount, total_taking_amount)?;
+        let fee_exempt = Self::fee_exempt_internal(&env, &order.maker, &taker);
+        let (protocol_fee, referral_fee) =
+            Self::taker_fees_internal(&env, remaining_amount, taking_amount, fee_exempt)?;
+
+        let total_outlay = taking_amount
+            .checked_add(protocol_fee)
+            .and_then(|v| v.checked_add(referral_fee))
+            .ok_or(Error::InvalidOrder)?;
+        let fill_fee = Self::fill_fee_internal(&env, taking_amount, fee_exempt)?;
+
+        Ok(FullQuote {
+            taking_amount,
+            protocol_fee,
+            referral_fee,
+            total_outlay,
+            fill_fee,
+            maker_receives: taking_amount - fill_fee,
+        })
+    }
+
+    /// List every transfer `fill_order(order, taker, requested_making_amount, _)` would perform now
+    /// Each entry is `(token, from, to, amount)`, in execution order; nothing is executed
+    pub fn preview_transfers(
+        env: Env,
+        order: Order,
+        taker: Address,
+        requested_making_amount: i128,
+    ) -> Result<Vec<(Address, Address, Address, i128)>, Error> {
+        let order_hash = Self::calculate_order_hash(&env, &order);
+        match Self::get_order_state(env.clone(), order.clone()) {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        let remaining_amount = Self::remaining_amount_internal(&env, &order_hash, &order);
+        if requested_making_amount <= 0
+            || requested_making_amount > remaining_amount
+            || (order.maker_traits & ALLOW_PARTIAL_FILLS == 0 && requested_making_amount != remaining_amount)
+        {
+            return Err(Error::InvalidOrder);
+        }
+        Self::check_min_fill(&order, requested_making_amount, remaining_amount)?;
+
+        let total_taking_amount = Self::get_current_price(env.clone(), order.clone())?;
+        if order.making_amount <= 0 || total_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        let making_amount = Self::dust_adjusted_making_amount(
+            &env,
+            &order,
+            requested_making_amount,
+            remaining_amount,
+            total_taking_amount,
+        )?;
+        let taking_amount =
+            Self::proportional_taking_amount(&order, making_amount, total_taking_amount)?;
+
+        let mut transfers = Vec::new(&env);
+
+        // Maker asset out, from the locked balance or the maker
+        let maker_source = if Self::is_locked_internal(&env, &order_hash) {
+            env.current_contract_address()
+        } else {
+            order.maker.clone()
+        };
+        let receiver = Self::receiver_internal(&env, &order, &taker);
+        transfers.push_back((order.maker_asset.clone(), maker_source, receiver, making_amount));
+
+        // Taker asset in, less the fill fee
+        let fee_exempt = Self::fee_exempt_internal(&env, &order.maker, &taker);
+        let fill_fee = Self::fill_fee_internal(&env, taking_amount, fee_exempt)?;
+        transfers.push_back((
+            order.taker_asset.clone(),
+            taker.clone(),
+            order.maker.clone(),
+            taking_amount - fill_fee,
+        ));
+        if fill_fee > 0 {
+            let fee_config = Self::get_fee_config(env.clone())?;
+            transfers.push_back((order.taker_asset.clone(), taker.clone(), fee_config.fee_recipient, fill_fee));
+        }
+
+        // Protocol and referral fees on top
+        let (protocol_fee, referral_fee) =
+            Self::taker_fees_internal(&env, making_amount, taking_amount, fee_exempt)?;
+        if protocol_fee > 0 {
+            let fee_config = Self::get_protocol_fee(env.clone()).ok_or(Error::InvalidFee)?;
+            transfers.push_back((order.taker_asset.clone(), taker.clone(), fee_config.fee_recipient, protocol_fee));
+        }
+        if referral_fee > 0 {
+            let fee_config = Self::get_referral_fee(env.clone()).ok_or(Error::InvalidFee)?;
+            transfers.push_back((order.taker_asset.clone(), taker.clone(), fee_config.fee_recipient, referral_fee));
+        }
+
+        // The creation deposit goes to the protocol once the order fills
+        if making_amount == remaining_amount {
+            let deposit: Option<Deposit> = env
+                .storage()
+                .persistent()
+                .get(&DataKey::CreationDeposit(order_hash));
+            if let Some(deposit) = deposit {
+                let admin = Self::get_admin(env.clone())?;
+                transfers.push_back((deposit.token, env.current_contract_address(), admin, deposit.amount));
+            }
+        }
+
+        Ok(transfers)
+    }
+
+    /// Helper function to determine who receives the maker asset: order.receiver if specified
+    /// (not the LOP address), otherwise the maker's default receiver, otherwise the taker
+    fn receiver_internal(env: &Env, order: &Order, taker: &Address) -> Address {
+        if order.receiver == env.current_contract_address() {
+            env.storage()
+                .persistent()
+                .get(&DataKey::DefaultReceiver(order.maker.clone()))
+                .unwrap_or(taker.clone())
+        } else {
+            order.receiver.clone()
+        }
+    }
+
+    /// Helper function to widen a fill to the whole remainder when it would leave dust
+    fn dust_adjusted_making_amount(
+        env: &Env,
+        order: &Order,
+        making_amount: i128,
+        remaining_amount: i128,
+        total_taking_amount: i128,
+    ) -> Result<i128, Error> {
+        let leftover_amount = remaining_amount - making_amount;
+        if leftover_amount > 0 {
+            let dust_threshold = Self::get_dust_threshold(env.clone(), order.taker_asset.clone());
+            let leftover_value =
+                Self::proportional_taking_amount(order, leftover_amount, total_taking_amount)?;
+            if leftover_value < dust_threshold {
+                return Ok(remaining_amount);
+            }
+        }
+        Ok(making_amount)
+    }
+
+    /// Helper function to compute the taking amount for part of an order, rounded up
+    fn proportional_taking_amount(
+        order: &Order,
+        making_amount: i128,
+        total_taking_amount: i128,
+    ) -> Result<i128, Error> {
+        let taking_amount = making_amount
+            .checked_mul(total_taking_amount)
+            .and_then(|v| v.checked_add(order.making_amount - 1))
+            .ok_or(Error::InvalidOrder)?
+            / order.making_amount;
+        Ok(taking_amount)
+    }
+
+    /// Helper function to check if either side of a fill is exempt from the protocol fee
+    fn fee_exempt_internal(env: &Env, maker: &Address, taker: &Address) -> bool {
+        Self::is_fee_exempt(env.clone(), maker.clone()) || Self::is_fee_exempt(env.clone(), taker.clone())
+    }
+
+    /// Helper function to compute a bps fee (protocol or referral) on a taking amount
+    fn protocol_fee_internal(fee_config: &FeeConfig, taking_amount: i128) -> Result<i128, Error> {
+        let protocol_fee = taking_amount
+            .checked_mul(fee_config.fee_bps as i128)
+            .ok_or(Error::InvalidFee)?
+            / BPS_DENOMINATOR;
+        Ok(protocol_fee)
+    }
+
+    /// Helper function to pay a fee from the taker, accruing it when the LOP is the recipient
+    fn pay_fee(env: &Env, fee_token: &token::Client, taker: &Address, recipient: &Address, amount: i128) {
+        fee_token.transfer(taker, recipient, &amount);
+        if *recipient == env.current_contract_address() {
+            let key = DataKey::AccruedFees(fee_token.address.clone());
+            let accrued = Self::get_accrued_fees(env.clone(), fee_token.address.clone());
+            env.storage().persistent().set(&key, &(accrued + amount));
+            env.storage().persistent().extend_ttl(&key, 100, 100);
+        }
+    }
+
+    /// Helper function to compute the fill fee withheld from the maker's share of a taking amount
+    /// It is waived when `fee_exempt` is set
+    fn fill_fee_internal(env: &Env, taking_amount: i128, fee_exempt: bool) -> Result<i128, Error> {
+        match env.storage().instance().get::<_, FeeConfig>(&DataKey::FillFeeConfig) {
+            Some(fee_config) if !fee_exempt => Self::protocol_fee_internal(&fee_config, taking_amount),
+            _ => Ok(0),
+        }
+    }
+
+    /// Helper function to get the protocol fee with its bps set by the tier matching `making_amount`
+    fn tiered_protocol_fee(env: &Env, making_amount: i128) -> Option<FeeConfig> {
+        let mut fee_config = Self::get_protocol_fee(env.clone())?;
+        for (min_amount, bps) in Self::get_fee_tiers(env.clone()).iter() {
+            if min_amount > making_amount {
+                break;
+            }
+            fee_config.fee_bps = bps;
+        }
+        Some(fee_config)
+    }
+
+    /// Helper function to compute the (protocol, referral) fees charged on a fill
+    /// `fee_exempt` waives the protocol fee
+    fn taker_fees_internal(
+        env: &Env,
+        making_amount: i128,
+        taking_amount: i128,
+        fee_exempt: bool,
+    ) -> Result<(i128, i128), Error> {
+        let protocol_fee = match Self::tiered_protocol_fee(env, making_amount) {
+            Some(fee_config) if !fee_exempt => Self::protocol_fee_internal(&fee_config, taking_amount)?,
+            _ => 0,
+        };
+        let referral_fee = match Self::get_referral_fee(env.clone()) {
+            Some(fee_config) => Self::protocol_fee_internal(&fee_config, taking_amount)?,
+            None => 0,
+        };
+        Ok((protocol_fee, referral_fee))
+    }
+
+    /// Helper function to execute a fill of `making_amount` (or the whole remainder if None)
+    /// Returns the (making, taking) amounts transferred; taking is in the settle token if paid in it.
+    /// A flash callback is invoked after the maker asset is delivered and before the taker pays.
+    /// `dest`, if set, receives the maker asset instead of the computed receiver.
+    /// `maker_authorized` skips the maker auth check when the caller already performed it
+    #[allow(clippy::too_many_arguments)]
+    fn fill_internal(
+        env: &Env,
+        order: &Order,
+        taker: &Address,
+        making_amount: Option<i128>,
+        pay_in_settle_token: bool,
+        flash_callback: Option<(&Address, &Symbol)>,
+        dest: Option<&Address>,
+        maker_authorized: bool,
+    ) -> Result<(i128, i128), Error> {
+        // Circuit breaker
+        if Self::is_paused(env.clone()) {
+            return Err(Error::Paused);
+        }
+
+        // Swapping an asset for itself only self-transfers and inflates volume and fees
+        if order.maker_asset == order.taker_asset {
+            return Err(Error::IdenticalAssets);
+        }
+
+        // Calculate order hash
+        let order_hash = Self::calculate_order_hash(env, order);
+
+        // Check order state
+        let order_state: OrderState = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderState(order_hash.clone()))
+            .unwrap_or(OrderState::Active);
+
+        match order_state {
+            OrderState::Filled => return Err(Error::OrderAlreadyFilled),
+            OrderState::Cancelled => return Err(Error::OrderCancelled),
+            OrderState::Active => {},
+        }
+
+        // Check the order's time constraints
+        let (auction_start_time, auction_end_time) = Self::start_auction(env, &order_hash, order);
+        Self::check_time_window(order, auction_end_time, env.ledger().timestamp())?;
+
+        // Restrict fills to whitelisted takers
+        if order.maker_traits & HAS_ALLOWED_TAKERS != 0 {
+            if order.allowed_takers.len() > MAX_ALLOWED_TAKERS {
+                return Err(Error::InvalidOrder);
+            }
+            if !order.allowed_takers.contains(taker) {
+                return Err(Error::SenderNotAllowed);
+            }
+        }
+
+        // A hook address needs a function to call
+        if order.post_fill_hook.is_some() && order.post_fill_fn.is_none() {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Interaction flags need their contract
+        let pre_interaction = match order.pre_interaction.as_ref() {
+            Some(interaction) if order.maker_traits & HAS_PRE_INTERACTION != 0 => Some(interaction),
+            None if order.maker_traits & HAS_PRE_INTERACTION != 0 => return Err(Error::InvalidOrder),
+            _ => None,
+        };
+        let post_interaction = match order.post_interaction.as_ref() {
+            Some(interaction) if order.maker_traits & HAS_POST_INTERACTION != 0 => Some(interaction),
+            None if order.maker_traits & HAS_POST_INTERACTION != 0 => return Err(Error::InvalidOrder),
+            _ => None,
+        };
+
+        // A settle token needs a positive peg
+        if order.settle_token.is_some() && order.peg_rate <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        if Self::get_strict_auction_fields(env.clone()) {
+            Self::check_auction_fields(order)?;
+        }
+        Self::check_auction_cap(env, order)?;
+
+        // Determine how much maker asset this fill moves
+        let remaining_amount = Self::remaining_amount_internal(env, &order_hash, order);
+        let actual_making_amount = making_amount.unwrap_or(remaining_amount);
+        if actual_making_amount <= 0 || actual_making_amount > remaining_amount {
+            return Err(Error::InvalidOrder);
+        }
+        Self::check_min_fill(order, actual_making_amount, remaining_amount)?;
+
+        // Maker funds are either pre-locked in the LOP or pulled with maker authorization
+        let is_locked = Self::is_locked_internal(env, &order_hash);
+        if !is_locked && !maker_authorized {
+            order.maker.require_auth();
+        }
+
+        // Calculate the taking amount for the whole order
+        let total_taking_amount = if Self::is_dutch_auction(order) {
+            // Get Dutch auction contract
+            let dutch_auction_contract: Address = env
+                .storage()
+                .instance()
+                .get(&DataKey::DutchAuctionContract)
+                .ok_or(Error::NotInitialized)?;
+
+            let dutch_auction_client = dutch_auction::Client::new(env, &dutch_auction_contract);
+
+            // Calculate current taking amount based on time
+            dutch_auction_client
+                .calculate_taking_amount(
+                    &order.making_amount,
+                    &order.taking_amount_start,
+                    &order.taking_amount_end,
+                    &auction_start_time,
+                    &auction_end_time,
+                )
+        } else {
+            // Regular order - use fixed amounts
+            order.taking_amount
+        };
+
+        // Validate amounts are positive
+        if order.making_amount <= 0 || total_taking_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        // Close the order rather than leave a remainder too small to be worth filling
+        let actual_making_amount = Self::dust_adjusted_making_amount(
+            env,
+            order,
+            actual_making_amount,
+            remaining_amount,
+            total_taking_amount,
+        )?;
+
+        // Once the maker allocates the order, each taker fills at most its allocation,
+        // checked against the fill after any dust widening
+        if env.storage().persistent().has(&DataKey::HasFillAllowances(order_hash.clone())) {
+            let allowance = Self::fill_allowance_internal(env, &order_hash, taker);
+            if actual_making_amount > allowance {
+                return Err(Error::AllowanceExceeded);
+            }
+            Self::set_fill_allowance_internal(env, &order_hash, taker, allowance - actual_making_amount);
+        }
+
+        // Charge the proportional taking amount, rounded up in the maker's favor
+        let actual_taking_amount =
+            Self::proportional_taking_amount(order, actual_making_amount, total_taking_amount)?;
+
+        // Convert to the settle token at the order's fixed peg if the taker pays in it
+        let (payment_asset, actual_taking_amount) = if pay_in_settle_token {
+            let settle_token = order.settle_token.clone().ok_or(Error::InvalidOrder)?;
+            let pegged_amount = actual_taking_amount
+                .checked_mul(order.peg_rate)
+                .and_then(|v| v.checked_add(PEG_RATE_SCALE - 1))
+                .ok_or(Error::InvalidOrder)?
+                / PEG_RATE_SCALE;
+            (settle_token, pegged_amount)
+        } else {
+            (order.taker_asset.clone(), actual_taking_amount)
+        };
+
+        // An explicit destination wins over the order's receiver
+        let receiver = match dest {
+            Some(dest) => dest.clone(),
+            None => Self::receiver_internal(env, order, taker),
+        };
+
+        let new_remaining_amount = remaining_amount - actual_making_amount;
+
+        // Native delivery: transfers of the native asset contract credit the receiver's XLM
+        // balance directly, so the maker asset must be that contract rather than another wrapper
+        if order.maker_traits & UNWRAP_WETH != 0 {
+            let native_token: Option<Address> = env.storage().instance().get(&DataKey::NativeToken);
+            if native_token.as_ref() != Some(&order.maker_asset) {
+                return Err(Error::UnwrapFailed);
+            }
+        }
+
+        // Let the pre-interaction run before any tokens move, e.g. to source the taker's funds;
+        // a failing interaction reverts the fill
+        if let Some(interaction) = pre_interaction {
+            env.invoke_contract::<()>(
+                interaction,
+                &Symbol::new(env, "pre_interaction"),
+                (order_hash.clone(), actual_making_amount, actual_taking_amount).into_val(env),
+            );
+        }
+
+        // Execute token transfers
+        // Transfer maker asset from maker (or the locked balance) to receiver
+        let maker_token = token::Client::new(env, &order.maker_asset);
+        let maker_source = if is_locked {
+            env.current_contract_address()
+        } else {
+            order.maker.clone()
+        };
+        Self::transfer_to_receiver(env, &order.maker_asset, &maker_source, &receiver, actual_making_amount)?;
+        if is_locked {
+            // Release the filled amount from the lock; a completed order returns any excess
+            let locked_remaining = Self::locked_remaining_internal(env, &order_hash, order) - actual_making_amount;
+            if new_remaining_amount == 0 {
+                if locked_remaining > 0 {
+                    maker_token.transfer(&env.current_contract_address(), &order.maker, &locked_remaining);
+                }
+                env.storage().persistent().remove(&DataKey::LockedFor(order_hash.clone()));
+                env.storage().persistent().remove(&DataKey::LockedRemaining(order_hash.clone()));
+            } else {
+                Self::set_locked_remaining(env, &order_hash, locked_remaining);
+            }
+        }
+
+        // Transfer taker asset (or the settle token) from taker to maker, less the fill fee
+        let taker_token = token::Client::new(env, &payment_asset);
+        let fee_exempt = Self::fee_exempt_internal(env, &order.maker, taker);
+        let fill_fee = Self::fill_fee_internal(env, actual_taking_amount, fee_exempt)?;
+        let maker_payment = actual_taking_amount - fill_fee;
+        if let Some((callback, callback_fn)) = flash_callback {
+            // Let the taker source the payment now that it holds the maker asset
+            env.invoke_contract::<()>(
+                callback,
+                callback_fn,
+                (order_hash.clone(), actual_making_amount, actual_taking_amount).into_val(env),
+            );
+
+            // The maker must be paid, otherwise the whole fill reverts
+            match taker_token.try_transfer(taker, &order.maker, &maker_payment) {
+                Ok(Ok(())) => {},
+                _ => return Err(Error::InsufficientBalance),
+            }
+        } else {
+            taker_token.transfer(taker, &order.maker, &maker_payment);
+        }
+
+        // Route the fill fee to the recipient configured at initialization
+        if fill_fee > 0 {
+            let fee_config = Self::get_fee_config(env.clone())?;
+            Self::pay_fee(env, &taker_token, taker, &fee_config.fee_recipient, fill_fee);
+        }
+
+        // Charge the protocol fee on top of the taking amount unless either side is exempt
+        if let Some(fee_config) = Self::tiered_protocol_fee(env, actual_making_amount).filter(|_| !fee_exempt) {
+            let protocol_fee = Self::protocol_fee_internal(&fee_config, actual_taking_amount)?;
+            if protocol_fee > 0 {
+                Self::pay_fee(env, &taker_token, taker, &fee_config.fee_recipient, protocol_fee);
+            }
+        }
+
+        // Charge the referral fee on top of the taking amount
+        if let Some(fee_config) = Self::get_referral_fee(env.clone()) {
+            let referral_fee = Self::protocol_fee_internal(&fee_config, actual_taking_amount)?;
+            if referral_fee > 0 {
+                Self::pay_fee(env, &taker_token, taker, &fee_config.fee_recipient, referral_fee);
+            }
+        }
+
+        // Run the post-interaction once the maker asset is delivered; a failing one reverts the fill
+        if let Some(interaction) = post_interaction {
+            env.invoke_contract::<()>(
+                interaction,
+                &Symbol::new(env, "post_interaction"),
+                (order_hash.clone(), actual_making_amount, actual_taking_amount).into_val(env),
+            );
+        }
+
+        // Record the order's first fill and completion times
+        let mut timestamps: OrderTimestamps = env
+            .storage()
+            .persistent()
+            .get(&DataKey::OrderTimestamps(order_hash.clone()))
+            .unwrap_or_default();
+        if timestamps.first_fill_at == 0 {
+            timestamps.first_fill_at = env.ledger().timestamp();
+        }
+        if new_remaining_amount == 0 {
+            timestamps.completed_at = env.ledger().timestamp();
+        }
+        Self::set_order_timestamps(env, &order_hash, &timestamps);
+
+        if new_remaining_amount > 0 {
+            // Track what is left of a partially filled order
+            env.storage()
+                .persistent()
+                .set(&DataKey::RemainingAmount(order_hash.clone()), &new_remaining_amount);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::RemainingAmount(order_hash.clone()), 100, 100);
+            Self::track_maker_order(env, &order.maker, &order_hash);
+        } else {
+            env.storage().persistent().remove(&DataKey::RemainingAmount(order_hash.clone()));
+
+            Self::release_active_order(env, &order_hash, &order.maker);
+            Self::untrack_maker_order(env, &order.maker, &order_hash);
+
+            // Mark order as filled
+            env.storage()
+                .persistent()
+                .set(&DataKey::OrderState(order_hash.clone()), &OrderState::Filled);
+
+            // Extend TTL for the order state
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::OrderState(order_hash.clone()), 100, 100);
+
+            // A creation deposit is kept by the protocol once the order fills
+            let deposit: Option<Deposit> = env
+                .storage()
+                .persistent()
+                .get(&DataKey::CreationDeposit(order_hash.clone()));
+            if let Some(deposit) = deposit {
+                let admin: Address = env
+                    .storage()
+                    .instance()
+                    .get(&DataKey::Admin)
+                    .ok_or(Error::NotInitialized)?;
+                let deposit_token = token::Client::new(env, &deposit.token);
+                deposit_token.transfer(&env.current_contract_address(), &admin, &deposit.amount);
+                env.storage().persistent().remove(&DataKey::CreationDeposit(order_hash.clone()));
+            }
+        }
+
+        // Count the fill in its time bucket
+        let bucket_size: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::FillBucketSize)
+            .unwrap_or(0);
+        if let Some(bucket) = env.ledger().timestamp().checked_div(bucket_size) {
+            let fills: u32 = env
+                .storage()
+                .persistent()
+                .get(&DataKey::FillsInWindow(bucket))
+                .unwrap_or(0);
+            env.storage()
+                .persistent()
+                .set(&DataKey::FillsInWindow(bucket), &(fills + 1));
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::FillsInWindow(bucket), 100, 100);
+        }
+
+        // Notify the order's post-fill hook; a failing hook reverts the fill
+        if let (Some(hook), Some(hook_fn)) = (&order.post_fill_hook, &order.post_fill_fn) {
+            env.invoke_contract::<()>(
+                hook,
+                hook_fn,
+                (order_hash.clone(), taker.clone(), actual_making_amount, actual_taking_amount)
+                    .into_val(env),
+            );
+        }
+
+        // Emit event with the parties and tokens so indexers don't need the full order
+        env.events().publish(
+            ("order_filled",),
+            (
+                order_hash,
+                actual_making_amount,
+                actual_taking_amount,
+                order.maker.clone(),
+                taker.clone(),
+                order.maker_asset.clone(),
+                payment_asset,
+            ),
+        );
+
+        Ok((actual_making_amount, actual_taking_amount))
+    }
+
+    /// Helper function to store a maker's active order count
+    fn set_active_order_count(env: &Env, maker: &Address, count: u32) {
+        env.storage()
+            .persistent()
+            .set(&DataKey::ActiveOrderCount(maker.clone()), &count);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::ActiveOrderCount(maker.clone()), 100, 100);
+    }
+
+    /// Helper function to store an order's lifecycle timestamps
+    fn set_order_timestamps(env: &Env, order_hash: &BytesN<32>, timestamps: &OrderTimestamps) {
+        let key = DataKey::OrderTimestamps(order_hash.clone());
+        env.storage().persistent().set(&key, timestamps);
+        env.storage().persistent().extend_ttl(&key, 100, 100);
+    }
+
+    /// Helper function to point a maker's salt at a created order
+    fn set_salt_index(env: &Env, maker: &Address, salt: u64, order_hash: &BytesN<32>) {
+        let key = DataKey::SaltIndex(maker.clone(), salt);
+        env.storage().persistent().set(&key, order_hash);
+        env.storage().persistent().extend_ttl(&key, 100, 100);
+    }
+
+    /// Helper function to list an order under its maker the first time it is touched
+    fn track_maker_order(env: &Env, maker: &Address, order_hash: &BytesN<32>) {
+        let maker_key = DataKey::OrderMaker(order_hash.clone());
+        if !env.storage().persistent().has(&maker_key) {
+            env.storage().persistent().set(&maker_key, maker);
+        }
+        env.storage().persistent().extend_ttl(&maker_key, 100, 100);
+
+        let mut hashes = Self::get_maker_order_hashes(env.clone(), maker.clone());
+        if hashes.contains(order_hash) {
+            return;
+        }
+        hashes.push_back(order_hash.clone());
+        env.storage()
+            .persistent()
+            .set(&DataKey::MakerOrders(maker.clone()), &hashes);
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::MakerOrders(maker.clone()), 100, 100);
+    }
+
+    /// Helper function to prune a filled or cancelled order from its maker's list
+    fn untrack_maker_order(env: &Env, maker: &Address, order_hash: &BytesN<32>) {
+        let mut hashes = Self::get_maker_order_hashes(env.clone(), maker.clone());
+        let Some(index) = hashes.first_index_of(order_hash) else {
+            return;
+        };
+        hashes.remove(index);
+        if hashes.is_empty() {
+            env.storage().persistent().remove(&DataKey::MakerOrders(maker.clone()));
+        } else {
+            env.storage()
+                .persistent()
+                .set(&DataKey::MakerOrders(maker.clone()), &hashes);
+            env.storage()
+                .persistent()
+                .extend_ttl(&DataKey::MakerOrders(maker.clone()), 100, 100);
+        }
+    }
+
+    /// Helper function to return a cancelled order's unfilled locked funds and creation deposit to the maker
+    fn refund_order_funds(env: &Env, order_hash: &BytesN<32>, order: &Order) {
+        if Self::is_locked_internal(env, order_hash) {
+            let locked_remaining = Self::locked_remaining_internal(env, order_hash, order);
+            let maker_token = token::Client::new(env, &order.maker_asset);
+            maker_token.transfer(&env.current_contract_address(), &order.maker, &locked_remaining);
+            env.storage().persistent().remove(&DataKey::LockedFor(order_hash.clone()));
+            env.storage().persistent().remove(&DataKey::LockedRemaining(order_hash.clone()));
+        }
+
+        let deposit: Option<Deposit> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::CreationDeposit(order_hash.clone()));
+        if let Some(deposit) = deposit {
+            let deposit_token = token::Client::new(env, &deposit.token);
+            deposit_token.transfer(&env.current_contract_address(), &order.maker, &deposit.amount);
+            env.storage().persistent().remove(&DataKey::CreationDeposit(order_hash.clone()));
+        }
+    }
+
+    /// Helper function to free a maker's active order slot when a created order closes
+    /// Only orders registered via `create_order` have a stored state while active
+    fn release_active_order(env: &Env, order_hash: &BytesN<32>, maker: &Address) {
+        if env.storage().persistent().has(&DataKey::OrderState(order_hash.clone())) {
+            let active_orders = Self::get_active_order_count(env.clone(), maker.clone());
+            Self::set_active_order_count(env, maker, active_orders.saturating_sub(1));
+        }
+    }
+
+    /// Helper function to read the unfilled making amount of an order
+    fn remaining_amount_internal(env: &Env, order_hash: &BytesN<32>, order: &Order) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::RemainingAmount(order_hash.clone()))
+            .unwrap_or(order.making_amount)
+    }
+
+    /// Helper function to read the locked maker funds of an order not yet released to fills
+    /// Locks recorded before this was tracked hold exactly the unfilled amount
+    fn locked_remaining_internal(env: &Env, order_hash: &BytesN<32>, order: &Order) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::LockedRemaining(order_hash.clone()))
+            .unwrap_or_else(|| Self::remaining_amount_internal(env, order_hash, order))
+    }
+
+    /// Helper function to read a taker's remaining allocation of an order
+    fn fill_allowance_internal(env: &Env, order_hash: &BytesN<32>, taker: &Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::FillAllowance(order_hash.clone(), taker.clone()))
+            .unwrap_or(0)
+    }
+
+    /// Helper function to store a taker's remaining allocation of an order
+    fn set_fill_allowance_internal(env: &Env, order_hash: &BytesN<32>, taker: &Address, amount: i128) {
+        let key = DataKey::FillAllowance(order_hash.clone(), taker.clone());
+        env.storage().persistent().set(&key, &amount);
+        env.storage().persistent().extend_ttl(&key, 100, 100);
+    }
+
+    /// Helper function to store the locked maker funds of an order not yet released to fills
+    fn set_locked_remaining(env: &Env, order_hash: &BytesN<32>, amount: i128) {
+        let key = DataKey::LockedRemaining(order_hash.clone());
+        env.storage().persistent().set(&key, &amount);
+        env.storage().persistent().extend_ttl(&key, 100, 100);
+    }
+
+    /// Helper function to check if maker funds are locked for an order hash
+    fn is_locked_internal(env: &Env, order_hash: &BytesN<32>) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::LockedFor(order_hash.clone()))
+            .unwrap_or(false)
+    }
+
+    /// Helper function to reject partial fills below the order's minimum fill size
+    fn check_min_fill(order: &Order, making_amount: i128, remaining_amount: i128) -> Result<(), Error> {
+        if making_amount < remaining_amount && making_amount < order.min_fill_making_amount {
+            return Err(Error::FillTooSmall);
+        }
+        Ok(())
+    }
+
+    /// Helper function to reject auction parameters on orders without the Dutch trait
+    fn check_auction_fields(order: &Order) -> Result<(), Error> {
+        if !Self::is_dutch_auction(order)
+            && (order.auction_start_time != 0
+                || order.auction_end_time != 0
+                || order.auction_duration != 0
+                || order.taking_amount_start != 0
+                || order.taking_amount_end != 0)
+        {
+            return Err(Error::InvalidOrder);
+        }
+        Ok(())
+    }
+
+    /// Helper function to run the checks `create_order` applies to an order before registering it
+    fn check_new_order(env: &Env, order: &Order) -> Result<(), Error> {
+        if order.making_amount <= 0 {
+            return Err(Error::InvalidOrder);
+        }
+
+        Self::check_auction_fields(order)?;
+        Self::check_auction_cap(env, order)
+    }
+
+    /// Helper function to take the configured creation deposit from `maker` for `order_hash`
+    fn take_creation_deposit(env: &Env, maker: &Address, order_hash: &BytesN<32>) {
+        let deposit_config: Option<Deposit> = env
+            .storage()
+            .instance()
+            .get(&DataKey::CreationDepositConfig);
+        if let Some(deposit) = deposit_config {
+            if deposit.amount > 0 {
+                let deposit_token = token::Client::new(env, &deposit.token);
+                deposit_token.transfer(maker, &env.current_contract_address(), &deposit.amount);
+
+                env.storage()
+                    .persistent()
+                    .set(&DataKey::CreationDeposit(order_hash.clone()), &deposit);
+                env.storage()
+                    .persistent()
+                    .extend_ttl(&DataKey::CreationDeposit(order_hash.clone()), 100, 100);
+            }
+        }
+    }
+
+    /// Helper function to reject Dutch orders starting above the configured cap
+    fn check_auction_cap(env: &Env, order: &Order) -> Result<(), Error> {
+        let max_taking_amount_start = Self::get_max_taking_amount_start(env.clone());
+        if order.maker_traits & IS_DUTCH_AUCTION != 0
+            && max_taking_amount_start > 0
+            && order.taking_amount_start > max_taking_amount_start
+        {
+            return Err(Error::InvalidOrder);
+        }
+        Ok(())
+    }
+
+    /// Helper function to get an order's auction window
+    /// A RELATIVE_AUCTION order whose clock hasn't started is treated as starting at `default_start`
+    fn auction_window(env: &Env, order_hash: &BytesN<32>, order: &Order, default_start: u64) -> (u64, u64) {
+        if order.maker_traits & RELATIVE_AUCTION == 0 {
+            return (order.auction_start_time, order.auction_end_time);
+        }
+        let auction_start_time: u64 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::AuctionStart(order_hash.clone()))
+            .unwrap_or(default_start);
+        (auction_start_time, auction_start_time.saturating_add(order.auction_duration))
+    }
+
+    /// Helper function to get an order's auction window, recording the start of a RELATIVE_AUCTION
+    /// order on its first fill
+    fn start_auction(env: &Env, order_hash: &BytesN<32>, order: &Order) -> (u64, u64) {
+        let key = DataKey::AuctionStart(order_hash.clone());
+        if order.maker_traits & RELATIVE_AUCTION != 0 && !env.storage().persistent().has(&key) {
+            let timestamp = env.ledger().timestamp();
+            env.storage().persistent().set(&key, &timestamp);
+            env.storage().persistent().extend_ttl(&key, 100, 100);
+            env.events()
+                .publish(("auction_started",), (order_hash.clone(), timestamp));
+        }
+        Self::auction_window(env, order_hash, order, env.ledger().timestamp())
+    }
+
+    /// Helper function to check an order's time constraints at `timestamp`
+    fn check_time_window(order: &Order, auction_end_time: u64, timestamp: u64) -> Result<(), Error> {
+        if order.expiration != 0 && timestamp >= order.expiration {
+            return Err(Error::OrderExpired);
+        }
+        if order.maker_traits & CUTOFF_AT_AUCTION_END != 0 && timestamp >= auction_end_time {
+            return Err(Error::AuctionEnded);
+        }
+        Ok(())
+    }
+
+    /// Helper function to send the maker asset to the fill's receiver
+    /// A receiver contract that refuses the token surfaces as `ReceiverRejected`; failures on the
+    /// sending side (short balance, deauthorized holder) and sends to accounts keep the token's own error
+    fn transfer_to_receiver(
+        env: &Env,
+        asset: &Address,
+        from: &Address,
+        receiver: &Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        let asset_client = token::Client::new(env, asset);
+        let sender_short = matches!(asset_client.try_balance(from), Ok(Ok(balance)) if balance < amount);
+        let sender_frozen = matches!(
+            token::StellarAssetClient::new(env, asset).try_authorized(from),
+            Ok(Ok(false))
+        );
+        let receiver_is_account = Self::account_public_key(env, receiver).is_ok();
+        if sender_short || sender_frozen || receiver_is_account {
+            asset_client.transfer(from, receiver, &amount);
+            return Ok(());
+        }
+
+        match asset_client.try_transfer(from, receiver, &amount) {
+            Ok(Ok(())) => Ok(()),
+            _ => Err(Error::ReceiverRejected),
+        }
+    }
+
+    /// Helper function to read the ed25519 public key of an account address
+    fn account_public_key(env: &Env, address: &Address) -> Result<BytesN<32>, Error> {
+        let xdr = address.clone().to_xdr(env);
+        // SCV_ADDRESS | SC_ADDRESS_TYPE_ACCOUNT | PUBLIC_KEY_TYPE_ED25519 | key
+        if xdr.len() != ACCOUNT_KEY_XDR_OFFSET + 32 || xdr.get(7) != Some(0) {
+            return Err(Error::NotAuthorized);
+        }
+        xdr.slice(ACCOUNT_KEY_XDR_OFFSET..)
+            .try_into()
+            .map_err(|_| Error::NotAuthorized)
+    }
+
+    /// Helper function to check if order is a Dutch auction
+    fn is_dutch_auction(order: &Order) -> bool {
+        order.maker_traits & IS_DUTCH_AUCTION != 0
+    }
+
+    /// Helper function to append an optional field to the hash preimage behind a presence tag,
+    /// so None and the neighbouring fields can't be confused with one another
+    fn append_tagged<T: ToXdr + Clone>(env: &Env, data: &mut Bytes, value: &Option<T>) {
+        match value {
+            Some(value) => {
+                data.push_back(1);
+                data.append(&value.clone().to_xdr(env));
+            }
+            None => data.push_back(0),
+        }
+    }
+
+    /// Calculate order hash; delegates to `hash_order`
+    fn calculate_order_hash(env: &Env, order: &Order) -> BytesN<32> {
+        Self::hash_order(env.clone(), order.clone())
+    }
+
+    /// Get admin address
+    pub fn get_admin(env: Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::Admin)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get Dutch auction contract address
+    pub fn get_dutch_auction_contract(env: Env) -> Result<Address, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::DutchAuctionContract)
+            .ok_or(Error::NotInitialized)
+    }
+
+    /// Get the Dutch auction contract this LOP prices with, which may be shared with other LOPs
+    pub fn get_shared_auction(env: Env) -> Result<Address, Error> {
+        Self::get_dutch_auction_contract(env)
+    }
+
+    /// Check that the configured Dutch auction contract responds to a price query
+    /// Returns false if the LOP is uninitialized or the call fails for any reason
+    pub fn check_auction_contract(env: Env) -> bool {
+        let dutch_auction_contract: Address = match env
+            .storage()
+            .instance()
+            .get(&DataKey::DutchAuctionContract)
+        {
+            Some(address) => address,
+            None => return false,
+        };
+
+        // Dummy auction: 1000 -> 500 over [0, 1)
+        let dutch_auction_client = dutch_auction::Client::new(&env, &dutch_auction_contract);
+        matches!(
+            dutch_auction_client.try_calculate_taking_amount(&100, &1000, &500, &0, &1),
+            Ok(Ok(_))
+        )
+    }
+
+    /// Get the symbolic name of an error code (e.g. 24 -> "AllowanceExceeded")
+    /// Returns "Unknown" for codes not defined by this contract
+    pub fn error_name(env: Env, code: u32) -> Symbol {
+        let name = match code {
+            1 => "NotInitialized",
+            2 => "AlreadyInitialized",
+            3 => "NotAuthorized",
+            4 => "OrderAlreadyFilled",
+            5 => "OrderCancelled",
+            6 => "InsufficientBalance",
+            7 => "InvalidOrder",
+            8 => "DutchAuctionError",
+            9 => "TransferFailed",
+            10 => "InvalidDeposit",
+            11 => "SenderNotAllowed",
+            12 => "AuctionEnded",
+            13 => "TooManyActiveOrders",
+            14 => "InvalidFee",
+            15 => "UnwrapFailed",
+            16 => "OrderExpired",
+            17 => "EscrowDeploymentFailed",
+            18 => "OrderNotFound",
+            19 => "Paused",
+            20 => "ReceiverRejected",
+            21 => "FillTooSmall",
+            22 => "SlippageExceeded",
+            23 => "IdenticalAssets",
+            24 => "AllowanceExceeded",
+            _ => "Unknown",
+        };
+        Symbol::new(&env, name)
+    }
+}
+
 mod test;
\ No newline at end of file
@@ -1,315 +1,3661 @@
-#![cfg(test)]
-extern crate std;
-
-use super::*;
-use soroban_sdk::{
-    testutils::{Address as _, Ledger},
-    token, Address, Env,
-};
-
-fn create_token_contract<'a>(
-    e: &Env,
-    admin: &Address,
-) -> (token::Client<'a>, token::StellarAssetClient<'a>) {
-    let sac = e.register_stellar_asset_contract_v2(admin.clone());
-    (
-        token::Client::new(e, &sac.address()),
-        token::StellarAssetClient::new(e, &sac.address()),
-    )
-}
-
-fn create_lop_contract(e: &Env) -> SorobanLOPClient {
-    SorobanLOPClient::new(e, &e.register(SorobanLOP, ()))
-}
-
-fn create_dutch_auction_contract(e: &Env) -> dutch_auction::Client {
-    dutch_auction::Client::new(e, &e.register(dutch_auction::WASM, ()))
-}
-
-#[test]
-fn test_initialize() {
-    let env = Env::default();
-    let lop = create_lop_contract(&env);
-    let dutch_auction = create_dutch_auction_contract(&env);
-    let admin = Address::generate(&env);
-
-    // Should initialize successfully
-    lop.initialize(&admin, &dutch_auction.address);
-    
-    // Should fail to initialize again
-    assert_eq!(
-        lop.try_initialize(&admin, &dutch_auction.address),
-        Err(Ok(Error::AlreadyInitialized))
-    );
-
-    // Check stored values
-    assert_eq!(lop.get_admin(), admin.clone());
-    assert_eq!(lop.get_dutch_auction_contract(), dutch_auction.address.clone());
-}
-
-#[test]
-fn test_fill_regular_order() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let lop = create_lop_contract(&env);
-    let dutch_auction = create_dutch_auction_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Initialize LOP
-    lop.initialize(&admin, &dutch_auction.address);
-
-    // Set up participants and tokens
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    
-    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
-    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
-
-    // Mint tokens
-    token_a_admin.mint(&maker, &1000);
-    token_b_admin.mint(&taker, &2000);
-
-    // Create regular order (not Dutch auction)
-    let order = Order {
-        salt: 1,
-        maker: maker.clone(),
-        receiver: taker.clone(),
-        maker_asset: token_a.address.clone(),
-        taker_asset: token_b.address.clone(),
-        making_amount: 1000,
-        taking_amount: 2000,
-        maker_traits: 0, // No flags set - regular order
-        auction_start_time: 0,
-        auction_end_time: 0,
-        taking_amount_start: 0,
-        taking_amount_end: 0,
-    };
-
-    // Fill the order
-    lop.fill_order(&order, &taker);
-
-    // Check balances
-    assert_eq!(token_a.balance(&maker), 0);
-    assert_eq!(token_a.balance(&taker), 1000);
-    assert_eq!(token_b.balance(&maker), 2000);
-    assert_eq!(token_b.balance(&taker), 0);
-
-    // Check order state
-    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
-}
-
-#[test]
-fn test_fill_dutch_auction_order() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    // Set initial timestamp
-    env.ledger().with_mut(|li| {
-        li.timestamp = 1500; // Midway through auction
-    });
-
-    let lop = create_lop_contract(&env);
-    let dutch_auction = create_dutch_auction_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Initialize LOP
-    lop.initialize(&admin, &dutch_auction.address);
-
-    // Set up participants and tokens
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    
-    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
-    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
-
-    // Mint tokens
-    token_a_admin.mint(&maker, &1000);
-    token_b_admin.mint(&taker, &3000); // Extra to cover Dutch auction price
-
-    // Create Dutch auction order
-    let order = Order {
-        salt: 2,
-        maker: maker.clone(),
-        receiver: taker.clone(),
-        maker_asset: token_a.address.clone(),
-        taker_asset: token_b.address.clone(),
-        making_amount: 1000,
-        taking_amount: 0, // Not used for Dutch auction
-        maker_traits: IS_DUTCH_AUCTION, // Dutch auction flag
-        auction_start_time: 1000,
-        auction_end_time: 2000,
-        taking_amount_start: 3000, // High starting price
-        taking_amount_end: 1500,   // Lower ending price
-    };
-
-    // Get current price (should be 2250 at timestamp 1500)
-    let current_price = lop.get_current_price(&order);
-    assert_eq!(current_price, 2250); // Midway: 3000 - (1500 * 0.5) = 2250
-
-    // Fill the order
-    lop.fill_order(&order, &taker);
-
-    // Check balances - taker should pay the calculated Dutch auction price
-    assert_eq!(token_a.balance(&maker), 0);
-    assert_eq!(token_a.balance(&taker), 1000);
-    assert_eq!(token_b.balance(&maker), 2250); // Dutch auction price
-    assert_eq!(token_b.balance(&taker), 750);  // Remaining: 3000 - 2250
-
-    // Check order state
-    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
-}
-
-#[test]
-fn test_cancel_order() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let lop = create_lop_contract(&env);
-    let dutch_auction = create_dutch_auction_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Initialize LOP
-    lop.initialize(&admin, &dutch_auction.address);
-
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token_a, _) = create_token_contract(&env, &token_admin);
-    let (token_b, _) = create_token_contract(&env, &token_admin);
-
-    let order = Order {
-        salt: 3,
-        maker: maker.clone(),
-        receiver: taker.clone(),
-        maker_asset: token_a.address.clone(),
-        taker_asset: token_b.address.clone(),
-        making_amount: 1000,
-        taking_amount: 2000,
-        maker_traits: 0,
-        auction_start_time: 0,
-        auction_end_time: 0,
-        taking_amount_start: 0,
-        taking_amount_end: 0,
-    };
-
-    // Cancel the order
-    lop.cancel_order(&order);
-
-    // Check order state
-    assert_eq!(lop.get_order_state(&order), OrderState::Cancelled);
-
-    // Try to fill cancelled order should fail
-    assert_eq!(
-        lop.try_fill_order(&order, &taker),
-        Err(Ok(Error::OrderCancelled))
-    );
-}
-
-#[test]
-fn test_fill_already_filled_order() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let lop = create_lop_contract(&env);
-    let dutch_auction = create_dutch_auction_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Initialize LOP
-    lop.initialize(&admin, &dutch_auction.address);
-
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    
-    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
-    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
-
-    // Mint tokens
-    token_a_admin.mint(&maker, &2000); // Double amount for potential double fill
-    token_b_admin.mint(&taker, &4000);
-
-    let order = Order {
-        salt: 4,
-        maker: maker.clone(),
-        receiver: taker.clone(),
-        maker_asset: token_a.address.clone(),
-        taker_asset: token_b.address.clone(),
-        making_amount: 1000,
-        taking_amount: 2000,
-        maker_traits: 0,
-        auction_start_time: 0,
-        auction_end_time: 0,
-        taking_amount_start: 0,
-        taking_amount_end: 0,
-    };
-
-    // Fill the order first time
-    lop.fill_order(&order, &taker);
-
-    // Try to fill again should fail
-    assert_eq!(
-        lop.try_fill_order(&order, &taker),
-        Err(Ok(Error::OrderAlreadyFilled))
-    );
-}
-
-#[test]
-fn test_dutch_auction_price_progression() {
-    let env = Env::default();
-    env.mock_all_auths();
-    
-    let lop = create_lop_contract(&env);
-    let dutch_auction = create_dutch_auction_contract(&env);
-    let admin = Address::generate(&env);
-    
-    // Initialize LOP
-    lop.initialize(&admin, &dutch_auction.address);
-
-    let maker = Address::generate(&env);
-    let taker = Address::generate(&env);
-    let token_admin = Address::generate(&env);
-    let (token_a, _) = create_token_contract(&env, &token_admin);
-    let (token_b, _) = create_token_contract(&env, &token_admin);
-
-    let order = Order {
-        salt: 5,
-        maker: maker.clone(),
-        receiver: taker.clone(),
-        maker_asset: token_a.address.clone(),
-        taker_asset: token_b.address.clone(),
-        making_amount: 1000,
-        taking_amount: 0,
-        maker_traits: IS_DUTCH_AUCTION,
-        auction_start_time: 1000,
-        auction_end_time: 2000,
-        taking_amount_start: 2000, // High starting price
-        taking_amount_end: 1000,   // Lower ending price
-    };
-
-    // Test at start
-    env.ledger().with_mut(|li| { li.timestamp = 1000; });
-    assert_eq!(lop.get_current_price(&order), 2000);
-
-    // Test at 25% through
-    env.ledger().with_mut(|li| { li.timestamp = 1250; });
-    assert_eq!(lop.get_current_price(&order), 1750);
-
-    // Test at 50% through
-    env.ledger().with_mut(|li| { li.timestamp = 1500; });
-    assert_eq!(lop.get_current_price(&order), 1500);
-
-    // Test at 75% through
-    env.ledger().with_mut(|li| { li.timestamp = 1750; });
-    assert_eq!(lop.get_current_price(&order), 1250);
-
-    // Test at end
-    env.ledger().with_mut(|li| { li.timestamp = 2000; });
-    assert_eq!(lop.get_current_price(&order), 1000);
-
-    // Test after end
-    env.ledger().with_mut(|li| { li.timestamp = 2500; });
-    assert_eq!(lop.get_current_price(&order), 1000);
-}
\ No newline at end of file
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use ed25519_dalek::{Signer, SigningKey};
+use soroban_sdk::{
+    testutils::{Address as _, Events, IssuerFlags, Ledger},
+    token, vec,
+    xdr::{FromXdr, ToXdr},
+    Address, Bytes, Env,
+};
+
+// Hook contract that records the last fill it was notified about
+#[contract]
+pub struct MockFillHook;
+
+#[contractimpl]
+impl MockFillHook {
+    pub fn on_fill(env: Env, order_hash: BytesN<32>, taker: Address, making_amount: i128, taking_amount: i128) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "last_fill"), &(order_hash, taker, making_amount, taking_amount));
+    }
+
+    pub fn last_fill(env: Env) -> Option<(BytesN<32>, Address, i128, i128)> {
+        env.storage().instance().get(&Symbol::new(&env, "last_fill"))
+    }
+}
+
+// Flash-fill taker that mints its payment in the callback when configured to pay
+#[contract]
+pub struct MockFlashTaker;
+
+#[contractimpl]
+impl MockFlashTaker {
+    pub fn setup(env: Env, payment_token: Address, pays: bool) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "setup"), &(payment_token, pays));
+    }
+
+    pub fn on_flash(env: Env, _order_hash: BytesN<32>, _making_amount: i128, taking_amount: i128) {
+        let (payment_token, pays): (Address, bool) =
+            env.storage().instance().get(&Symbol::new(&env, "setup")).unwrap();
+        if pays {
+            token::StellarAssetClient::new(&env, &payment_token)
+                .mint(&env.current_contract_address(), &taking_amount);
+        }
+    }
+}
+
+// Interaction contract that sources the taker's payment before the fill and records the fill after
+#[contract]
+pub struct MockInteraction;
+
+#[contractimpl]
+impl MockInteraction {
+    pub fn prepare(env: Env, payment_token: Address, taker: Address, traps: bool) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "prepare"), &(payment_token, taker, traps));
+    }
+
+    pub fn pre_interaction(env: Env, _order_hash: BytesN<32>, _making_amount: i128, taking_amount: i128) {
+        let (payment_token, taker, traps): (Address, Address, bool) =
+            env.storage().instance().get(&Symbol::new(&env, "prepare")).unwrap();
+        if traps {
+            panic!("interaction failed");
+        }
+        token::StellarAssetClient::new(&env, &payment_token).mint(&taker, &taking_amount);
+    }
+
+    pub fn post_interaction(env: Env, order_hash: BytesN<32>, making_amount: i128, taking_amount: i128) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "post"), &(order_hash, making_amount, taking_amount));
+    }
+
+    pub fn last_post_interaction(env: Env) -> Option<(BytesN<32>, i128, i128)> {
+        env.storage().instance().get(&Symbol::new(&env, "post"))
+    }
+}
+
+// Token whose transfers notify the recipient through `on_token_received`, like a transfer hook
+#[contract]
+pub struct MockHookToken;
+
+#[contractimpl]
+impl MockHookToken {
+    pub fn transfer(env: Env, _from: Address, to: Address, amount: i128) {
+        env.invoke_contract::<()>(&to, &Symbol::new(&env, "on_token_received"), (amount,).into_val(&env));
+    }
+}
+
+// Receiver contract that rejects every incoming token
+#[contract]
+pub struct MockRejectingReceiver;
+
+#[contractimpl]
+impl MockRejectingReceiver {
+    pub fn on_token_received(_env: Env, _amount: i128) {
+        panic!("tokens rejected");
+    }
+}
+
+// Escrow factory that records the deployment and hands back a preset escrow address
+#[contract]
+pub struct MockEscrowFactory;
+
+#[contractimpl]
+impl MockEscrowFactory {
+    pub fn configure(env: Env, escrow: Address, fails: bool) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "setup"), &(escrow, fails));
+    }
+
+    pub fn deploy_escrow(env: Env, immutables: EscrowImmutables, salt: BytesN<32>) -> Address {
+        let (escrow, fails): (Address, bool) =
+            env.storage().instance().get(&Symbol::new(&env, "setup")).unwrap();
+        if fails {
+            panic!("deployment failed");
+        }
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "deployed"), &(immutables, salt));
+        escrow
+    }
+
+    pub fn deployed(env: Env) -> Option<(EscrowImmutables, BytesN<32>)> {
+        env.storage().instance().get(&Symbol::new(&env, "deployed"))
+    }
+}
+
+// Price oracle that quotes a preset taking amount for any pair
+#[contract]
+pub struct MockPriceOracle;
+
+#[contractimpl]
+impl MockPriceOracle {
+    pub fn set_quote(env: Env, taking_amount: i128) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "quote"), &taking_amount);
+    }
+
+    pub fn quote_taking_amount(env: Env, _maker_asset: Address, _taker_asset: Address, _making_amount: i128) -> i128 {
+        env.storage().instance().get(&Symbol::new(&env, "quote")).unwrap()
+    }
+}
+
+// Account address whose ed25519 key is `signing_key`
+fn account_address(e: &Env, signing_key: &SigningKey) -> Address {
+    let mut xdr = Bytes::from_array(e, &[0, 0, 0, 18, 0, 0, 0, 0, 0, 0, 0, 0]);
+    xdr.extend_from_array(&signing_key.verifying_key().to_bytes());
+    Address::from_xdr(e, &xdr).unwrap()
+}
+
+fn sign_reduction(
+    e: &Env,
+    signing_key: &SigningKey,
+    lop: &Address,
+    order_hash: &BytesN<32>,
+    new_remaining: i128,
+) -> BytesN<64> {
+    let mut message = Bytes::from_slice(e, b"reduce");
+    message.append(&lop.clone().to_xdr(e));
+    message.append(&order_hash.clone().into());
+    message.extend_from_slice(&new_remaining.to_be_bytes());
+    let message: std::vec::Vec<u8> = message.iter().collect();
+    BytesN::from_array(e, &signing_key.sign(&message).to_bytes())
+}
+
+fn create_token_contract<'a>(
+    e: &Env,
+    admin: &Address,
+) -> (token::Client<'a>, token::StellarAssetClient<'a>) {
+    let sac = e.register_stellar_asset_contract_v2(admin.clone());
+    (
+        token::Client::new(e, &sac.address()),
+        token::StellarAssetClient::new(e, &sac.address()),
+    )
+}
+
+fn create_lop_contract(e: &Env) -> SorobanLOPClient<'_> {
+    SorobanLOPClient::new(e, &e.register(SorobanLOP, ()))
+}
+
+fn create_dutch_auction_contract(e: &Env) -> dutch_auction::Client<'_> {
+    dutch_auction::Client::new(e, &e.register(dutch_auction::WASM, ()))
+}
+
+// Order of 1000 `maker_asset` for 2000 `taker_asset` with every optional feature off;
+// tests override the fields they exercise with struct update syntax
+fn default_order(e: &Env, maker: &Address, receiver: &Address, maker_asset: &Address, taker_asset: &Address) -> Order {
+    Order {
+        salt: 0,
+        maker: maker.clone(),
+        receiver: receiver.clone(),
+        maker_asset: maker_asset.clone(),
+        taker_asset: taker_asset.clone(),
+        making_amount: 1000,
+        taking_amount: 2000,
+        maker_traits: 0,
+        auction_start_time: 0,
+        auction_end_time: 0,
+        auction_duration: 0,
+        taking_amount_start: 0,
+        taking_amount_end: 0,
+        allowed_takers: Vec::new(e),
+        post_fill_hook: None,
+        post_fill_fn: None,
+        settle_token: None,
+        peg_rate: 0,
+        expiration: 0,
+        pre_interaction: None,
+        post_interaction: None,
+        min_fill_making_amount: 0,
+    }
+}
+
+#[test]
+fn test_initialize() {
+    let env = Env::default();
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Should initialize successfully
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+    
+    // Should fail to initialize again
+    assert_eq!(
+        lop.try_initialize(&admin, &dutch_auction.address, &0, &admin),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+
+    // Check stored values
+    assert_eq!(lop.get_admin(), admin.clone());
+    assert_eq!(lop.get_dutch_auction_contract(), dutch_auction.address.clone());
+    assert_eq!(
+        lop.get_fee_config(),
+        FeeConfig {
+            fee_bps: 0,
+            fee_recipient: admin.clone(),
+        }
+    );
+}
+
+#[test]
+fn test_fill_regular_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    // Set up participants and tokens
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    // Mint tokens
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // Create regular order (not Dutch auction)
+    let order = Order {
+        salt: 1,
+        maker: maker.clone(),
+        receiver: taker.clone(),
+        maker_asset: token_a.address.clone(),
+        taker_asset: token_b.address.clone(),
+        making_amount: 1000,
+        taking_amount: 2000,
+        maker_traits: 0, // No flags set - regular order
+        auction_start_time: 0,
+        auction_end_time: 0,
+        taking_amount_start: 0,
+        taking_amount_end: 0,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    let order_hash = env.as_contract(&lop.address, || SorobanLOP::calculate_order_hash(&env, &order));
+
+    // Fill the order
+    let (making_amount, taking_amount) = lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!((making_amount, taking_amount), (1000, 2000));
+
+    // The fill event names the parties and tokens
+    let events = env.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![
+            &env,
+            (
+                lop.address.clone(),
+                ("order_filled",).into_val(&env),
+                (
+                    order_hash,
+                    1000_i128,
+                    2000_i128,
+                    maker.clone(),
+                    taker.clone(),
+                    token_a.address.clone(),
+                    token_b.address.clone(),
+                )
+                    .into_val(&env),
+            ),
+        ]
+    );
+
+    // Check balances
+    assert_eq!(token_a.balance(&maker), 0);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(token_b.balance(&taker), 0);
+
+    // Check order state
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_fill_dutch_auction_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    // Set initial timestamp
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500; // Midway through auction
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    // Set up participants and tokens
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    // Mint tokens
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &3000); // Extra to cover Dutch auction price
+
+    // Create Dutch auction order
+    let order = Order {
+        salt: 2,
+        maker: maker.clone(),
+        receiver: taker.clone(),
+        maker_asset: token_a.address.clone(),
+        taker_asset: token_b.address.clone(),
+        making_amount: 1000,
+        taking_amount: 0, // Not used for Dutch auction
+        maker_traits: IS_DUTCH_AUCTION, // Dutch auction flag
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000, // High starting price
+        taking_amount_end: 1500,   // Lower ending price
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Get current price (should be 2250 at timestamp 1500)
+    let current_price = lop.get_current_price(&order);
+    assert_eq!(current_price, 2250); // Midway: 3000 - (1500 * 0.5) = 2250
+
+    // Fill the order; the returned amounts are what actually moved
+    let (making_amount, taking_amount) = lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(making_amount, 1000);
+    assert_eq!(taking_amount, 2250);
+
+    // Check balances - taker should pay the calculated Dutch auction price
+    assert_eq!(token_a.balance(&maker), 0);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2250); // Dutch auction price
+    assert_eq!(token_b.balance(&taker), 750);  // Remaining: 3000 - 2250
+
+    // Check order state
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_cancel_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let order = Order {
+        salt: 3,
+        maker: maker.clone(),
+        receiver: taker.clone(),
+        maker_asset: token_a.address.clone(),
+        taker_asset: token_b.address.clone(),
+        making_amount: 1000,
+        taking_amount: 2000,
+        maker_traits: 0,
+        auction_start_time: 0,
+        auction_end_time: 0,
+        taking_amount_start: 0,
+        taking_amount_end: 0,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Cancel the order
+    lop.cancel_order(&order);
+
+    // Check order state
+    assert_eq!(lop.get_order_state(&order), OrderState::Cancelled);
+
+    // Try to fill cancelled order should fail
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::OrderCancelled))
+    );
+}
+
+#[test]
+fn test_fill_already_filled_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    // Mint tokens
+    token_a_admin.mint(&maker, &2000); // Double amount for potential double fill
+    token_b_admin.mint(&taker, &4000);
+
+    let order = Order {
+        salt: 4,
+        maker: maker.clone(),
+        receiver: taker.clone(),
+        maker_asset: token_a.address.clone(),
+        taker_asset: token_b.address.clone(),
+        making_amount: 1000,
+        taking_amount: 2000,
+        maker_traits: 0,
+        auction_start_time: 0,
+        auction_end_time: 0,
+        taking_amount_start: 0,
+        taking_amount_end: 0,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Fill the order first time
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+
+    // Try to fill again should fail
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::OrderAlreadyFilled))
+    );
+}
+
+#[test]
+fn test_dutch_auction_price_progression() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let order = Order {
+        salt: 5,
+        maker: maker.clone(),
+        receiver: taker.clone(),
+        maker_asset: token_a.address.clone(),
+        taker_asset: token_b.address.clone(),
+        making_amount: 1000,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 2000, // High starting price
+        taking_amount_end: 1000,   // Lower ending price
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Test at start
+    env.ledger().with_mut(|li| { li.timestamp = 1000; });
+    assert_eq!(lop.get_current_price(&order), 2000);
+
+    // Test at 25% through
+    env.ledger().with_mut(|li| { li.timestamp = 1250; });
+    assert_eq!(lop.get_current_price(&order), 1750);
+
+    // Test at 50% through
+    env.ledger().with_mut(|li| { li.timestamp = 1500; });
+    assert_eq!(lop.get_current_price(&order), 1500);
+
+    // Test at 75% through
+    env.ledger().with_mut(|li| { li.timestamp = 1750; });
+    assert_eq!(lop.get_current_price(&order), 1250);
+
+    // Test at end
+    env.ledger().with_mut(|li| { li.timestamp = 2000; });
+    assert_eq!(lop.get_current_price(&order), 1000);
+
+    // Test after end
+    env.ledger().with_mut(|li| { li.timestamp = 2500; });
+    assert_eq!(lop.get_current_price(&order), 1000);
+}
+#[test]
+fn test_lock_funds() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+
+    let order = Order {
+        salt: 6,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Lock the maker's funds
+    lop.lock_funds(&order);
+
+    // Funds moved into the LOP
+    assert_eq!(token_a.balance(&maker), 0);
+    assert_eq!(token_a.balance(&lop.address), 1000);
+    assert!(lop.is_locked(&order));
+
+    // Locking twice should fail
+    assert_eq!(lop.try_lock_funds(&order), Err(Ok(Error::InvalidOrder)));
+}
+
+#[test]
+fn test_fill_locked_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 7,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    lop.lock_funds(&order);
+
+    // Fill is paid from the locked balance
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+
+    // Only the taker authorized the fill
+    let auths = env.auths();
+    assert_eq!(auths.len(), 1);
+    assert_eq!(auths[0].0, taker);
+
+    // Check balances
+    assert_eq!(token_a.balance(&lop.address), 0);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+    assert!(!lop.is_locked(&order));
+}
+
+#[test]
+fn test_cancel_locked_order_refunds() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+
+    let order = Order {
+        salt: 8,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    lop.lock_funds(&order);
+    assert_eq!(token_a.balance(&maker), 0);
+
+    // Cancelling returns the locked funds
+    lop.cancel_order(&order);
+
+    assert_eq!(token_a.balance(&maker), 1000);
+    assert_eq!(token_a.balance(&lop.address), 0);
+    assert_eq!(lop.get_order_state(&order), OrderState::Cancelled);
+    assert!(!lop.is_locked(&order));
+}
+
+#[test]
+fn test_create_then_cancel_refunds_deposit() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+    let (deposit_token, deposit_token_admin) = create_token_contract(&env, &token_admin);
+
+    // Configure a creation deposit
+    lop.set_creation_deposit(&deposit_token.address, &10);
+    deposit_token_admin.mint(&maker, &10);
+
+    let order = Order {
+        salt: 9,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Creating the order takes the deposit
+    lop.create_order(&order);
+    assert_eq!(deposit_token.balance(&maker), 0);
+    assert_eq!(deposit_token.balance(&lop.address), 10);
+
+    // Creating it again should fail
+    assert_eq!(lop.try_create_order(&order), Err(Ok(Error::InvalidOrder)));
+
+    // Cancelling refunds the deposit
+    lop.cancel_order(&order);
+    assert_eq!(deposit_token.balance(&maker), 10);
+    assert_eq!(deposit_token.balance(&lop.address), 0);
+}
+
+#[test]
+fn test_create_then_fill_keeps_deposit() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let (deposit_token, deposit_token_admin) = create_token_contract(&env, &token_admin);
+
+    // Configure a creation deposit
+    lop.set_creation_deposit(&deposit_token.address, &10);
+    deposit_token_admin.mint(&maker, &10);
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 10,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    lop.create_order(&order);
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+
+    // Deposit is collected by the protocol on fill
+    assert_eq!(deposit_token.balance(&maker), 0);
+    assert_eq!(deposit_token.balance(&lop.address), 0);
+    assert_eq!(deposit_token.balance(&admin), 10);
+
+    // Trade itself is unaffected
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+}
+
+#[test]
+fn test_fill_order_buy_exact_making_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 11,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Buy exactly 300 of the maker asset
+    let charged = lop.fill_order_buy(&order, &taker, &300);
+    assert_eq!(charged, 600);
+    assert_eq!(token_a.balance(&taker), 300);
+    assert_eq!(token_b.balance(&maker), 600);
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+
+    // Buying more than what remains should fail
+    assert_eq!(
+        lop.try_fill_order_buy(&order, &taker, &701),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // Filling the order takes the remainder
+    lop.fill_order(&order, &taker, &700, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_fill_order_buy_requires_partial_fills() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let order = Order {
+        salt: 12,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    assert_eq!(
+        lop.try_fill_order_buy(&order, &taker, &300),
+        Err(Ok(Error::InvalidOrder))
+    );
+}
+
+#[test]
+fn test_allowed_takers_whitelist() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker_1 = Address::generate(&env);
+    let taker_2 = Address::generate(&env);
+    let taker_3 = Address::generate(&env);
+    let outsider = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker_1, &2000);
+    token_b_admin.mint(&taker_2, &2000);
+    token_b_admin.mint(&outsider, &2000);
+
+    // Partial-fill OTC order open to three counterparties, delivered to each taker
+    let order = Order {
+        salt: 13,
+        maker_traits: ALLOW_PARTIAL_FILLS | HAS_ALLOWED_TAKERS,
+        allowed_takers: vec![&env, taker_1.clone(), taker_2.clone(), taker_3.clone()],
+        ..default_order(&env, &maker, &lop.address, &token_a.address, &token_b.address)
+    };
+
+    // Two whitelisted takers fill their share
+    lop.fill_order_buy(&order, &taker_1, &400);
+    lop.fill_order_buy(&order, &taker_2, &400);
+    assert_eq!(token_a.balance(&taker_1), 400);
+    assert_eq!(token_a.balance(&taker_2), 400);
+
+    // Outsider is rejected
+    assert_eq!(
+        lop.try_fill_order_buy(&order, &outsider, &200),
+        Err(Ok(Error::SenderNotAllowed))
+    );
+    assert_eq!(token_a.balance(&outsider), 0);
+}
+
+#[test]
+fn test_default_receiver_used_when_unset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let default_receiver = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // Maker registers a default receiver
+    lop.set_default_receiver(&maker, &default_receiver);
+    assert_eq!(lop.get_default_receiver(&maker), Some(default_receiver.clone()));
+
+    // Receiver left unset (LOP address sentinel)
+    let order = Order {
+        salt: 14,
+        ..default_order(&env, &maker, &lop.address, &token_a.address, &token_b.address)
+    };
+
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+
+    // Maker asset went to the registered default receiver
+    assert_eq!(token_a.balance(&default_receiver), 1000);
+    assert_eq!(token_a.balance(&taker), 0);
+    assert_eq!(token_b.balance(&maker), 2000);
+}
+
+#[test]
+fn test_fills_in_window_counter() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1050;
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP and count fills in 100 second buckets
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+    lop.set_fill_bucket_size(&100);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &3000);
+    token_b_admin.mint(&taker, &6000);
+
+    // Fill three orders in the same bucket
+    for salt in 15..18 {
+        let order = Order {
+            salt,
+            ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+        };
+        lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    }
+
+    // Timestamp 1050 falls in bucket 10
+    assert_eq!(lop.get_fills_in_window(&10), 3);
+    assert_eq!(lop.get_fills_in_window(&11), 0);
+}
+
+#[test]
+fn test_price_comparison() {
+    let env = Env::default();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500; // Midway through auction
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let token_a = Address::generate(&env);
+    let token_b = Address::generate(&env);
+
+    let mut order = Order {
+        salt: 18,
+        maker_traits: IS_DUTCH_AUCTION,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000,
+        taking_amount_end: 1500,
+        ..default_order(&env, &maker, &maker, &token_a, &token_b)
+    };
+
+    // Descending auction: filling now costs more than waiting
+    let (price_now, price_at_end) = lop.price_comparison(&order);
+    assert_eq!(price_now, 2250);
+    assert_eq!(price_at_end, 1500);
+    assert!(price_now > price_at_end);
+
+    // Fixed-price order returns the same price twice
+    order.maker_traits = 0;
+    assert_eq!(lop.price_comparison(&order), (2000, 2000));
+}
+
+#[test]
+fn test_reference_price_blends_auction_and_oracle() {
+    let env = Env::default();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500; // Midway through auction
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let oracle = MockPriceOracleClient::new(&env, &env.register(MockPriceOracle, ()));
+    oracle.set_quote(&2050);
+
+    let maker = Address::generate(&env);
+    let order = Order {
+        salt: 81,
+        maker_traits: IS_DUTCH_AUCTION,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000,
+        taking_amount_end: 1500,
+        ..default_order(&env, &maker, &maker, &Address::generate(&env), &Address::generate(&env))
+    };
+
+    // Auction is at 2250 midway; the blend is the mean with the oracle's 2050
+    assert_eq!(lop.get_current_price(&order), 2250);
+    assert_eq!(lop.reference_price(&order, &oracle.address), 2150);
+
+    // A nonsensical oracle quote is rejected rather than blended
+    oracle.set_quote(&0);
+    assert_eq!(
+        lop.try_reference_price(&order, &oracle.address),
+        Err(Ok(Error::InvalidOrder))
+    );
+}
+
+#[test]
+fn test_is_fillable_at() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 500; // Before the auction starts
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &3000);
+
+    // Dutch auction order that cannot be filled once the auction ends
+    let order = Order {
+        salt: 19,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION | CUTOFF_AT_AUCTION_END,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000,
+        taking_amount_end: 1500,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Fillable during its window, not at or after the cutoff
+    assert!(lop.is_fillable_at(&order, &1500));
+    assert!(!lop.is_fillable_at(&order, &2000));
+    assert!(!lop.is_fillable_at(&order, &2500));
+
+    // Filling after the cutoff is rejected
+    env.ledger().with_mut(|li| {
+        li.timestamp = 2000;
+    });
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::AuctionEnded))
+    );
+
+    // Cancelled orders are never fillable
+    lop.cancel_order(&order);
+    assert!(!lop.is_fillable_at(&order, &1500));
+}
+
+#[test]
+fn test_error_name() {
+    let env = Env::default();
+    let lop = create_lop_contract(&env);
+
+    assert_eq!(lop.error_name(&4), Symbol::new(&env, "OrderAlreadyFilled"));
+    assert_eq!(lop.error_name(&(Error::SenderNotAllowed as u32)), Symbol::new(&env, "SenderNotAllowed"));
+    assert_eq!(lop.error_name(&999), Symbol::new(&env, "Unknown"));
+}
+
+#[test]
+fn test_max_active_orders_per_maker() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP and allow two active orders per maker
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+    lop.set_max_active_orders_per_maker(&2);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_a = Address::generate(&env);
+    let token_b = Address::generate(&env);
+
+    let order_with_salt = |salt: u64| Order {
+        salt,
+        ..default_order(&env, &maker, &taker, &token_a, &token_b)
+    };
+
+    // Create up to the limit
+    lop.create_order(&order_with_salt(20));
+    lop.create_order(&order_with_salt(21));
+    assert_eq!(lop.get_active_order_count(&maker), 2);
+
+    // The next creation is rejected
+    assert_eq!(
+        lop.try_create_order(&order_with_salt(22)),
+        Err(Ok(Error::TooManyActiveOrders))
+    );
+
+    // Cancelling frees a slot
+    lop.cancel_order(&order_with_salt(20));
+    assert_eq!(lop.get_active_order_count(&maker), 1);
+    lop.create_order(&order_with_salt(22));
+    assert_eq!(lop.get_active_order_count(&maker), 2);
+}
+
+#[test]
+fn test_all_in_rate_includes_protocol_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let fee_recipient = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2020);
+
+    let order = Order {
+        salt: 23,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Without a fee the all-in rate is the base rate
+    let scale = 10_000_000;
+    let base_rate = lop.get_all_in_rate(&order, &scale);
+    assert_eq!(base_rate, 20_000_000);
+
+    // A 1% fee raises the all-in rate by 1%
+    lop.set_protocol_fee(&100, &fee_recipient);
+    let all_in_rate = lop.get_all_in_rate(&order, &scale);
+    assert_eq!(all_in_rate, 20_200_000);
+    assert_eq!(all_in_rate - base_rate, base_rate / 100);
+
+    // Filling charges the fee on top of the taking amount
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(token_b.balance(&fee_recipient), 20);
+    assert_eq!(token_b.balance(&taker), 0);
+
+    // Fees above 100% are rejected
+    assert_eq!(
+        lop.try_set_protocol_fee(&10_001, &fee_recipient),
+        Err(Ok(Error::InvalidFee))
+    );
+}
+
+#[test]
+fn test_max_affordable_fill() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &801);
+
+    let order = Order {
+        salt: 24,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // 801 of taker asset buys at most 400 of maker asset at 2 per unit
+    let cap = lop.max_affordable_fill(&order, &taker);
+    assert_eq!(cap, 400);
+
+    // The cap can actually be filled
+    assert_eq!(lop.fill_order_buy(&order, &taker, &cap), 800);
+    assert_eq!(token_b.balance(&taker), 1);
+    assert_eq!(lop.max_affordable_fill(&order, &taker), 0);
+
+    // Bounded by the remaining amount for a wealthy taker
+    token_b_admin.mint(&taker, &10_000);
+    assert_eq!(lop.max_affordable_fill(&order, &taker), 600);
+}
+
+#[test]
+fn test_post_fill_hook() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let hook = MockFillHookClient::new(&env, &env.register(MockFillHook, ()));
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    token_b_admin.mint(&taker, &4000);
+
+    let mut order = Order {
+        salt: 25,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Without a hook nothing is called
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(hook.last_fill(), None);
+
+    // With a hook it is called with the fill details
+    order.salt = 26;
+    order.post_fill_hook = Some(hook.address.clone());
+    order.post_fill_fn = Some(Symbol::new(&env, "on_fill"));
+    let order_hash = lop.create_order(&order);
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(hook.last_fill(), Some((order_hash, taker.clone(), 1000, 2000)));
+
+    // The hook function is signed over too, so a filler can't point the hook elsewhere
+    let mut redirected_order = order.clone();
+    redirected_order.post_fill_fn = Some(Symbol::new(&env, "last_fill"));
+    assert!(!lop.hashes_collide(&order, &redirected_order));
+}
+
+#[test]
+fn test_fill_order_in_settle_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+    let (settle_token, settle_token_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    settle_token_admin.mint(&taker, &6000);
+
+    // Quoted in token_b, payable in settle_token at a 1:1 peg
+    let mut order = Order {
+        salt: 27,
+        settle_token: Some(settle_token.address.clone()),
+        peg_rate: 10_000_000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    lop.fill_order_in_settle_token(&order, &taker);
+    assert_eq!(settle_token.balance(&maker), 2000);
+    assert_eq!(token_b.balance(&maker), 0);
+    assert_eq!(token_a.balance(&taker), 1000);
+
+    // At a 2:1 peg the taker pays twice as many settle tokens
+    order.salt = 28;
+    order.peg_rate = 20_000_000;
+    lop.fill_order_in_settle_token(&order, &taker);
+    assert_eq!(settle_token.balance(&maker), 6000);
+    assert_eq!(settle_token.balance(&taker), 0);
+    assert_eq!(token_a.balance(&taker), 2000);
+
+    // The peg rate must be positive
+    order.salt = 29;
+    order.peg_rate = 0;
+    assert_eq!(
+        lop.try_fill_order_in_settle_token(&order, &taker),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // A settle token can't pass for the hook address in the order hash
+    let mut hooked_order = order.clone();
+    hooked_order.settle_token = None;
+    hooked_order.post_fill_hook = Some(settle_token.address.clone());
+    assert!(!lop.hashes_collide(&order, &hooked_order));
+}
+
+#[test]
+fn test_admin_cancel_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 30,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    lop.admin_cancel_order(&order);
+
+    // Authorized by the admin, not the maker
+    assert_eq!(env.auths()[0].0, admin);
+    assert_eq!(lop.get_order_state(&order), OrderState::Cancelled);
+
+    // The order can no longer be filled and no funds moved
+    assert_eq!(lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX), Err(Ok(Error::OrderCancelled)));
+    assert_eq!(token_a.balance(&maker), 1000);
+    assert_eq!(token_b.balance(&taker), 2000);
+}
+
+#[test]
+fn test_admin_cancel_leaves_funds_for_maker_to_reclaim() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let (deposit_token, deposit_token_admin) = create_token_contract(&env, &token_admin);
+
+    lop.set_creation_deposit(&deposit_token.address, &10);
+    deposit_token_admin.mint(&maker, &10);
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 83,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    lop.create_order(&order);
+    lop.lock_funds(&order);
+    lop.fill_order(&order, &taker, &400, &i128::MAX);
+    assert_eq!(token_a.balance(&maker), 0);
+    assert_eq!(deposit_token.balance(&maker), 0);
+
+    // Only cancelled orders can be reclaimed
+    assert_eq!(lop.try_reclaim_order_funds(&order), Err(Ok(Error::InvalidOrder)));
+
+    lop.admin_cancel_order(&order);
+
+    // The admin cancel moves no funds
+    assert_eq!(token_a.balance(&maker), 0);
+    assert_eq!(token_a.balance(&lop.address), 600);
+    assert_eq!(deposit_token.balance(&lop.address), 10);
+    assert!(lop.is_locked(&order));
+
+    // The maker reclaims the unfilled locked funds and the deposit
+    lop.reclaim_order_funds(&order);
+    assert_eq!(token_a.balance(&maker), 600);
+    assert_eq!(token_a.balance(&lop.address), 0);
+    assert_eq!(deposit_token.balance(&maker), 10);
+    assert_eq!(deposit_token.balance(&lop.address), 0);
+    assert!(!lop.is_locked(&order));
+
+    // Nothing is left to reclaim a second time
+    assert_eq!(lop.try_reclaim_order_funds(&order), Err(Ok(Error::InvalidOrder)));
+}
+
+#[test]
+fn test_break_even_price() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500; // Midway through auction
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &3000);
+
+    let order = Order {
+        salt: 31,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION | ALLOW_PARTIAL_FILLS,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000,
+        taking_amount_end: 1500,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Break-even is the current price plus the deposit
+    let raw_price = lop.get_current_price(&order);
+    assert_eq!(raw_price, 2250);
+    assert_eq!(lop.break_even_price(&order, &50), raw_price + 50);
+
+    // After a partial fill the deposit is amortized over the smaller remainder
+    lop.fill_order_buy(&order, &taker, &600);
+    assert_eq!(lop.break_even_price(&order, &50), 900 + 50);
+
+    assert_eq!(
+        lop.try_break_even_price(&order, &-1),
+        Err(Ok(Error::InvalidDeposit))
+    );
+}
+
+#[test]
+fn test_reduce_order_signed() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker_key = SigningKey::from_bytes(&[7; 32]);
+    let maker = account_address(&env, &maker_key);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 32,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let order_hash = lop.create_order(&order);
+
+    // A valid maker signature lowers the remaining amount without maker auth
+    let signature = sign_reduction(&env, &maker_key, &lop.address, &order_hash, 400);
+    lop.reduce_order_signed(&order, &400, &signature);
+    assert!(env.auths().is_empty());
+
+    // Replaying the same reduction is rejected
+    assert_eq!(
+        lop.try_reduce_order_signed(&order, &400, &signature),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // Only the reduced amount can still be filled
+    assert_eq!(lop.max_affordable_fill(&order, &taker), 400);
+}
+
+#[test]
+fn test_reduce_order_signed_forged() {
+    let env = Env::default();
+    
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker_key = SigningKey::from_bytes(&[7; 32]);
+    let forger_key = SigningKey::from_bytes(&[8; 32]);
+    let maker = account_address(&env, &maker_key);
+
+    let order = Order {
+        salt: 33,
+        ..default_order(&env, &maker, &maker, &Address::generate(&env), &Address::generate(&env))
+    };
+
+    // A signature from another key is rejected
+    let order_hash = env.as_contract(&lop.address, || SorobanLOP::calculate_order_hash(&env, &order));
+    let forged = sign_reduction(&env, &forger_key, &lop.address, &order_hash, 400);
+    assert!(lop.try_reduce_order_signed(&order, &400, &forged).is_err());
+
+    // So is a maker signature for a different amount
+    let signed_other_amount = sign_reduction(&env, &maker_key, &lop.address, &order_hash, 500);
+    assert!(lop.try_reduce_order_signed(&order, &400, &signed_other_amount).is_err());
+
+    // And a maker signature for the same order on another LOP deployment
+    let other_lop = create_lop_contract(&env);
+    other_lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+    let signed_other_lop = sign_reduction(&env, &maker_key, &other_lop.address, &order_hash, 400);
+    assert!(lop.try_reduce_order_signed(&order, &400, &signed_other_lop).is_err());
+}
+
+#[test]
+fn test_full_quote_includes_all_fees() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let fee_recipient = Address::generate(&env);
+    let referrer = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2030);
+
+    let order = Order {
+        salt: 34,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // 1% protocol fee and 0.5% referral fee
+    lop.set_protocol_fee(&100, &fee_recipient);
+    lop.set_referral_fee(&50, &referrer);
+
+    let quote = lop.get_full_quote(&order, &taker);
+    assert_eq!(quote.taking_amount, 2000);
+    assert_eq!(quote.protocol_fee, 20);
+    assert_eq!(quote.referral_fee, 10);
+    assert_eq!(quote.maker_receives, 2000);
+    assert_eq!(
+        quote.total_outlay,
+        quote.taking_amount + quote.protocol_fee + quote.referral_fee
+    );
+
+    // The quote matches what a fill actually costs
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&maker), quote.maker_receives);
+    assert_eq!(token_b.balance(&fee_recipient), quote.protocol_fee);
+    assert_eq!(token_b.balance(&referrer), quote.referral_fee);
+    assert_eq!(token_b.balance(&taker), 2030 - quote.total_outlay);
+}
+
+#[test]
+fn test_dust_remainder_is_filled_in_full() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 35,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Remainders worth less than 100 of the taker asset are dust
+    lop.set_dust_threshold(&token_b.address, &100);
+    assert_eq!(lop.get_dust_threshold(&token_b.address), 100);
+
+    // Leaving 200 (worth 400) is fine
+    let charged = lop.fill_order_buy(&order, &taker, &800);
+    assert_eq!(charged, 1600);
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+
+    // Leaving 40 (worth 80) would be dust, so the fill closes the order
+    let charged = lop.fill_order_buy(&order, &taker, &160);
+    assert_eq!(charged, 400);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_check_auction_contract() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+
+    // Correctly configured LOP
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+    assert!(lop.check_auction_contract());
+
+    // LOP pointed at a token instead of an auction
+    let misconfigured = create_lop_contract(&env);
+    let (token, _) = create_token_contract(&env, &admin);
+    misconfigured.initialize(&admin, &token.address, &0, &admin);
+    assert!(!misconfigured.check_auction_contract());
+
+    // Uninitialized LOP
+    let uninitialized = create_lop_contract(&env);
+    assert!(!uninitialized.check_auction_contract());
+}
+
+#[test]
+fn test_reprice_order_keeps_fill_progress() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 36,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    let old_hash = lop.create_order(&order);
+    lop.fill_order_buy(&order, &taker, &300);
+
+    // Lower the price from 2 to 1.5 per maker unit
+    let new_hash = lop.reprice_order(&order, &1500);
+    let mut new_order = order.clone();
+    new_order.taking_amount = 1500;
+
+    assert_eq!(lop.get_order_state(&order), OrderState::Cancelled);
+    assert_eq!(lop.get_order_state(&new_order), OrderState::Active);
+    assert_eq!(lop.get_active_order_count(&maker), 1);
+    assert_ne!(new_hash, old_hash);
+
+    // The replacement is already registered
+    assert_eq!(lop.try_create_order(&new_order), Err(Ok(Error::InvalidOrder)));
+
+    // Only the 700 left over can be filled, at the new price
+    assert_eq!(
+        lop.try_fill_order_buy(&new_order, &taker, &701),
+        Err(Ok(Error::InvalidOrder))
+    );
+    lop.fill_order(&new_order, &taker, &700, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 600 + 1050);
+    assert_eq!(lop.get_order_state(&new_order), OrderState::Filled);
+    assert_eq!(lop.get_active_order_count(&maker), 0);
+}
+
+#[test]
+fn test_fill_order_partial_amounts() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    token_b_admin.mint(&taker, &4000);
+
+    let mut order = Order {
+        salt: 37,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Without the flag a partial fill is rejected
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &400, &i128::MAX),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // With the flag the order fills in parts
+    order.maker_traits = ALLOW_PARTIAL_FILLS;
+    let (making_amount, taking_amount) = lop.fill_order(&order, &taker, &400, &i128::MAX);
+    assert_eq!((making_amount, taking_amount), (400, 800));
+    assert_eq!(token_a.balance(&taker), 400);
+    assert_eq!(token_b.balance(&maker), 800);
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+
+    // More than the remaining 600 is rejected
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &601, &i128::MAX),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // Filling the rest closes the order
+    lop.fill_order(&order, &taker, &600, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_fee_exempt_taker_pays_no_protocol_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let exempt_taker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let fee_recipient = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    token_b_admin.mint(&exempt_taker, &2020);
+    token_b_admin.mint(&taker, &2020);
+
+    let mut order = Order {
+        salt: 38,
+        ..default_order(&env, &maker, &lop.address, &token_a.address, &token_b.address)
+    };
+
+    lop.set_protocol_fee(&100, &fee_recipient);
+    lop.set_fee_exempt(&exempt_taker);
+    assert!(lop.is_fee_exempt(&exempt_taker));
+    assert!(!lop.is_fee_exempt(&taker));
+
+    // The exempt taker pays only the taking amount
+    lop.fill_order(&order, &exempt_taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&exempt_taker), 20);
+    assert_eq!(token_b.balance(&fee_recipient), 0);
+
+    // An identical order filled by a regular taker pays the 1% fee
+    order.salt = 39;
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&taker), 0);
+    assert_eq!(token_b.balance(&fee_recipient), 20);
+
+    // Removing the exemption restores the fee
+    lop.unset_fee_exempt(&exempt_taker);
+    assert!(!lop.is_fee_exempt(&exempt_taker));
+}
+
+#[test]
+fn test_unwrap_weth_requires_native_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (native, native_admin) = create_token_contract(&env, &token_admin);
+    let (wrapped, wrapped_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    native_admin.mint(&maker, &1000);
+    wrapped_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &4000);
+
+    let mut order = Order {
+        salt: 40,
+        maker_traits: UNWRAP_WETH,
+        ..default_order(&env, &maker, &taker, &wrapped.address, &token_b.address)
+    };
+
+    // Without a native token configured nothing can be unwrapped
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::UnwrapFailed))
+    );
+
+    // A maker asset other than the native token is rejected, not delivered as-is
+    lop.set_native_token(&native.address);
+    assert_eq!(lop.get_native_token(), Some(native.address.clone()));
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::UnwrapFailed))
+    );
+    assert_eq!(wrapped.balance(&taker), 0);
+
+    // The native token is delivered to the receiver
+    order.maker_asset = native.address.clone();
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(native.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+}
+
+#[test]
+fn test_order_hash_covers_assets() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_c, token_c_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_c_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 41,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Same salt and amounts, different maker asset
+    let mut other_order = order.clone();
+    other_order.maker_asset = token_c.address.clone();
+
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+
+    // Filling one order doesn't mark the other as filled
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+    assert_eq!(lop.get_order_state(&other_order), OrderState::Active);
+    assert_eq!(token_c.balance(&maker), 1000);
+}
+
+#[test]
+fn test_order_expiration() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1000;
+    });
+
+    // Short-lived order valid for 300 seconds
+    let order = Order {
+        salt: 42,
+        expiration: 1300,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    assert!(lop.is_fillable_at(&order, &1299));
+    assert!(!lop.is_fillable_at(&order, &1300));
+
+    // Fills at or after the expiration revert
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1300;
+    });
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::OrderExpired))
+    );
+
+    // Within the window the order fills normally
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1299;
+    });
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_flash_fill() {
+    let env = Env::default();
+    // The callback mints with the token admin's auth deep in the call tree
+    env.mock_all_auths_allowing_non_root_auth();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+
+    // The taker starts with no taker asset at all
+    let taker = env.register(MockFlashTaker, ());
+    let flash_taker = MockFlashTakerClient::new(&env, &taker);
+    let callback_fn = Symbol::new(&env, "on_flash");
+
+    let mut order = Order {
+        salt: 43,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // A callback that sources the payment completes the fill
+    flash_taker.setup(&token_b.address, &true);
+    lop.flash_fill(&order, &taker, &taker, &callback_fn);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+
+    // A callback that doesn't pay reverts everything
+    order.salt = 44;
+    flash_taker.setup(&token_b.address, &false);
+    assert_eq!(
+        lop.try_flash_fill(&order, &taker, &taker, &callback_fn),
+        Err(Ok(Error::InsufficientBalance))
+    );
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_a.balance(&maker), 1000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+}
+
+#[test]
+fn test_build_maker_traits() {
+    let env = Env::default();
+    let lop = create_lop_contract(&env);
+
+    assert_eq!(lop.build_maker_traits(&false, &false, &false, &false, &false, &false, &false, &false), 0);
+
+    // Dutch auction + partial fills + auction-end cutoff
+    let maker_traits = lop.build_maker_traits(&true, &false, &true, &false, &true, &false, &false, &false);
+    assert_eq!(maker_traits, 0b10101);
+    assert_eq!(
+        maker_traits,
+        IS_DUTCH_AUCTION | ALLOW_PARTIAL_FILLS | CUTOFF_AT_AUCTION_END
+    );
+
+    // Pre- and post-interaction hooks
+    let maker_traits = lop.build_maker_traits(&false, &false, &false, &false, &false, &true, &true, &false);
+    assert_eq!(maker_traits, HAS_PRE_INTERACTION | HAS_POST_INTERACTION);
+
+    // Dutch auction timed from the first fill
+    let maker_traits = lop.build_maker_traits(&true, &false, &false, &false, &false, &false, &false, &true);
+    assert_eq!(maker_traits, IS_DUTCH_AUCTION | RELATIVE_AUCTION);
+}
+
+#[test]
+fn test_fill_orders_batch() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &3000);
+    token_b_admin.mint(&taker, &6000);
+
+    let first = Order {
+        salt: 45,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let mut second = first.clone();
+    second.salt = 46;
+    second.making_amount = 500;
+    second.taking_amount = 1500;
+
+    // Both orders fill in one call
+    lop.fill_orders(&vec![&env, first.clone(), second.clone()], &taker);
+    assert_eq!(lop.get_order_state(&first), OrderState::Filled);
+    assert_eq!(lop.get_order_state(&second), OrderState::Filled);
+    assert_eq!(token_a.balance(&taker), 1500);
+    assert_eq!(token_b.balance(&maker), 3500);
+
+    // A batch containing a filled order reverts entirely with the first error
+    let mut third = first.clone();
+    third.salt = 47;
+    assert_eq!(
+        lop.try_fill_orders(&vec![&env, third.clone(), first.clone()], &taker),
+        Err(Ok(Error::OrderAlreadyFilled))
+    );
+    assert_eq!(lop.get_order_state(&third), OrderState::Active);
+    assert_eq!(token_a.balance(&taker), 1500);
+}
+
+#[test]
+fn test_fill_fee_deducted_from_maker() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500; // Midway through auction
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    let relayer = Address::generate(&env);
+
+    // Fees above 100% are rejected
+    assert_eq!(
+        lop.try_initialize(&admin, &dutch_auction.address, &10_001, &relayer),
+        Err(Ok(Error::InvalidFee))
+    );
+
+    // Initialize LOP with a 2% fill fee for the relayer
+    lop.initialize(&admin, &dutch_auction.address, &200, &relayer);
+    assert_eq!(lop.get_fee_config().fee_bps, 200);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    token_b_admin.mint(&taker, &6000);
+
+    let mut order = Order {
+        salt: 48,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000,
+        taking_amount_end: 1500,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    let quote = lop.get_full_quote(&order, &taker);
+    assert_eq!(quote.fill_fee, 45);
+    assert_eq!(quote.maker_receives, 2205);
+
+    // The fee is 2% of the auction price (2250), not of the static taking amount
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&relayer), 45);
+    assert_eq!(token_b.balance(&maker), 2205);
+    assert_eq!(token_b.balance(&taker), 3750);
+
+    // An exempt maker keeps the whole taking amount
+    lop.set_fee_exempt(&maker);
+    order.salt = 82;
+    assert_eq!(lop.get_full_quote(&order, &taker).fill_fee, 0);
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&relayer), 45);
+    assert_eq!(token_b.balance(&maker), 2205 + 2250);
+}
+
+#[test]
+fn test_fill_and_escrow() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let escrow = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let (token_c, token_c_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+    token_c_admin.mint(&taker, &500);
+
+    let factory = MockEscrowFactoryClient::new(&env, &env.register(MockEscrowFactory, ()));
+
+    let order = Order {
+        salt: 49,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    let immutables = EscrowImmutables {
+        hashlock: BytesN::from_array(&env, &[7; 32]),
+        maker: maker.clone(),
+        taker: taker.clone(),
+        token: token_c.address.clone(),
+        amount: 500,
+        timelocks: EscrowTimelocks {
+            deployed_at: 0,
+            withdrawal: 0,
+            public_withdrawal: 12000,
+            cancellation: 12345,
+            public_cancellation: 12345,
+        },
+        safety_deposit: 0,
+        deposit_token: token_c.address.clone(),
+        hash_algorithm: 0,
+        arbiter: None,
+    };
+    let salt = BytesN::from_array(&env, &[9; 32]);
+
+    // A failed deployment reverts the fill too
+    factory.configure(&escrow, &true);
+    assert_eq!(
+        lop.try_fill_and_escrow(&order, &taker, &factory.address, &immutables, &salt),
+        Err(Ok(Error::EscrowDeploymentFailed))
+    );
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+    assert_eq!(token_a.balance(&maker), 1000);
+    assert_eq!(token_b.balance(&taker), 2000);
+
+    factory.configure(&escrow, &false);
+    let escrow_address = lop.fill_and_escrow(&order, &taker, &factory.address, &immutables, &salt);
+    assert_eq!(escrow_address, escrow);
+
+    // Both the fill and the funded escrow deployment happened
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(factory.deployed(), Some((immutables, salt)));
+    assert_eq!(token_c.balance(&escrow), 500);
+    assert_eq!(token_c.balance(&taker), 0);
+}
+
+#[test]
+fn test_maker_order_hashes() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    token_b_admin.mint(&taker, &4000);
+
+    let first = Order {
+        salt: 50,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let mut second = first.clone();
+    second.salt = 51;
+    let first_hash = env.as_contract(&lop.address, || SorobanLOP::calculate_order_hash(&env, &first));
+    let second_hash = env.as_contract(&lop.address, || SorobanLOP::calculate_order_hash(&env, &second));
+
+    assert_eq!(lop.get_maker_order_hashes(&maker), Vec::new(&env));
+
+    // Partially filled orders are listed once, however often they are touched
+    lop.fill_order(&first, &taker, &200, &i128::MAX);
+    lop.fill_order(&first, &taker, &200, &i128::MAX);
+    lop.fill_order(&second, &taker, &200, &i128::MAX);
+    assert_eq!(
+        lop.get_maker_order_hashes(&maker),
+        vec![&env, first_hash.clone(), second_hash.clone()]
+    );
+
+    // Filled and cancelled orders are pruned
+    lop.fill_order(&first, &taker, &600, &i128::MAX);
+    assert_eq!(lop.get_maker_order_hashes(&maker), vec![&env, second_hash]);
+    lop.cancel_order(&second);
+    assert_eq!(lop.get_maker_order_hashes(&maker), Vec::new(&env));
+}
+
+#[test]
+fn test_get_state_by_salt() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let order = Order {
+        salt: 52,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Unknown until the order is created
+    assert_eq!(lop.try_get_state_by_salt(&maker, &52), Err(Ok(Error::OrderNotFound)));
+
+    lop.create_order(&order);
+    assert_eq!(lop.get_state_by_salt(&maker, &52), OrderState::Active);
+
+    // The index is per maker
+    assert_eq!(lop.try_get_state_by_salt(&taker, &52), Err(Ok(Error::OrderNotFound)));
+
+    lop.cancel_order(&order);
+    assert_eq!(lop.get_state_by_salt(&maker, &52), OrderState::Cancelled);
+}
+
+#[test]
+fn test_pre_and_post_interactions() {
+    let env = Env::default();
+    env.mock_all_auths_allowing_non_root_auth();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+
+    let interaction = MockInteractionClient::new(&env, &env.register(MockInteraction, ()));
+
+    let mut order = Order {
+        salt: 53,
+        maker_traits: HAS_PRE_INTERACTION | HAS_POST_INTERACTION,
+        post_interaction: Some(interaction.address.clone()),
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // A flag without its contract is rejected
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // Moving the contract to the other side of the fill yields a different order
+    let mut swapped_order = order.clone();
+    swapped_order.pre_interaction = order.post_interaction.clone();
+    swapped_order.post_interaction = None;
+    assert!(!lop.hashes_collide(&order, &swapped_order));
+
+    order.pre_interaction = Some(interaction.address.clone());
+
+    // A trapping interaction reverts the whole fill
+    interaction.prepare(&token_b.address, &taker, &true);
+    assert!(lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX).is_err());
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+    assert_eq!(token_a.balance(&maker), 1000);
+
+    // The taker holds nothing up front; the pre-interaction sources the payment
+    interaction.prepare(&token_b.address, &taker, &false);
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(token_b.balance(&taker), 0);
+
+    let order_hash = env.as_contract(&lop.address, || SorobanLOP::calculate_order_hash(&env, &order));
+    assert_eq!(interaction.last_post_interaction(), Some((order_hash, 1000, 2000)));
+}
+
+#[test]
+fn test_max_taking_amount_start() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1500; // Midway through auction
+    });
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &3000);
+
+    let mut order = Order {
+        salt: 54,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3001,
+        taking_amount_end: 1500,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    assert_eq!(lop.try_set_max_taking_amount_start(&-1), Err(Ok(Error::InvalidOrder)));
+    assert_eq!(lop.get_max_taking_amount_start(), 0);
+    lop.set_max_taking_amount_start(&3000);
+    assert_eq!(lop.get_max_taking_amount_start(), 3000);
+
+    // Beyond the cap the order can't be created or filled
+    assert_eq!(lop.try_create_order(&order), Err(Ok(Error::InvalidOrder)));
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // Exactly at the cap it fills
+    order.taking_amount_start = 3000;
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+    assert_eq!(token_b.balance(&maker), 2250);
+}
+
+#[test]
+fn test_preview_transfers_matches_fill() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let fee_recipient = Address::generate(&env);
+    let referrer = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &1000);
+
+    // 2% fill fee withheld from the maker, 1% protocol fee and 0.5% referral fee on top
+    lop.initialize(&admin, &dutch_auction.address, &200, &fee_recipient);
+    lop.set_protocol_fee(&100, &fee_recipient);
+    lop.set_referral_fee(&50, &referrer);
+
+    let order = Order {
+        salt: 55,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    let transfers = lop.preview_transfers(&order, &taker, &400);
+    assert_eq!(
+        transfers,
+        vec![
+            &env,
+            (token_a.address.clone(), maker.clone(), taker.clone(), 400),
+            (token_b.address.clone(), taker.clone(), maker.clone(), 784),
+            (token_b.address.clone(), taker.clone(), fee_recipient.clone(), 16),
+            (token_b.address.clone(), taker.clone(), fee_recipient.clone(), 8),
+            (token_b.address.clone(), taker.clone(), referrer.clone(), 4),
+        ]
+    );
+
+    // The fill moves exactly what the preview listed
+    lop.fill_order(&order, &taker, &400, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 400);
+    assert_eq!(token_b.balance(&maker), 784);
+    assert_eq!(token_b.balance(&fee_recipient), 16 + 8);
+    assert_eq!(token_b.balance(&referrer), 4);
+    assert_eq!(token_a.balance(&maker), 600);
+    assert_eq!(token_b.balance(&taker), 1000 - 784 - 16 - 8 - 4);
+}
+
+#[test]
+fn test_pause_blocks_fills_but_not_cancels() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Pausing needs an admin
+    assert_eq!(lop.try_pause(), Err(Ok(Error::NotInitialized)));
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 56,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let mut other = order.clone();
+    other.salt = 57;
+
+    assert!(!lop.is_paused());
+    lop.pause();
+    assert!(lop.is_paused());
+
+    // Fills are rejected while paused
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::Paused))
+    );
+    assert_eq!(lop.try_fill_orders(&vec![&env, order.clone()], &taker), Err(Ok(Error::Paused)));
+
+    // Makers can still exit
+    lop.cancel_order(&other);
+    assert_eq!(lop.get_order_state(&other), OrderState::Cancelled);
+
+    lop.unpause();
+    assert!(!lop.is_paused());
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_receiver_rejecting_tokens() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let hook_token = env.register(MockHookToken, ());
+    let receiver = env.register(MockRejectingReceiver, ());
+
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 58,
+        ..default_order(&env, &maker, &receiver, &hook_token, &token_b.address)
+    };
+
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::ReceiverRejected))
+    );
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+    assert_eq!(token_b.balance(&taker), 2000);
+}
+
+#[test]
+fn test_maker_side_transfer_failure_is_not_receiver_rejected() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let receiver = env.register(MockRejectingReceiver, ());
+
+    // Maker asset whose issuer can freeze holders
+    let sac = env.register_stellar_asset_contract_v2(token_admin.clone());
+    sac.issuer().set_flag(IssuerFlags::RevocableFlag);
+    let token_a = token::Client::new(&env, &sac.address());
+    let token_a_admin = token::StellarAssetClient::new(&env, &sac.address());
+
+    token_a_admin.mint(&maker, &500);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 59,
+        ..default_order(&env, &maker, &receiver, &token_a.address, &token_b.address)
+    };
+
+    // A maker short of the maker asset fails with the token's error, not ReceiverRejected
+    let result = lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert!(result.is_err());
+    assert_ne!(result, Err(Ok(Error::ReceiverRejected)));
+
+    // So does a maker whose holding is frozen
+    token_a_admin.mint(&maker, &500);
+    token_a_admin.set_authorized(&maker, &false);
+    let result = lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert!(result.is_err());
+    assert_ne!(result, Err(Ok(Error::ReceiverRejected)));
+
+    // Once the maker can pay, the contract receiver gets the tokens
+    token_a_admin.set_authorized(&maker, &true);
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_a.balance(&receiver), 1000);
+}
+
+#[test]
+fn test_min_fill_making_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // Partial fills must move at least 10% of the order
+    let order = Order {
+        salt: 59,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        min_fill_making_amount: 100,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    assert_eq!(lop.try_fill_order(&order, &taker, &1, &i128::MAX), Err(Ok(Error::FillTooSmall)));
+    assert_eq!(lop.try_fill_order(&order, &taker, &99, &i128::MAX), Err(Ok(Error::FillTooSmall)));
+    lop.fill_order(&order, &taker, &100, &i128::MAX);
+    lop.fill_order(&order, &taker, &850, &i128::MAX);
+
+    // The last 50 are below the minimum but may be taken as the whole remainder
+    assert_eq!(lop.try_fill_order(&order, &taker, &10, &i128::MAX), Err(Ok(Error::FillTooSmall)));
+    lop.fill_order(&order, &taker, &50, &i128::MAX);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+    assert_eq!(token_a.balance(&taker), 1000);
+}
+
+#[test]
+fn test_accrued_fees_per_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+    let (token_c, token_c_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &2000);
+    token_b_admin.mint(&taker, &2020);
+    token_c_admin.mint(&taker, &505);
+
+    // A 1% protocol fee kept by the LOP
+    lop.set_protocol_fee(&100, &lop.address);
+
+    let order_b = Order {
+        salt: 60,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let mut order_c = order_b.clone();
+    order_c.salt = 61;
+    order_c.taker_asset = token_c.address.clone();
+    order_c.taking_amount = 500;
+
+    assert_eq!(lop.get_accrued_fees(&token_b.address), 0);
+
+    lop.fill_order(&order_b, &taker, &order_b.making_amount, &i128::MAX);
+    lop.fill_order(&order_c, &taker, &order_c.making_amount, &i128::MAX);
+
+    // Each token accrues independently
+    assert_eq!(lop.get_accrued_fees(&token_b.address), 20);
+    assert_eq!(lop.get_accrued_fees(&token_c.address), 5);
+
+    // Collecting one token leaves the other untouched
+    assert_eq!(lop.collect_fees(&token_b.address, &treasury), 20);
+    assert_eq!(token_b.balance(&treasury), 20);
+    assert_eq!(lop.get_accrued_fees(&token_b.address), 0);
+    assert_eq!(lop.get_accrued_fees(&token_c.address), 5);
+}
+
+#[test]
+fn test_replace_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2500);
+
+    let old_order = Order {
+        salt: 62,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let mut new_order = old_order.clone();
+    new_order.salt = 63;
+    new_order.taking_amount = 2500;
+
+    // Orders of another maker can't be swapped in
+    let mut foreign_order = new_order.clone();
+    foreign_order.maker = taker.clone();
+    assert_eq!(
+        lop.try_replace_order(&old_order, &foreign_order),
+        Err(Ok(Error::NotAuthorized))
+    );
+
+    // The old order is cancelled and the new one is active in the same call
+    lop.create_order(&old_order);
+    lop.replace_order(&old_order, &new_order);
+    assert_eq!(lop.get_order_state(&old_order), OrderState::Cancelled);
+    assert_eq!(lop.get_state_by_salt(&maker, &63), OrderState::Active);
+    assert_eq!(lop.get_active_order_count(&maker), 1);
+
+    // Filled orders can't be replaced
+    lop.fill_order(&new_order, &taker, &new_order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&maker), 2500);
+    let mut newer_order = new_order.clone();
+    newer_order.salt = 64;
+    assert_eq!(
+        lop.try_replace_order(&new_order, &newer_order),
+        Err(Ok(Error::OrderAlreadyFilled))
+    );
+}
+
+#[test]
+fn test_replace_order_validates_new_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let old_order = Order {
+        salt: 66,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    lop.create_order(&old_order);
+
+    // A replacement `create_order` would reject is rejected here too
+    let stray_auction_order = Order {
+        salt: 67,
+        auction_end_time: 1000,
+        ..old_order.clone()
+    };
+    assert_eq!(lop.try_create_order(&stray_auction_order), Err(Ok(Error::InvalidOrder)));
+    assert_eq!(
+        lop.try_replace_order(&old_order, &stray_auction_order),
+        Err(Ok(Error::InvalidOrder))
+    );
+    assert_eq!(lop.get_order_state(&old_order), OrderState::Active);
+}
+
+#[test]
+fn test_replace_uncreated_order_takes_deposit() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+    let (deposit_token, deposit_token_admin) = create_token_contract(&env, &token_admin);
+
+    lop.set_creation_deposit(&deposit_token.address, &10);
+    deposit_token_admin.mint(&maker, &20);
+
+    // An order that was never created has no deposit to hand over
+    let old_order = Order {
+        salt: 68,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+    let new_order = Order {
+        salt: 69,
+        ..old_order.clone()
+    };
+    lop.replace_order(&old_order, &new_order);
+    assert_eq!(deposit_token.balance(&maker), 10);
+    assert_eq!(deposit_token.balance(&lop.address), 10);
+
+    // A created order's deposit moves to its replacement instead of being taken twice
+    let newer_order = Order {
+        salt: 70,
+        ..old_order.clone()
+    };
+    lop.replace_order(&new_order, &newer_order);
+    assert_eq!(deposit_token.balance(&maker), 10);
+
+    // Cancelling the replacement refunds the one deposit it carries
+    lop.cancel_order(&newer_order);
+    assert_eq!(deposit_token.balance(&maker), 20);
+    assert_eq!(deposit_token.balance(&lop.address), 0);
+}
+
+#[test]
+fn test_relative_auction_starts_at_first_fill() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // Absolute times are ignored; the window is the 1000s after the first fill
+    let order = Order {
+        salt: 65,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION | RELATIVE_AUCTION | CUTOFF_AT_AUCTION_END | ALLOW_PARTIAL_FILLS,
+        auction_duration: 1000,
+        taking_amount_start: 2000,
+        taking_amount_end: 1000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Queries price an unstarted auction as if it started now, without starting it
+    env.ledger().with_mut(|li| { li.timestamp = 5000; });
+    assert!(lop.is_fillable_at(&order, &9000));
+    assert_eq!(lop.get_current_price(&order), 2000);
+    lop.preview_transfers(&order, &taker, &order.making_amount);
+    env.ledger().with_mut(|li| { li.timestamp = 5500; });
+    assert_eq!(lop.get_current_price(&order), 2000);
+
+    // The first fill starts the clock
+    lop.fill_order(&order, &taker, &100, &i128::MAX);
+    assert_eq!(token_b.balance(&maker), 200);
+
+    // Later queries decay from the recorded start
+    env.ledger().with_mut(|li| { li.timestamp = 6000; });
+    assert_eq!(lop.get_current_price(&order), 1500);
+    env.ledger().with_mut(|li| { li.timestamp = 6250; });
+    assert_eq!(lop.get_current_price(&order), 1250);
+    assert!(!lop.is_fillable_at(&order, &6500));
+
+    // Fills are priced on the same clock
+    lop.fill_order(&order, &taker, &900, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 1325);
+}
+
+#[test]
+fn test_fill_order_to() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let receiver = Address::generate(&env);
+    let dest = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // The order names its own receiver, but the explicit destination wins
+    let order = Order {
+        salt: 66,
+        ..default_order(&env, &maker, &receiver, &token_a.address, &token_b.address)
+    };
+
+    assert_eq!(lop.fill_order_to(&order, &taker, &dest), (1000, 2000));
+
+    // Maker asset went to the destination, the taker asset came from the taker
+    assert_eq!(token_a.balance(&dest), 1000);
+    assert_eq!(token_a.balance(&receiver), 0);
+    assert_eq!(token_a.balance(&taker), 0);
+    assert_eq!(token_b.balance(&taker), 0);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_hashes_collide() {
+    let env = Env::default();
+
+    let lop = create_lop_contract(&env);
+    let maker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let order = Order {
+        salt: 67,
+        ..default_order(&env, &maker, &maker, &token_a.address, &token_b.address)
+    };
+
+    // Identical orders collide
+    assert!(lop.hashes_collide(&order, &order.clone()));
+
+    // Orders differing in any hashed field don't
+    let mut other_order = order.clone();
+    other_order.salt = 68;
+    assert!(!lop.hashes_collide(&order, &other_order));
+}
+
+#[test]
+fn test_fill_order_slippage_protection() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // The price decays, so a fill landing before the quoted time costs more
+    let order = Order {
+        salt: 69,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 2000,
+        taking_amount_end: 1000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Quote late in the auction, then the fill lands earlier at a higher price
+    env.ledger().with_mut(|li| { li.timestamp = 1750; });
+    let quote = lop.get_current_price(&order);
+    assert_eq!(quote, 1250);
+
+    env.ledger().with_mut(|li| { li.timestamp = 1500; });
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &quote),
+        Err(Ok(Error::SlippageExceeded))
+    );
+    assert_eq!(token_a.balance(&maker), 1000);
+    assert_eq!(token_b.balance(&taker), 2000);
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+
+    // Within the bound the fill goes through
+    assert_eq!(lop.fill_order(&order, &taker, &order.making_amount, &1500), (1000, 1500));
+    assert_eq!(token_b.balance(&maker), 1500);
+}
+
+#[test]
+fn test_order_timestamps() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 70,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Nothing has happened yet
+    assert_eq!(lop.get_order_timestamps(&order), OrderTimestamps::default());
+
+    env.ledger().with_mut(|li| { li.timestamp = 100; });
+    lop.create_order(&order);
+
+    env.ledger().with_mut(|li| { li.timestamp = 250; });
+    lop.fill_order(&order, &taker, &400, &i128::MAX);
+    assert_eq!(
+        lop.get_order_timestamps(&order),
+        OrderTimestamps { created_at: 100, first_fill_at: 250, completed_at: 0 }
+    );
+
+    // Later fills don't move the first fill time
+    env.ledger().with_mut(|li| { li.timestamp = 600; });
+    lop.fill_order(&order, &taker, &600, &i128::MAX);
+    assert_eq!(
+        lop.get_order_timestamps(&order),
+        OrderTimestamps { created_at: 100, first_fill_at: 250, completed_at: 600 }
+    );
+}
+
+#[test]
+fn test_fill_order_by_taking() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 71,
+        taking_amount: 0,
+        maker_traits: IS_DUTCH_AUCTION | ALLOW_PARTIAL_FILLS,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 2000,
+        taking_amount_end: 1000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Halfway through the price is 1.5 taker units per maker unit
+    env.ledger().with_mut(|li| { li.timestamp = 1500; });
+    assert_eq!(lop.fill_order_by_taking(&order, &taker, &750), (500, 750));
+    assert_eq!(token_a.balance(&taker), 500);
+    assert_eq!(token_b.balance(&maker), 750);
+
+    // At the end price a budget beyond the remainder is rejected
+    env.ledger().with_mut(|li| { li.timestamp = 2000; });
+    assert_eq!(
+        lop.try_fill_order_by_taking(&order, &taker, &600),
+        Err(Ok(Error::InvalidOrder))
+    );
+    assert_eq!(lop.fill_order_by_taking(&order, &taker, &500), (500, 500));
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 1250);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}
+
+#[test]
+fn test_private_order_allowed_taker() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let counterparty = Address::generate(&env);
+    let outsider = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&counterparty, &2000);
+    token_b_admin.mint(&outsider, &2000);
+
+    // OTC order for a single counterparty
+    let order = Order {
+        salt: 72,
+        maker_traits: HAS_ALLOWED_TAKERS,
+        allowed_takers: vec![&env, counterparty.clone()],
+        ..default_order(&env, &maker, &lop.address, &token_a.address, &token_b.address)
+    };
+
+    // Anyone else is rejected
+    assert_eq!(
+        lop.try_fill_order(&order, &outsider, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::SenderNotAllowed))
+    );
+
+    // The counterparty is part of the hash and can't be swapped out
+    let mut swapped_order = order.clone();
+    swapped_order.allowed_takers = vec![&env, outsider.clone()];
+    assert!(!lop.hashes_collide(&order, &swapped_order));
+
+    lop.fill_order(&order, &counterparty, &order.making_amount, &i128::MAX);
+    assert_eq!(token_a.balance(&counterparty), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(token_b.balance(&outsider), 2000);
+}
+
+#[test]
+fn test_stray_auction_fields_rejected() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // Fixed-price order carrying auction parameters it would never use
+    let order = Order {
+        salt: 73,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        taking_amount_start: 3000,
+        taking_amount_end: 1000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    assert_eq!(lop.try_create_order(&order), Err(Ok(Error::InvalidOrder)));
+
+    // A single stray field is enough
+    let mut stray_order = order.clone();
+    stray_order.auction_start_time = 0;
+    stray_order.auction_end_time = 0;
+    stray_order.taking_amount_start = 0;
+    assert_eq!(lop.try_create_order(&stray_order), Err(Ok(Error::InvalidOrder)));
+
+    // Once strict mode is on, fills reject them too
+    assert!(!lop.get_strict_auction_fields());
+    lop.set_strict_auction_fields(&true);
+    assert!(lop.get_strict_auction_fields());
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::InvalidOrder))
+    );
+
+    // Once cleaned up the order fills at its fixed price
+    let mut clean_order = stray_order.clone();
+    clean_order.taking_amount_end = 0;
+    lop.fill_order(&clean_order, &taker, &clean_order.making_amount, &i128::MAX);
+    assert_eq!(token_b.balance(&maker), 2000);
+}
+
+#[test]
+fn test_legacy_order_with_auction_fields_fills() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    // Fixed-price order signed before auction fields were checked
+    let order = Order {
+        salt: 74,
+        auction_start_time: 1000,
+        auction_end_time: 2000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Fills ignore the stray fields unless the admin turns on strict mode
+    lop.fill_order(&order, &taker, &order.making_amount, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(token_b.balance(&maker), 2000);
+}
+
+#[test]
+fn test_hash_order_is_stable() {
+    let env = Env::default();
+
+    let lop = create_lop_contract(&env);
+
+    // Fixed addresses so the hash doesn't depend on test address generation
+    let maker = Address::from_str(&env, "GAAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQDZ7H");
+    let order = Order {
+        salt: 1,
+        maker: maker.clone(),
+        receiver: maker.clone(),
+        maker_asset: Address::from_str(&env, "CABAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAFNSZ"),
+        taker_asset: Address::from_str(&env, "CABQGAYDAMBQGAYDAMBQGAYDAMBQGAYDAMBQGAYDAMBQGAYDAMBQGCK3"),
+        making_amount: 1000,
+        taking_amount: 2000,
+        maker_traits: 0,
+        auction_start_time: 0,
+        auction_end_time: 0,
+        auction_duration: 0,
+        taking_amount_start: 0,
+        taking_amount_end: 0,
+        allowed_takers: Vec::new(&env),
+        post_fill_hook: None,
+        post_fill_fn: None,
+        settle_token: None,
+        peg_rate: 0,
+        expiration: 0,
+        pre_interaction: None,
+        post_interaction: None,
+        min_fill_making_amount: 0,
+    };
+
+    // The public hash is the one the contract keys order state by
+    let order_hash = lop.hash_order(&order);
+    assert_eq!(
+        order_hash,
+        env.as_contract(&lop.address, || SorobanLOP::calculate_order_hash(&env, &order))
+    );
+
+    // Changing the hash layout invalidates every stored order state and signature
+    assert_eq!(
+        order_hash,
+        BytesN::from_array(
+            &env,
+            &[
+                119, 147, 149, 144, 67, 65, 234, 188, 94, 15, 172, 198, 173, 141, 43, 22, 42, 55, 231,
+                67, 80, 222, 247, 14, 176, 229, 136, 241, 54, 153, 234, 64,
+            ]
+        )
+    );
+
+    // Optional fields are pinned with their presence tags too
+    let contract = Address::from_str(&env, "CACQKBIFAUCQKBIFAUCQKBIFAUCQKBIFAUCQKBIFAUCQKBIFAUCQLC2U");
+    let mut full_order = order.clone();
+    full_order.post_fill_hook = Some(contract.clone());
+    full_order.post_fill_fn = Some(Symbol::new(&env, "on_fill"));
+    full_order.settle_token = Some(contract.clone());
+    full_order.peg_rate = 10_000_000;
+    full_order.pre_interaction = Some(contract.clone());
+    full_order.post_interaction = Some(contract);
+    assert_eq!(
+        lop.hash_order(&full_order),
+        BytesN::from_array(
+            &env,
+            &[
+                136, 90, 70, 99, 179, 217, 248, 160, 192, 106, 153, 132, 132, 157, 253, 84, 224, 148, 244,
+                220, 179, 30, 183, 2, 100, 222, 167, 226, 245, 213, 242, 155,
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_get_remaining_making_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+
+    // Initialize LOP
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 74,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Never stored: the whole order is available
+    assert_eq!(lop.get_remaining_making_amount(&order), 1000);
+
+    lop.fill_order(&order, &taker, &300, &i128::MAX);
+    assert_eq!(lop.get_remaining_making_amount(&order), 700);
+
+    // Cancelled orders have nothing left
+    lop.cancel_order(&order);
+    assert_eq!(lop.get_remaining_making_amount(&order), 0);
+
+    // Neither do filled ones
+    token_a_admin.mint(&maker, &300);
+    token_b_admin.mint(&taker, &600);
+    let mut filled_order = order.clone();
+    filled_order.salt = 75;
+    lop.fill_order(&filled_order, &taker, &filled_order.making_amount, &i128::MAX);
+    assert_eq!(lop.get_remaining_making_amount(&filled_order), 0);
+}
+
+#[test]
+fn test_cancel_order_by_hash() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, _) = create_token_contract(&env, &token_admin);
+    let (token_b, _) = create_token_contract(&env, &token_admin);
+
+    let order = Order {
+        salt: 76,
+        ..default_order(&env, &maker, &maker, &token_a.address, &token_b.address)
+    };
+
+    // Hashes nobody has touched cannot be cancelled
+    let order_hash = lop.hash_order(&order);
+    assert_eq!(
+        lop.try_cancel_order_by_hash(&order_hash, &maker),
+        Err(Ok(Error::NotAuthorized))
+    );
+
+    lop.create_order(&order);
+    assert_eq!(
+        lop.try_cancel_order_by_hash(&order_hash, &stranger),
+        Err(Ok(Error::NotAuthorized))
+    );
+
+    lop.cancel_order_by_hash(&order_hash, &maker);
+    assert_eq!(lop.get_order_state(&order), OrderState::Cancelled);
+    assert_eq!(lop.get_active_order_count(&maker), 0);
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::OrderCancelled))
+    );
+}
+
+#[test]
+fn test_fee_tiers_discount_large_fills() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let fee_recipient = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2005);
+
+    let order = Order {
+        salt: 77,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    // Tiers must be sorted by min amount
+    lop.set_protocol_fee(&100, &fee_recipient);
+    assert_eq!(
+        lop.try_set_fee_tiers(&vec![&env, (500i128, 20u32), (0i128, 100u32)]),
+        Err(Ok(Error::InvalidFee))
+    );
+    lop.set_fee_tiers(&vec![&env, (0i128, 100u32), (500i128, 20u32)]);
+
+    // A small fill pays 1%
+    lop.fill_order(&order, &taker, &100, &i128::MAX);
+    assert_eq!(token_b.balance(&fee_recipient), 2);
+
+    // A large fill pays 0.2%: 1800 * 20 / 10_000 = 3
+    lop.fill_order(&order, &taker, &900, &i128::MAX);
+    assert_eq!(token_b.balance(&fee_recipient), 5);
+    assert_eq!(token_b.balance(&maker), 2000);
+    assert_eq!(token_b.balance(&taker), 0);
+}
+
+#[test]
+fn test_fill_rejects_identical_assets() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_a_admin.mint(&taker, &1000);
+
+    let order = Order {
+        salt: 78,
+        taking_amount: 1000,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_a.address)
+    };
+
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &order.making_amount, &i128::MAX),
+        Err(Ok(Error::IdenticalAssets))
+    );
+    assert_eq!(lop.get_order_state(&order), OrderState::Active);
+}
+
+#[test]
+fn test_partial_fills_release_locked_funds() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 79,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &taker, &token_a.address, &token_b.address)
+    };
+
+    assert_eq!(lop.get_locked_remaining(&order), 0);
+    lop.lock_funds(&order);
+    assert_eq!(lop.get_locked_remaining(&order), 1000);
+
+    // Each partial fill releases only what it delivers
+    lop.fill_order(&order, &taker, &300, &i128::MAX);
+    assert_eq!(lop.get_locked_remaining(&order), 700);
+    assert_eq!(token_a.balance(&lop.address), 700);
+
+    lop.fill_order(&order, &taker, &200, &i128::MAX);
+    assert_eq!(lop.get_locked_remaining(&order), 500);
+    assert_eq!(token_a.balance(&lop.address), 500);
+    assert!(lop.is_locked(&order));
+
+    // Cancelling refunds the rest of the lock
+    lop.cancel_order(&order);
+    assert_eq!(token_a.balance(&maker), 500);
+    assert_eq!(token_a.balance(&taker), 500);
+    assert_eq!(token_a.balance(&lop.address), 0);
+    assert_eq!(lop.get_locked_remaining(&order), 0);
+    assert!(!lop.is_locked(&order));
+}
+
+#[test]
+fn test_fill_allowances_limit_takers() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker_a = Address::generate(&env);
+    let taker_b = Address::generate(&env);
+    let outsider = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker_a, &2000);
+    token_b_admin.mint(&taker_b, &2000);
+    token_b_admin.mint(&outsider, &2000);
+
+    let order = Order {
+        salt: 80,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &lop.address, &token_a.address, &token_b.address)
+    };
+
+    lop.set_fill_allowance(&order, &taker_a, &600);
+    lop.set_fill_allowance(&order, &taker_b, &300);
+
+    // Each taker fills up to its own allocation
+    assert_eq!(
+        lop.try_fill_order(&order, &taker_a, &700, &i128::MAX),
+        Err(Ok(Error::AllowanceExceeded))
+    );
+    lop.fill_order(&order, &taker_a, &600, &i128::MAX);
+    assert_eq!(lop.get_fill_allowance(&order, &taker_a), 0);
+
+    assert_eq!(
+        lop.try_fill_order(&order, &outsider, &100, &i128::MAX),
+        Err(Ok(Error::AllowanceExceeded))
+    );
+    lop.fill_order(&order, &taker_b, &200, &i128::MAX);
+    assert_eq!(lop.get_fill_allowance(&order, &taker_b), 100);
+    assert_eq!(
+        lop.try_fill_order(&order, &taker_b, &101, &i128::MAX),
+        Err(Ok(Error::AllowanceExceeded))
+    );
+
+    assert_eq!(token_a.balance(&taker_a), 600);
+    assert_eq!(token_a.balance(&taker_b), 200);
+    assert_eq!(lop.get_remaining_making_amount(&order), 200);
+}
+
+#[test]
+fn test_fill_allowance_checks_dust_widened_fill() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let lop = create_lop_contract(&env);
+    let dutch_auction = create_dutch_auction_contract(&env);
+    let admin = Address::generate(&env);
+    lop.initialize(&admin, &dutch_auction.address, &0, &admin);
+
+    let maker = Address::generate(&env);
+    let taker = Address::generate(&env);
+    let token_admin = Address::generate(&env);
+    let (token_a, token_a_admin) = create_token_contract(&env, &token_admin);
+    let (token_b, token_b_admin) = create_token_contract(&env, &token_admin);
+
+    token_a_admin.mint(&maker, &1000);
+    token_b_admin.mint(&taker, &2000);
+
+    let order = Order {
+        salt: 81,
+        maker_traits: ALLOW_PARTIAL_FILLS,
+        ..default_order(&env, &maker, &lop.address, &token_a.address, &token_b.address)
+    };
+
+    // Remainders worth less than 100 of the taker asset are dust
+    lop.set_dust_threshold(&token_b.address, &100);
+    lop.set_fill_allowance(&order, &taker, &980);
+
+    // Filling 980 would leave 20 (worth 40), so the fill widens to 1000, past the allocation
+    assert_eq!(
+        lop.try_fill_order(&order, &taker, &980, &i128::MAX),
+        Err(Ok(Error::AllowanceExceeded))
+    );
+    assert_eq!(lop.get_fill_allowance(&order, &taker), 980);
+    assert_eq!(token_a.balance(&taker), 0);
+
+    // With the whole order allocated the widened fill goes through and uses it all
+    lop.set_fill_allowance(&order, &taker, &1000);
+    lop.fill_order(&order, &taker, &980, &i128::MAX);
+    assert_eq!(token_a.balance(&taker), 1000);
+    assert_eq!(lop.get_fill_allowance(&order, &taker), 0);
+    assert_eq!(lop.get_order_state(&order), OrderState::Filled);
+}